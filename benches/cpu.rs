@@ -0,0 +1,39 @@
+//! Instructions per second of the CPU emulator on the Pong ROM.
+//!
+//! Run with `cargo bench --bench cpu`.
+
+use nand2tetris::emulator::cpu::{Instruction, CPU};
+use std::path::Path;
+use std::time::Instant;
+
+const CYCLES: u64 = 50_000_000;
+
+fn report(name: &str, cycles: u64, start: Instant) {
+    let seconds = start.elapsed().as_secs_f64();
+    println!(
+        "{:<24} {:>12} cycles in {:>7.3}s = {:>7.1} M instructions/s",
+        name,
+        cycles,
+        seconds,
+        cycles as f64 / seconds / 1e6
+    );
+}
+
+fn main() {
+    let path = Path::new("tests/assembler/Pong.cmp");
+
+    let mut cpu = CPU::from_hack_file(path);
+    let start = Instant::now();
+    let cycles = cpu.run(CYCLES);
+    report("pre-decoded run", cycles, start);
+
+    // The same program, decoding the ROM word again on every cycle.
+    let mut cpu = CPU::from_hack_file(path);
+    let words = cpu.words().to_vec();
+    let start = Instant::now();
+    for _ in 0..CYCLES {
+        let word = words.get(cpu.pc as usize).copied().unwrap_or(0);
+        cpu.execute(Instruction::decode(word));
+    }
+    report("decode every cycle", CYCLES, start);
+}
@@ -1,6 +1,6 @@
 mod code;
 mod parser;
-mod table;
+pub mod table;
 
 use code::Code;
 use parser::{CommandType, Parser};
@@ -31,6 +31,16 @@ impl Assembler {
         self.second_pass();
     }
 
+    /// The labels and variables of the program, complete after `run`.
+    pub fn symbol_table(&self) -> &SymbolTalbe {
+        &self.symbol_table
+    }
+
+    /// The path of the `.hack` file written by `run`.
+    pub fn hack_path(&self) -> PathBuf {
+        get_hack_path(&self.path)
+    }
+
     fn first_pass(&mut self) {
         let mut line_num = 0;
         while self.parser.has_more_commands() {
@@ -40,7 +50,7 @@ impl Assembler {
                 CommandType::CCommand => line_num += 1,
                 CommandType::LCommand => {
                     let symbol = self.parser.symbol();
-                    self.symbol_table.add_entry(&symbol, line_num);
+                    self.symbol_table.add_label(&symbol, line_num);
                 }
                 CommandType::WhiteSpace => {}
             }
@@ -88,7 +88,7 @@ impl Parser {
         };
         let comp = match comp_and_jump.find(';') {
             Some(size) => &comp_and_jump[..size],
-            None => &comp_and_jump[..],
+            None => comp_and_jump,
         };
         comp.to_string()
     }
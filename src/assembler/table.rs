@@ -1,7 +1,9 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
+#[derive(Clone)]
 pub struct SymbolTalbe {
     table: HashMap<String, i16>,
+    labels: HashSet<String>,
     next_alloc: i16,
 }
 
@@ -10,6 +12,7 @@ impl SymbolTalbe {
         let table = Self::predefined_table();
         SymbolTalbe {
             table,
+            labels: HashSet::new(),
             next_alloc: 16,
         }
     }
@@ -18,6 +21,27 @@ impl SymbolTalbe {
         self.table.insert(symbol.to_string(), address);
     }
 
+    /// Adds a `(LABEL)`, whose address is in ROM rather than RAM.
+    pub fn add_label(&mut self, symbol: &str, address: i16) {
+        self.add_entry(symbol, address);
+        self.labels.insert(symbol.to_string());
+    }
+
+    pub fn is_label(&self, symbol: &str) -> bool {
+        self.labels.contains(symbol)
+    }
+
+    pub fn symbols(&self) -> impl Iterator<Item = (&str, i16)> {
+        self.table.iter().map(|(s, a)| (s.as_str(), *a))
+    }
+
+    /// The label at or closest before ROM `address`, with its address.
+    pub fn label_before(&self, address: i16) -> Option<(&str, i16)> {
+        self.symbols()
+            .filter(|(s, a)| self.is_label(s) && *a <= address)
+            .max_by(|x, y| x.1.cmp(&y.1).then(y.0.cmp(x.0)))
+    }
+
     pub fn add_alloc(&mut self, symbol: &str) -> i16 {
         let alloc = self.next_alloc;
         self.table.insert(symbol.to_string(), self.next_alloc);
@@ -1,14 +1,80 @@
+use nand2tetris::compiler::analyzer::Diagnostic;
+use nand2tetris::compiler::ast::Grouping;
+use nand2tetris::compiler::linter::Linter;
+use nand2tetris::compiler::tkzr::Dialect;
 use nand2tetris::compiler::Compiler;
-use std::path::Path;
 use std::env;
+use std::path::Path;
+use std::process;
+
+const USAGE: &str = "usage: compiler [--extended] [--precedence] [--vm | --check [--strict] | \
+                     --tokens -o dir | --lint [--enable rule | --disable rule]...] \
+                     <file.jack | dir>";
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        panic!("too many or too few arguments, only one argument is required");
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    // The extended dialect and operator precedence, for every mode but the
+    // XML one.
+    let mut dialect = Dialect::Standard;
+    let mut grouping = Grouping::LeftToRight;
+    loop {
+        match args.first().map(String::as_str) {
+            Some("--extended") => dialect = Dialect::Extended,
+            Some("--precedence") => grouping = Grouping::Precedence,
+            _ => break,
+        }
+        args.remove(0);
+    }
+    let compiler = |path: &String| {
+        Compiler::new(Path::new(path))
+            .with_dialect(dialect)
+            .with_grouping(grouping)
+    };
+    match args.as_slice() {
+        [path] => compiler(path).compile(),
+        [flag, path] if flag == "--vm" => report(compiler(path).generate()),
+        [flag, path] if flag == "--check" => report(compiler(path).analyze(false)),
+        [flag, strict, path] if flag == "--check" && strict == "--strict" => {
+            report(compiler(path).analyze(true))
+        }
+        [flag, o, dir, path] if flag == "--tokens" && o == "-o" => {
+            compiler(path).write_tokens(Path::new(dir))
+        }
+        [flag, rules @ .., path] if flag == "--lint" => lint(compiler(path), grouping, rules),
+        _ => panic!("{}", USAGE),
+    }
+}
+
+// Prints the errors, exits with 1 if there are any.
+fn report(diagnostics: Vec<Diagnostic>) {
+    for diagnostic in &diagnostics {
+        eprintln!("{}", diagnostic);
+    }
+    if !diagnostics.is_empty() {
+        process::exit(1);
     }
+}
 
-    let path = Path::new(&args[1]);
-    let compiler = Compiler::new(&path);
-    compiler.compile();
+// Applies `--enable rule` and `--disable rule` in order, all rules being
+// enabled at first, and prints the warnings.
+fn lint(compiler: Compiler, grouping: Grouping, rules: &[String]) {
+    let mut linter = Linter::new();
+    linter.set_grouping(grouping);
+    for pair in rules.chunks(2) {
+        let result = match pair {
+            [flag, rule] if flag == "--enable" => linter.enable(rule),
+            [flag, rule] if flag == "--disable" => linter.disable(rule),
+            _ => panic!("{}", USAGE),
+        };
+        if let Err(e) = result {
+            panic!("{}", e);
+        }
+    }
+    let warnings = compiler.lint(&linter);
+    for warning in &warnings {
+        eprintln!("{}", warning);
+    }
+    if !warnings.is_empty() {
+        process::exit(1);
+    }
 }
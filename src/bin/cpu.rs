@@ -0,0 +1,115 @@
+use nand2tetris::assembler::table::SymbolTalbe;
+use nand2tetris::assembler::Assembler;
+use nand2tetris::emulator::cpu::CPU;
+use nand2tetris::emulator::gdb::GdbServer;
+use nand2tetris::emulator::keyboard::Keyboard;
+use nand2tetris::emulator::profiler::{Profiler, SourceMap};
+use nand2tetris::emulator::screen;
+use nand2tetris::emulator::snapshot;
+use nand2tetris::emulator::trace;
+use std::env;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::net::TcpListener;
+use std::path::Path;
+
+const USAGE: &str = "\
+usage: cpu [-n cycles] [--pbm out.pbm] [--png out.png] [--screen] [--scale n]
+           [--keys script | --trace out.trace | --profile [--folded out.folded]]
+           [--gdb port] [--save out.snapshot] <file.hack | file.asm | file.snapshot>";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let value = |flag: &str| {
+        args.iter()
+            .position(|arg| arg == flag)
+            .map(|i| args.get(i + 1).expect(USAGE).as_str())
+    };
+    let cycles = value("-n").map_or(10_000_000, |n| n.parse().expect(USAGE));
+    let scale: usize = value("--scale").map_or(1, |n| n.parse().expect(USAGE));
+    if scale == 0 {
+        panic!("{}", USAGE);
+    }
+    let (pbm, png) = (value("--pbm"), value("--png"));
+    let show = args.iter().any(|arg| arg == "--screen");
+    let profile = args.iter().any(|arg| arg == "--profile");
+    let mut paths = Vec::new();
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-n" | "--pbm" | "--png" | "--scale" | "--keys" | "--save" | "--trace" | "--folded"
+            | "--gdb" => i += 1,
+            "--screen" | "--profile" => {}
+            arg => paths.push(arg),
+        }
+        i += 1;
+    }
+    if paths.len() != 1 {
+        panic!("{}", USAGE);
+    }
+
+    let path = Path::new(paths[0]);
+    // Labels, and VM functions if the assembly was annotated, for --profile.
+    let mut symbols = SymbolTalbe::new();
+    let mut source_map = None;
+    let mut cpu = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("asm") => {
+            let mut assembler = Assembler::new(path);
+            assembler.run();
+            symbols = assembler.symbol_table().clone();
+            source_map = Some(SourceMap::from_asm(path));
+            CPU::from_hack_file(&assembler.hack_path())
+        }
+        Some("snapshot") => {
+            let text = fs::read_to_string(path).unwrap();
+            snapshot::load_cpu(&text).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+        }
+        _ => CPU::from_hack_file(path),
+    };
+    if let Some(port) = value("--gdb") {
+        let listener = TcpListener::bind(("127.0.0.1", port.parse().expect(USAGE))).unwrap();
+        println!(
+            "waiting for a debugger on {}",
+            listener.local_addr().unwrap()
+        );
+        GdbServer::new(cpu).serve(&listener).unwrap();
+        return;
+    }
+    let mut profiler = None;
+    // --keys, --trace and --profile each drive the run, so only one applies.
+    let run = match (value("--keys"), value("--trace")) {
+        (Some(_), Some(_)) => panic!("{}", USAGE),
+        (Some(_), None) | (None, Some(_)) if profile => panic!("{}", USAGE),
+        (Some(script), None) => Keyboard::from_file(Path::new(script)).run(&mut cpu, cycles),
+        (None, Some(out)) => {
+            let mut out = BufWriter::new(File::create(out).unwrap());
+            trace::trace(&mut cpu, cycles, &mut out)
+        }
+        (None, None) if profile => {
+            let mut p = Profiler::new(&symbols, cpu.words().len(), source_map);
+            let run = p.run(&mut cpu, cycles);
+            profiler = Some(p);
+            run
+        }
+        (None, None) => cpu.run(cycles),
+    };
+    println!("ran {} cycles, PC = {}", run, cpu.pc);
+    if let Some(profiler) = profiler {
+        print!("{}", profiler.flat());
+        if let Some(out) = value("--folded") {
+            fs::write(out, profiler.folded()).unwrap();
+        }
+    }
+    if let Some(out) = pbm {
+        fs::write(out, screen::to_pbm(&cpu.ram)).unwrap();
+    }
+    if let Some(out) = png {
+        fs::write(out, screen::to_png(&cpu.ram)).unwrap();
+    }
+    if let Some(out) = value("--save") {
+        fs::write(out, snapshot::save_cpu(&cpu)).unwrap();
+    }
+    if show {
+        print!("{}", screen::to_terminal(&cpu.ram, scale));
+    }
+}
@@ -0,0 +1,16 @@
+use nand2tetris::emulator::debugger::Debugger;
+use std::env;
+use std::io;
+use std::path::Path;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 2 {
+        panic!("usage: debugger <file.asm>");
+    }
+
+    let path = Path::new(&args[1]);
+    let mut debugger = Debugger::load(path);
+    let stdin = io::stdin();
+    debugger.repl(stdin.lock(), io::stdout());
+}
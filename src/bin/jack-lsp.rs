@@ -0,0 +1,18 @@
+use nand2tetris::compiler::lsp;
+use std::io;
+use std::process;
+
+// Speaks the Language Server Protocol over stdin and stdout.
+fn main() {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    match lsp::run(stdin.lock(), stdout.lock()) {
+        Ok(true) => {}
+        // Exiting without a shutdown request is an error.
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("jack-lsp: {}", e);
+            process::exit(1);
+        }
+    }
+}
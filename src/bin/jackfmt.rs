@@ -0,0 +1,48 @@
+use nand2tetris::compiler::formatter;
+use nand2tetris::compiler::Compiler;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+const USAGE: &str = "usage: jackfmt [--check] <file.jack | dir>...";
+
+// Formats Jack files in place or, with `--check`, lists those that are not
+// formatted and fails if there are any.
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let check = args.iter().any(|arg| arg == "--check");
+    let paths: Vec<&String> = args.iter().filter(|arg| !arg.starts_with("--")).collect();
+    if paths.is_empty() {
+        panic!("{}", USAGE);
+    }
+
+    let mut failed = false;
+    for path in paths {
+        for file in Compiler::new(Path::new(path)).files() {
+            let source = fs::read_to_string(file).unwrap();
+            let formatted = match formatter::format(&source) {
+                Ok(formatted) => formatted,
+                Err(errors) => {
+                    for e in errors {
+                        eprintln!("{}: {}", file.display(), e);
+                    }
+                    failed = true;
+                    continue;
+                }
+            };
+            if formatted == source {
+                continue;
+            }
+            if check {
+                println!("{}", file.display());
+                failed = true;
+            } else {
+                fs::write(file, formatted).unwrap();
+            }
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,32 @@
+use nand2tetris::assembler::Assembler;
+use nand2tetris::emulator::cpu::CPU;
+use nand2tetris::emulator::recompiler;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+const USAGE: &str = "usage: recompile [-o out.c] <file.hack | file.asm>";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (out, path) = match args.as_slice() {
+        [path] => (None, path),
+        [flag, out, path] if flag == "-o" => (Some(out), path),
+        _ => panic!("{}", USAGE),
+    };
+
+    let path = Path::new(path);
+    let cpu = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("asm") => {
+            let mut assembler = Assembler::new(path);
+            assembler.run();
+            CPU::from_hack_file(&assembler.hack_path())
+        }
+        _ => CPU::from_hack_file(path),
+    };
+    let out = match out {
+        Some(out) => Path::new(out).to_path_buf(),
+        None => path.with_extension("c"),
+    };
+    fs::write(&out, recompiler::recompile(cpu.words())).unwrap();
+}
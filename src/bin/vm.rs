@@ -1,14 +1,87 @@
-use nand2tetris::vm::VM;
+use nand2tetris::emulator::differential::Differential;
+use nand2tetris::vm::c_code::CCode;
+use nand2tetris::vm::code::Code;
+use nand2tetris::vm::optimizer::Optimizer;
+use nand2tetris::vm::wat_code::WatCode;
+use nand2tetris::vm::{self, VM};
 use std::env;
 use std::path::Path;
+use std::process;
+
+/// VM commands `--diff` runs before comparing.
+const DIFF_CYCLES: u64 = 10_000_000;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        panic!("too many or too few arguments, only one argument is required");
+    let args: Vec<String> = env::args().skip(1).collect();
+    let optimize = args.iter().any(|arg| arg == "-O");
+    let verify = args.iter().any(|arg| arg == "--verify");
+    let annotate = args.iter().any(|arg| arg == "--annotate");
+    let diff = args.iter().any(|arg| arg == "--diff");
+    let target = match args.iter().position(|arg| arg == "--target") {
+        Some(i) => args.get(i + 1).map(|s| s.as_str()),
+        None => Some("hack"),
+    };
+    let paths: Vec<&String> = args
+        .iter()
+        .filter(|arg| !arg.starts_with('-') && Some(arg.as_str()) != target)
+        .collect();
+    if paths.len() != 1 {
+        panic!(
+            "usage: vm [-O] [--verify] [--diff] [--annotate] [--target hack|c|wat] <file.vm | directory>"
+        );
     }
 
-    let path = Path::new(&args[1]);
-    let mut vm = VM::new(&path);
+    let path = Path::new(paths[0]);
+    if diff {
+        let mut differential = Differential::new(path, &path.with_extension("asm"));
+        match differential.run(DIFF_CYCLES) {
+            Some(divergence) => {
+                eprintln!("{}", divergence);
+                process::exit(1);
+            }
+            // A program stuck in a callee was never compared.
+            None if differential.checkpoints == 0 => {
+                eprintln!(
+                    "no checkpoint after {} commands, nothing was compared",
+                    differential.vm.cycles
+                );
+                process::exit(1);
+            }
+            None => println!(
+                "no divergence after {} commands, {} checkpoints",
+                differential.vm.cycles, differential.checkpoints
+            ),
+        }
+        return;
+    }
+    if verify {
+        let report = vm::verify(path);
+        for function in report.functions.iter() {
+            println!(
+                "{}: max stack depth {}",
+                function.name, function.max_stack_depth
+            );
+        }
+        for diagnostic in report.diagnostics.iter() {
+            eprintln!("{}", diagnostic);
+        }
+        if !report.is_ok() {
+            process::exit(1);
+        }
+        return;
+    }
+    let mut vm = match target {
+        Some("hack") => {
+            let mut code = Code::new(&path.with_extension("asm"));
+            code.set_annotated(annotate);
+            VM::with_writer(path, Box::new(code))
+        }
+        Some("c") => VM::with_writer(path, Box::new(CCode::new(&path.with_extension("c")))),
+        Some("wat") => VM::with_writer(path, Box::new(WatCode::new(&path.with_extension("wat")))),
+        _ => panic!("unknown target, must be one of hack, c or wat"),
+    };
+    if optimize {
+        vm.set_optimizer(Optimizer::new());
+    }
     vm.translate();
 }
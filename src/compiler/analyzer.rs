@@ -0,0 +1,678 @@
+use super::ast::*;
+use super::os;
+use super::symbol_table::{ClassTable, Kind, SubroutineInfo, SymbolTable};
+use super::tkzr::Span;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub file: String,
+    pub span: Span,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.span, self.message)
+    }
+}
+
+/// The classes of a program and of the OS. A class of the program hides the
+/// OS class of the same name, as when writing the OS in Jack.
+pub fn class_table(program: &[(String, Class)]) -> ClassTable {
+    let mut classes = ClassTable::new();
+    for (file, class) in program {
+        classes.add(file, class);
+    }
+    for class in os::classes() {
+        classes.add("", &class);
+    }
+    classes
+}
+
+/// The type of an expression as far as it can be told.
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Type(Type),
+    Null,
+    // Array elements, and whatever an error was already reported for.
+    Unknown,
+}
+
+impl Value {
+    fn is_numeric(&self) -> bool {
+        matches!(
+            self,
+            Value::Type(Type::Int) | Value::Type(Type::Char) | Value::Unknown
+        )
+    }
+
+    fn is_boolean(&self) -> bool {
+        matches!(self, Value::Type(Type::Boolean) | Value::Unknown)
+    }
+
+    /// Whether a value of this type may be stored where `expected` is
+    /// declared. `int` and `char` mix freely, and an `Array` may stand for
+    /// any object and the other way around, as Jack programs expect.
+    fn fits(&self, expected: &Type) -> bool {
+        match (self, expected) {
+            (Value::Unknown, _) => true,
+            (Value::Null, Type::Class(_)) => true,
+            (Value::Null, _) => false,
+            (Value::Type(Type::Class(found)), Type::Class(expected))
+                if found == "Array" || expected == "Array" =>
+            {
+                true
+            }
+            (Value::Type(found), expected) => {
+                found == expected
+                    || (self.is_numeric() && Value::Type(expected.clone()).is_numeric())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Type(var_type) => write!(f, "{}", var_type),
+            Value::Null => write!(f, "null"),
+            Value::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// Checks that every class of a program only refers to variables, classes
+/// and subroutines that exist, calls them with the right number of
+/// arguments and the right way, and returns from every non-void
+/// subroutine, and, in the extended dialect, that constants have constant
+/// values and are not assigned, and `break` and `continue` are in loops.
+/// `program` holds each class with the file it came from.
+///
+/// With `strict`, it also infers the type of every expression and reports
+/// values used where their type does not fit: in assignments, arguments,
+/// returns, conditions and operators.
+pub fn analyze(program: &[(String, Class)], strict: bool) -> Vec<Diagnostic> {
+    analyze_grouped(program, strict, Grouping::LeftToRight)
+}
+
+/// Like `analyze`, for a compiler that groups operators by `grouping`,
+/// which decides the types of expressions and the values of constants.
+pub fn analyze_grouped(
+    program: &[(String, Class)],
+    strict: bool,
+    grouping: Grouping,
+) -> Vec<Diagnostic> {
+    let mut analyzer = Analyzer {
+        strict,
+        grouping,
+        classes: class_table(program),
+        diagnostics: Vec::new(),
+        file: String::new(),
+        class: String::new(),
+        kind: SubroutineKind::Function,
+        table: SymbolTable::new(),
+        constants: HashMap::new(),
+        loops: 0,
+    };
+    for (file, class) in program {
+        analyzer.file = file.clone();
+        analyzer.class(class);
+    }
+    let mut diagnostics = analyzer.diagnostics;
+    let file_index = |file: &str| program.iter().position(|(f, _)| f == file);
+    diagnostics.sort_by_key(|d| (file_index(&d.file), d.span.start));
+    diagnostics
+}
+
+struct Analyzer {
+    strict: bool,
+    grouping: Grouping,
+    classes: ClassTable,
+    diagnostics: Vec<Diagnostic>,
+    file: String,
+    class: String,
+    // The kind of the subroutine being checked.
+    kind: SubroutineKind,
+    table: SymbolTable,
+    // The class's constants, with their types and where they are declared.
+    constants: HashMap<String, (Type, Span)>,
+    // How many loops the statement being checked is in.
+    loops: usize,
+}
+
+impl Analyzer {
+    fn error(&mut self, span: Span, message: String) {
+        self.diagnostics.push(Diagnostic {
+            file: self.file.clone(),
+            span,
+            message,
+        });
+    }
+
+    // A type error, only reported in strict mode.
+    fn mismatch(&mut self, span: Span, message: String) {
+        if self.strict {
+            self.error(span, message);
+        }
+    }
+
+    fn class(&mut self, class: &Class) {
+        self.class = class.name.name.clone();
+        if let Some(info) = self.classes.get(&self.class) {
+            if info.file != self.file {
+                let message = format!("class {} is already declared in {}", self.class, info.file);
+                self.error(class.name.span, message);
+            }
+        }
+        self.table = SymbolTable::new();
+        for dec in &class.vars {
+            self.check_type(&dec.var_type, dec.type_span);
+            for name in &dec.names {
+                self.define(name, &dec.var_type, dec.kind.into());
+            }
+        }
+        self.constants.clear();
+        let mut values = HashMap::new();
+        for constant in &class.constants {
+            self.constant(constant, &mut values);
+        }
+        let mut seen: Vec<&str> = Vec::new();
+        for subroutine in &class.subroutines {
+            let name = &subroutine.name;
+            if seen.contains(&name.name.as_str()) {
+                let message = format!(
+                    "subroutine {}.{} is already declared",
+                    self.class, name.name
+                );
+                self.error(name.span, message);
+            }
+            seen.push(&name.name);
+            self.subroutine(subroutine);
+        }
+    }
+
+    // Checks a constant, adds its value to `values`.
+    fn constant(&mut self, constant: &Constant, values: &mut HashMap<String, i16>) {
+        let name = &constant.name;
+        let earlier = match self.constants.get(&name.name) {
+            Some((_, span)) => Some(*span),
+            None => self.table.get(&name.name).map(|symbol| symbol.span),
+        };
+        if let Some(span) = earlier {
+            let message = format!("{} is already declared at {}", name.name, span.start);
+            self.error(name.span, message);
+            return;
+        }
+        if let Type::Class(_) = constant.var_type {
+            let message = format!("constant {} must be int, char or boolean", name.name);
+            self.error(constant.type_span, message);
+        }
+        match constant.value.value(values, self.grouping) {
+            Some(value) => {
+                values.insert(name.name.clone(), value);
+                let found = self.expression(&constant.value);
+                if !found.fits(&constant.var_type) {
+                    let message = format!(
+                        "cannot assign {} to {}, which is {}",
+                        found, name.name, constant.var_type
+                    );
+                    self.mismatch(constant.value.span(), message);
+                }
+            }
+            None => {
+                let message = format!("the value of constant {} is not constant", name.name);
+                self.error(constant.value.span(), message);
+                // So that the constants that use it are not reported too.
+                values.insert(name.name.clone(), 0);
+            }
+        }
+        let declared = (constant.var_type.clone(), name.span);
+        self.constants.insert(name.name.clone(), declared);
+    }
+
+    fn check_type(&mut self, var_type: &Type, span: Span) {
+        if let Type::Class(name) = var_type {
+            if self.classes.get(name).is_none() {
+                self.error(span, format!("unknown class {}", name));
+            }
+        }
+    }
+
+    fn define(&mut self, name: &Name, var_type: &Type, kind: Kind) {
+        if let Some(symbol) = self.table.define(&name.name, var_type, kind, name.span) {
+            let message = format!("{} is already declared at {}", name.name, symbol.span.start);
+            self.error(name.span, message);
+        }
+    }
+
+    fn subroutine(&mut self, subroutine: &Subroutine) {
+        self.kind = subroutine.kind;
+        self.table.start_subroutine(subroutine.kind);
+        if let Some(return_type) = &subroutine.return_type {
+            self.check_type(return_type, subroutine.type_span);
+        }
+        for parameter in &subroutine.parameters {
+            self.check_type(&parameter.var_type, parameter.type_span);
+            self.define(&parameter.name, &parameter.var_type, Kind::Argument);
+        }
+        for dec in &subroutine.locals {
+            self.check_type(&dec.var_type, dec.type_span);
+            for name in &dec.names {
+                self.define(name, &dec.var_type, Kind::Var);
+            }
+        }
+        let name = format!("{}.{}", self.class, subroutine.name.name);
+        self.statements(
+            &subroutine.statements,
+            &name,
+            subroutine.return_type.as_ref(),
+        );
+        if !returns(&subroutine.statements) {
+            self.error(
+                subroutine.end,
+                format!("missing return at the end of {}", name),
+            );
+        }
+    }
+
+    // `name` and `return_type` are the subroutine's.
+    fn statements(&mut self, statements: &[Statement], name: &str, return_type: Option<&Type>) {
+        for statement in statements {
+            match statement {
+                Statement::Let {
+                    target,
+                    index,
+                    op,
+                    value,
+                } => {
+                    let target_type = if self.table.get(&target.name).is_some() {
+                        self.variable(target)
+                    } else if self.constants.contains_key(&target.name) {
+                        let message = format!("cannot assign to constant {}", target.name);
+                        self.error(target.span, message);
+                        Value::Unknown
+                    } else {
+                        let message = format!("assignment to undeclared variable {}", target.name);
+                        self.error(target.span, message);
+                        Value::Unknown
+                    };
+                    let target_type = match index {
+                        Some(index) => {
+                            self.index(target, &target_type, index);
+                            Value::Unknown
+                        }
+                        None => target_type,
+                    };
+                    let mut found = self.expression(value);
+                    if let Some(op) = op {
+                        found = self.binary(*op, value.span(), target_type.clone(), found);
+                    }
+                    if let Value::Type(expected) = &target_type {
+                        if !found.fits(expected) {
+                            let message = format!(
+                                "cannot assign {} to {}, which is {}",
+                                found, target.name, expected
+                            );
+                            self.mismatch(value.span(), message);
+                        }
+                    }
+                }
+                Statement::If {
+                    condition,
+                    statements,
+                    else_statements,
+                    ..
+                } => {
+                    self.condition("if", condition);
+                    self.statements(statements, name, return_type);
+                    if let Some(statements) = else_statements {
+                        self.statements(statements, name, return_type);
+                    }
+                }
+                Statement::While {
+                    condition,
+                    statements,
+                    ..
+                } => {
+                    self.condition("while", condition);
+                    self.loops += 1;
+                    self.statements(statements, name, return_type);
+                    self.loops -= 1;
+                }
+                Statement::For {
+                    init,
+                    condition,
+                    update,
+                    statements,
+                    ..
+                } => {
+                    if let Some(init) = init {
+                        self.statements(std::slice::from_ref(init), name, return_type);
+                    }
+                    if let Some(condition) = condition {
+                        self.condition("for", condition);
+                    }
+                    self.loops += 1;
+                    self.statements(statements, name, return_type);
+                    self.loops -= 1;
+                    if let Some(update) = update {
+                        self.statements(std::slice::from_ref(update), name, return_type);
+                    }
+                }
+                Statement::Break { span } if self.loops == 0 => {
+                    self.error(*span, "break is not in a loop".to_string());
+                }
+                Statement::Continue { span } if self.loops == 0 => {
+                    self.error(*span, "continue is not in a loop".to_string());
+                }
+                Statement::Break { .. } | Statement::Continue { .. } => {}
+                Statement::Do { call, .. } => {
+                    self.call(call);
+                }
+                Statement::Return { span, value } => match value {
+                    Some(value) => {
+                        let found = self.expression(value);
+                        match return_type {
+                            None => {
+                                let message = format!("{} is void and cannot return a value", name);
+                                self.error(*span, message);
+                            }
+                            Some(expected) if !found.fits(expected) => {
+                                let message =
+                                    format!("{} must return {}, found {}", name, expected, found);
+                                self.mismatch(value.span(), message);
+                            }
+                            Some(_) => {}
+                        }
+                    }
+                    None if return_type.is_some() => {
+                        self.error(*span, format!("{} must return a value", name));
+                    }
+                    None => {}
+                },
+            }
+        }
+    }
+
+    fn condition(&mut self, statement: &str, condition: &Expression) {
+        let found = self.expression(condition);
+        if !found.is_boolean() {
+            let message = format!("condition of {} is {}, not boolean", statement, found);
+            self.mismatch(condition.span(), message);
+        }
+    }
+
+    // Checks `name[index]`, where `name` is of `var_type`.
+    fn index(&mut self, name: &Name, var_type: &Value, index: &Expression) {
+        if !matches!(var_type, Value::Type(Type::Class(class)) if class == "Array")
+            && *var_type != Value::Unknown
+        {
+            let message = format!(
+                "{} is {}, only an Array can be indexed",
+                name.name, var_type
+            );
+            self.mismatch(name.span, message);
+        }
+        let found = self.expression(index);
+        if !found.is_numeric() {
+            let message = format!("array index is {}, not int", found);
+            self.mismatch(index.span(), message);
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) -> Value {
+        self.operation(&expression.group(self.grouping))
+    }
+
+    fn operation(&mut self, operation: &Operation) -> Value {
+        match operation {
+            Operation::Term(term) => self.term(term),
+            Operation::Binary(op, span, left, right) => {
+                let left = self.operation(left);
+                let right = self.operation(right);
+                self.binary(*op, *span, left, right)
+            }
+        }
+    }
+
+    // The type of `left op right`.
+    fn binary(&mut self, op: Op, span: Span, left: Value, right: Value) -> Value {
+        match op {
+            Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Lt | Op::Gt => {
+                if !left.is_numeric() || !right.is_numeric() {
+                    let message = format!(
+                        "operator {} needs int operands, found {} and {}",
+                        op, left, right
+                    );
+                    self.mismatch(span, message);
+                }
+                match op {
+                    Op::Lt | Op::Gt => Value::Type(Type::Boolean),
+                    _ => Value::Type(Type::Int),
+                }
+            }
+            Op::Eq => {
+                let comparable = match (&left, &right) {
+                    (Value::Type(left_type), _) => right.fits(left_type),
+                    (_, Value::Type(right_type)) => left.fits(right_type),
+                    _ => true,
+                };
+                if !comparable {
+                    let message = format!("cannot compare {} with {}", left, right);
+                    self.mismatch(span, message);
+                }
+                Value::Type(Type::Boolean)
+            }
+            Op::And | Op::Or => {
+                if left.is_boolean() && right.is_boolean() {
+                    Value::Type(Type::Boolean)
+                } else if left.is_numeric() && right.is_numeric() {
+                    Value::Type(Type::Int)
+                } else {
+                    let message = format!(
+                        "operator {} needs two booleans or two ints, found {} and {}",
+                        op, left, right
+                    );
+                    self.mismatch(span, message);
+                    Value::Unknown
+                }
+            }
+        }
+    }
+
+    fn term(&mut self, term: &Term) -> Value {
+        match term {
+            Term::Int(..) => Value::Type(Type::Int),
+            Term::Str(..) => Value::Type(Type::Class("String".to_string())),
+            Term::Keyword(KeywordConst::This, span) => {
+                if self.kind == SubroutineKind::Function {
+                    self.error(*span, "this cannot be used in a function".to_string());
+                    return Value::Unknown;
+                }
+                Value::Type(Type::Class(self.class.clone()))
+            }
+            Term::Keyword(KeywordConst::Null, _) => Value::Null,
+            Term::Keyword(..) => Value::Type(Type::Boolean),
+            Term::Var(name) => self.variable(name),
+            Term::Index(name, index, _) => {
+                let var_type = self.variable(name);
+                self.index(name, &var_type, index);
+                Value::Unknown
+            }
+            Term::Call(call) => match self.call(call) {
+                Some(SubroutineInfo {
+                    return_type: Some(return_type),
+                    ..
+                }) => Value::Type(return_type),
+                Some(_) => {
+                    let message = format!("{} is void and has no value", call_name(call));
+                    self.error(call.span, message);
+                    Value::Unknown
+                }
+                None => Value::Unknown,
+            },
+            Term::Paren(expression, _) => self.expression(expression),
+            Term::Unary(op, term, span) => {
+                let found = self.term(term);
+                match op {
+                    UnaryOp::Neg if !found.is_numeric() => {
+                        let message = format!("operator - needs an int operand, found {}", found);
+                        self.mismatch(*span, message);
+                        Value::Type(Type::Int)
+                    }
+                    UnaryOp::Neg => Value::Type(Type::Int),
+                    UnaryOp::Not if found.is_numeric() || found.is_boolean() => found,
+                    UnaryOp::Not => {
+                        let message =
+                            format!("operator ~ needs a boolean or int operand, found {}", found);
+                        self.mismatch(*span, message);
+                        Value::Unknown
+                    }
+                }
+            }
+        }
+    }
+
+    // Checks the use of a variable, returns its type.
+    fn variable(&mut self, name: &Name) -> Value {
+        let symbol = match self.table.get(&name.name) {
+            Some(symbol) => symbol.clone(),
+            None if self.constants.contains_key(&name.name) => {
+                return Value::Type(self.constants[&name.name].0.clone());
+            }
+            None => {
+                self.error(name.span, format!("undeclared variable {}", name.name));
+                return Value::Unknown;
+            }
+        };
+        if symbol.kind == Kind::Field && self.kind == SubroutineKind::Function {
+            let message = format!("field {} cannot be used in a function", name.name);
+            self.error(name.span, message);
+        }
+        Value::Type(symbol.var_type)
+    }
+
+    // Checks a call and its arguments, returns what is called if it exists.
+    fn call(&mut self, call: &Call) -> Option<SubroutineInfo> {
+        let arguments: Vec<Value> = call
+            .arguments
+            .iter()
+            .map(|argument| self.expression(argument))
+            .collect();
+        let info = match &call.receiver {
+            None => {
+                let info = self.lookup(&self.class.clone(), call)?;
+                if info.kind == SubroutineKind::Method && self.kind == SubroutineKind::Function {
+                    let message = format!(
+                        "{}.{} is a method and cannot be called without an object from a function",
+                        self.class, call.name.name
+                    );
+                    self.error(call.span, message);
+                }
+                info
+            }
+            Some(receiver) => match self.table.get(&receiver.name).cloned() {
+                Some(symbol) => {
+                    self.variable(receiver);
+                    match &symbol.var_type {
+                        Type::Class(class) if self.classes.get(class).is_some() => {
+                            self.lookup(class, call)?
+                        }
+                        // An unknown class was reported where it was used.
+                        Type::Class(_) => return None,
+                        var_type => {
+                            let message = format!(
+                                "{} is {} {} and has no subroutines",
+                                receiver.name,
+                                article(var_type),
+                                var_type
+                            );
+                            self.error(receiver.span, message);
+                            return None;
+                        }
+                    }
+                }
+                None if self.classes.get(&receiver.name).is_some() => {
+                    let info = self.lookup(&receiver.name, call)?;
+                    if info.kind == SubroutineKind::Method {
+                        let message =
+                            format!("{} is a method and needs an object", call_name(call));
+                        self.error(call.span, message);
+                    }
+                    info
+                }
+                None => {
+                    let message = format!("unknown class or variable {}", receiver.name);
+                    self.error(receiver.span, message);
+                    return None;
+                }
+            },
+        };
+        if info.parameters.len() != call.arguments.len() {
+            let message = format!(
+                "{} expects {} argument{}, found {}",
+                call_name(call),
+                info.parameters.len(),
+                if info.parameters.len() == 1 { "" } else { "s" },
+                call.arguments.len()
+            );
+            self.error(call.span, message);
+        }
+        let parameters = info
+            .parameters
+            .iter()
+            .zip(call.arguments.iter().zip(arguments));
+        for (i, ((expected, _), (argument, found))) in parameters.enumerate() {
+            if !found.fits(expected) {
+                let message = format!(
+                    "argument {} of {} must be {}, found {}",
+                    i + 1,
+                    call_name(call),
+                    expected,
+                    found
+                );
+                self.mismatch(argument.span(), message);
+            }
+        }
+        Some(info)
+    }
+
+    fn lookup(&mut self, class: &str, call: &Call) -> Option<SubroutineInfo> {
+        let info = self.classes.subroutine(class, &call.name.name).cloned();
+        if info.is_none() {
+            let message = format!("unknown subroutine {}.{}", class, call.name.name);
+            self.error(call.name.span, message);
+        }
+        info
+    }
+}
+
+// The called name as written, e.g. `game.run` or `draw`.
+fn call_name(call: &Call) -> String {
+    match &call.receiver {
+        Some(receiver) => format!("{}.{}", receiver.name, call.name.name),
+        None => call.name.name.clone(),
+    }
+}
+
+fn article(var_type: &Type) -> &'static str {
+    match var_type {
+        Type::Int => "an",
+        _ => "a",
+    }
+}
+
+/// Whether every path through `statements` ends in a `return`.
+pub fn returns(statements: &[Statement]) -> bool {
+    statements.iter().any(|statement| match statement {
+        Statement::Return { .. } => true,
+        Statement::If {
+            statements,
+            else_statements: Some(else_statements),
+            ..
+        } => returns(statements) && returns(else_statements),
+        _ => false,
+    })
+}
@@ -0,0 +1,424 @@
+use super::tkzr::Span;
+use std::collections::HashMap;
+use std::fmt;
+use std::iter::Peekable;
+use std::slice;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Int,
+    Char,
+    Boolean,
+    Class(String),
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Char => write!(f, "char"),
+            Type::Boolean => write!(f, "boolean"),
+            Type::Class(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// A name as written in the source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Name {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClassVarKind {
+    Static,
+    Field,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Class {
+    pub name: Name,
+    pub vars: Vec<ClassVarDec>,
+    /// Always empty in standard Jack.
+    pub constants: Vec<Constant>,
+    pub subroutines: Vec<Subroutine>,
+}
+
+// 'static' int x, y;
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassVarDec {
+    pub kind: ClassVarKind,
+    pub var_type: Type,
+    pub type_span: Span,
+    pub names: Vec<Name>,
+}
+
+// 'const' int SIZE = 16;
+#[derive(Debug, Clone, PartialEq)]
+pub struct Constant {
+    pub var_type: Type,
+    pub type_span: Span,
+    pub name: Name,
+    pub value: Expression,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SubroutineKind {
+    Constructor,
+    Function,
+    Method,
+}
+
+impl fmt::Display for SubroutineKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubroutineKind::Constructor => write!(f, "constructor"),
+            SubroutineKind::Function => write!(f, "function"),
+            SubroutineKind::Method => write!(f, "method"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parameter {
+    pub var_type: Type,
+    pub type_span: Span,
+    pub name: Name,
+}
+
+// 'var' int x, y;
+#[derive(Debug, Clone, PartialEq)]
+pub struct VarDec {
+    pub var_type: Type,
+    pub type_span: Span,
+    pub names: Vec<Name>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Subroutine {
+    pub kind: SubroutineKind,
+    /// `None` for `void`.
+    pub return_type: Option<Type>,
+    pub type_span: Span,
+    pub name: Name,
+    pub parameters: Vec<Parameter>,
+    pub locals: Vec<VarDec>,
+    pub statements: Vec<Statement>,
+    /// The closing brace of the body.
+    pub end: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Let {
+        target: Name,
+        index: Option<Expression>,
+        /// The operator of a compound assignment such as `+=`.
+        op: Option<Op>,
+        value: Expression,
+    },
+    If {
+        span: Span,
+        condition: Expression,
+        statements: Vec<Statement>,
+        else_statements: Option<Vec<Statement>>,
+    },
+    While {
+        span: Span,
+        condition: Expression,
+        statements: Vec<Statement>,
+    },
+    Do {
+        span: Span,
+        call: Call,
+    },
+    Return {
+        span: Span,
+        value: Option<Expression>,
+    },
+    // The extended dialect's. An `else if` is an `if` alone in the `else`
+    // statements.
+    /// `for (init; condition; update) { ... }`, where `init` and `update`
+    /// are `Let`s.
+    For {
+        span: Span,
+        init: Option<Box<Statement>>,
+        condition: Option<Expression>,
+        update: Option<Box<Statement>>,
+        statements: Vec<Statement>,
+    },
+    Break {
+        span: Span,
+    },
+    Continue {
+        span: Span,
+    },
+}
+
+impl Statement {
+    /// Where the statement starts, or for `let` its target.
+    pub fn span(&self) -> Span {
+        match self {
+            Statement::Let { target, .. } => target.span,
+            Statement::If { span, .. }
+            | Statement::While { span, .. }
+            | Statement::Do { span, .. }
+            | Statement::Return { span, .. }
+            | Statement::For { span, .. }
+            | Statement::Break { span }
+            | Statement::Continue { span } => *span,
+        }
+    }
+}
+
+/// `term (op term)*`, as flat as the Jack grammar has it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+    pub term: Term,
+    pub rest: Vec<(Op, Span, Term)>,
+}
+
+impl Expression {
+    /// Groups the operators into binary operations.
+    pub fn group(&self, grouping: Grouping) -> Operation<'_> {
+        let mut rest = self.rest.iter().peekable();
+        climb(Operation::Term(&self.term), &mut rest, 0, grouping)
+    }
+
+    /// The value of a constant expression, one made of literals, `true`,
+    /// `false`, `null`, the named `constants` and operators, as the VM
+    /// would compute it. `None` if it is not constant or divides by zero.
+    pub fn value(&self, constants: &HashMap<String, i16>, grouping: Grouping) -> Option<i16> {
+        self.group(grouping).value(constants, grouping)
+    }
+
+    pub fn span(&self) -> Span {
+        let end = match self.rest.last() {
+            Some((_, _, term)) => term.span().end,
+            None => self.term.span().end,
+        };
+        Span {
+            start: self.term.span().start,
+            end,
+        }
+    }
+}
+
+/// How the operators of an expression group. Jack applies them left to
+/// right, so `1 + 2 * 3` is 9. With precedence, `*` and `/` come first,
+/// then `+` and `-`, then `<`, `>` and `=`, then `&` and `|`, so it is 7.
+/// Operators of the same precedence group to the left either way.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Grouping {
+    #[default]
+    LeftToRight,
+    Precedence,
+}
+
+/// An expression as the binary operations that compute it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation<'a> {
+    Term(&'a Term),
+    /// The operator, where it is, and its operands.
+    Binary(Op, Span, Box<Operation<'a>>, Box<Operation<'a>>),
+}
+
+impl Operation<'_> {
+    fn value(&self, constants: &HashMap<String, i16>, grouping: Grouping) -> Option<i16> {
+        let (op, left, right) = match self {
+            Operation::Term(term) => return term.value(constants, grouping),
+            Operation::Binary(op, _, left, right) => (
+                op,
+                left.value(constants, grouping)?,
+                right.value(constants, grouping)?,
+            ),
+        };
+        let value = match op {
+            Op::Add => left.wrapping_add(right),
+            Op::Sub => left.wrapping_sub(right),
+            Op::Mul => left.wrapping_mul(right),
+            Op::Div if right == 0 => return None,
+            Op::Div => left.wrapping_div(right),
+            Op::And => left & right,
+            Op::Or => left | right,
+            Op::Lt => -((left < right) as i16),
+            Op::Gt => -((left > right) as i16),
+            Op::Eq => -((left == right) as i16),
+        };
+        Some(value)
+    }
+}
+
+// Precedence climbing: takes the operators from `rest` that bind at least
+// as tightly as `min` into operations on `left`.
+fn climb<'a>(
+    mut left: Operation<'a>,
+    rest: &mut Peekable<slice::Iter<'a, (Op, Span, Term)>>,
+    min: u8,
+    grouping: Grouping,
+) -> Operation<'a> {
+    let level = |op: Op| match grouping {
+        Grouping::LeftToRight => 0,
+        Grouping::Precedence => op.precedence(),
+    };
+    while let Some((op, span, term)) = rest.next_if(|(op, _, _)| level(*op) >= min) {
+        let mut right = Operation::Term(term);
+        if rest
+            .peek()
+            .is_some_and(|(next, _, _)| level(*next) > level(*op))
+        {
+            right = climb(right, rest, level(*op) + 1, grouping);
+        }
+        left = Operation::Binary(*op, *span, Box::new(left), Box::new(right));
+    }
+    left
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    And,
+    Or,
+    Lt,
+    Gt,
+    Eq,
+}
+
+impl Op {
+    /// How tightly the operator binds with precedence, the higher the
+    /// tighter.
+    pub fn precedence(self) -> u8 {
+        match self {
+            Op::And | Op::Or => 0,
+            Op::Lt | Op::Gt | Op::Eq => 1,
+            Op::Add | Op::Sub => 2,
+            Op::Mul | Op::Div => 3,
+        }
+    }
+
+    pub fn from_symbol(symbol: &str) -> Option<Self> {
+        let op = match symbol {
+            "+" => Op::Add,
+            "-" => Op::Sub,
+            "*" => Op::Mul,
+            "/" => Op::Div,
+            "&" => Op::And,
+            "|" => Op::Or,
+            "<" => Op::Lt,
+            ">" => Op::Gt,
+            "=" => Op::Eq,
+            _ => return None,
+        };
+        Some(op)
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Op::Add => "+",
+            Op::Sub => "-",
+            Op::Mul => "*",
+            Op::Div => "/",
+            Op::And => "&",
+            Op::Or => "|",
+            Op::Lt => "<",
+            Op::Gt => ">",
+            Op::Eq => "=",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+impl fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnaryOp::Neg => write!(f, "-"),
+            UnaryOp::Not => write!(f, "~"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeywordConst {
+    True,
+    False,
+    Null,
+    This,
+}
+
+impl fmt::Display for KeywordConst {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeywordConst::True => write!(f, "true"),
+            KeywordConst::False => write!(f, "false"),
+            KeywordConst::Null => write!(f, "null"),
+            KeywordConst::This => write!(f, "this"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    Int(i16, Span),
+    Str(String, Span),
+    Keyword(KeywordConst, Span),
+    Var(Name),
+    Index(Name, Box<Expression>, Span),
+    Call(Call),
+    Paren(Box<Expression>, Span),
+    Unary(UnaryOp, Box<Term>, Span),
+}
+
+impl Term {
+    // See `Expression::value`.
+    fn value(&self, constants: &HashMap<String, i16>, grouping: Grouping) -> Option<i16> {
+        match self {
+            Term::Int(value, _) => Some(*value),
+            Term::Keyword(KeywordConst::True, _) => Some(-1),
+            Term::Keyword(KeywordConst::False, _) | Term::Keyword(KeywordConst::Null, _) => Some(0),
+            Term::Var(name) => constants.get(&name.name).copied(),
+            Term::Paren(expression, _) => expression.value(constants, grouping),
+            Term::Unary(UnaryOp::Neg, term, _) => {
+                Some(term.value(constants, grouping)?.wrapping_neg())
+            }
+            Term::Unary(UnaryOp::Not, term, _) => Some(!term.value(constants, grouping)?),
+            _ => None,
+        }
+    }
+
+    /// The whole term, e.g. from `a` to `]` for `a[i]`.
+    pub fn span(&self) -> Span {
+        match self {
+            Term::Int(_, span)
+            | Term::Str(_, span)
+            | Term::Keyword(_, span)
+            | Term::Index(_, _, span)
+            | Term::Paren(_, span)
+            | Term::Unary(_, _, span) => *span,
+            Term::Var(name) => name.span,
+            Term::Call(call) => call.span,
+        }
+    }
+}
+
+// subroutineName '(' expressionList ')' |
+// (className | varName) '.' subroutineName '(' expressionList ')'
+#[derive(Debug, Clone, PartialEq)]
+pub struct Call {
+    pub receiver: Option<Name>,
+    pub name: Name,
+    pub arguments: Vec<Expression>,
+    /// The whole call, up to the closing parenthesis.
+    pub span: Span,
+}
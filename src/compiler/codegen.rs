@@ -0,0 +1,395 @@
+use super::ast::*;
+use super::symbol_table::{Kind, SymbolTable};
+use std::collections::HashMap;
+
+/// Compiles a class to VM code, grouping the operators of expressions by
+/// `grouping`. The extended dialect is lowered to the same commands, so
+/// the output runs on the standard VM emulator either way.
+///
+/// The class is expected to have passed `analyzer::analyze`; what it
+/// checks is not checked again here.
+pub fn generate(class: &Class, grouping: Grouping) -> String {
+    let mut generator = Generator {
+        class,
+        grouping,
+        table: SymbolTable::new(),
+        constants: HashMap::new(),
+        out: String::new(),
+        function: String::new(),
+        labels: 0,
+        loops: Vec::new(),
+    };
+    generator.class();
+    generator.out
+}
+
+struct Generator<'a> {
+    class: &'a Class,
+    grouping: Grouping,
+    table: SymbolTable,
+    constants: HashMap<String, i16>,
+    out: String,
+    // The VM name of the subroutine, e.g. `Main.main`.
+    function: String,
+    // The number of labels used in the subroutine so far.
+    labels: usize,
+    // The labels `continue` and `break` go to in each enclosing loop, the
+    // innermost last.
+    loops: Vec<(String, String)>,
+}
+
+impl<'a> Generator<'a> {
+    fn write(&mut self, command: &str) {
+        self.out.push_str(command);
+        self.out.push('\n');
+    }
+
+    fn push(&mut self, segment: &str, index: usize) {
+        self.write(&format!("push {} {}", segment, index));
+    }
+
+    fn pop(&mut self, segment: &str, index: usize) {
+        self.write(&format!("pop {} {}", segment, index));
+    }
+
+    // A new number for the labels of a statement.
+    fn label(&mut self) -> usize {
+        self.labels += 1;
+        self.labels - 1
+    }
+
+    // The label `kind` of statement `n`, qualified with the subroutine's
+    // name since the VM translator keeps labels global.
+    fn label_name(&self, kind: &str, n: usize) -> String {
+        format!("{}${}{}", self.function, kind, n)
+    }
+
+    // `push constant` only takes 0 to 32767, a negative number is the `not`
+    // of one.
+    fn int(&mut self, value: i16) {
+        if value >= 0 {
+            self.push("constant", value as usize);
+        } else {
+            self.push("constant", !value as usize);
+            self.write("not");
+        }
+    }
+
+    fn class(&mut self) {
+        for dec in &self.class.vars {
+            for name in &dec.names {
+                self.table
+                    .define(&name.name, &dec.var_type, dec.kind.into(), name.span);
+            }
+        }
+        for constant in &self.class.constants {
+            let value = constant
+                .value
+                .value(&self.constants, self.grouping)
+                .unwrap_or(0);
+            self.constants.insert(constant.name.name.clone(), value);
+        }
+        for subroutine in &self.class.subroutines {
+            self.subroutine(subroutine);
+        }
+    }
+
+    fn subroutine(&mut self, subroutine: &Subroutine) {
+        self.table.start_subroutine(subroutine.kind);
+        for parameter in &subroutine.parameters {
+            let name = &parameter.name;
+            self.table
+                .define(&name.name, &parameter.var_type, Kind::Argument, name.span);
+        }
+        for dec in &subroutine.locals {
+            for name in &dec.names {
+                self.table
+                    .define(&name.name, &dec.var_type, Kind::Var, name.span);
+            }
+        }
+        self.labels = 0;
+        self.function = format!("{}.{}", self.class.name.name, subroutine.name.name);
+        let command = format!(
+            "function {} {}",
+            self.function,
+            self.table.var_count(Kind::Var)
+        );
+        self.write(&command);
+        match subroutine.kind {
+            SubroutineKind::Constructor => {
+                self.push("constant", self.table.var_count(Kind::Field));
+                self.write("call Memory.alloc 1");
+                self.pop("pointer", 0);
+            }
+            SubroutineKind::Method => {
+                self.push("argument", 0);
+                self.pop("pointer", 0);
+            }
+            SubroutineKind::Function => {}
+        }
+        self.statements(&subroutine.statements);
+    }
+
+    fn statements(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Let {
+                target,
+                index: None,
+                op,
+                value,
+            } => {
+                if let Some(op) = op {
+                    self.variable(&target.name);
+                    self.expression(value);
+                    self.op(*op);
+                } else {
+                    self.expression(value);
+                }
+                let symbol = self.table.get(&target.name).unwrap();
+                let (segment, index) = (symbol.kind.segment(), symbol.index);
+                self.pop(segment, index);
+            }
+            // The address is kept on the stack while the value is computed,
+            // which may use `that` and call subroutines that use `temp`.
+            Statement::Let {
+                target,
+                index: Some(index),
+                op,
+                value,
+            } => {
+                self.variable(&target.name);
+                self.expression(index);
+                self.write("add");
+                if let Some(op) = op {
+                    self.pop("temp", 0);
+                    self.push("temp", 0);
+                    self.push("temp", 0);
+                    self.pop("pointer", 1);
+                    self.push("that", 0);
+                    self.expression(value);
+                    self.op(*op);
+                } else {
+                    self.expression(value);
+                }
+                self.pop("temp", 0);
+                self.pop("pointer", 1);
+                self.push("temp", 0);
+                self.pop("that", 0);
+            }
+            Statement::If {
+                condition,
+                statements,
+                else_statements,
+                ..
+            } => {
+                let n = self.label();
+                let (skip, end) = (self.label_name("IF_FALSE", n), self.label_name("IF_END", n));
+                self.expression(condition);
+                self.write("not");
+                self.write(&format!("if-goto {}", skip));
+                self.statements(statements);
+                match else_statements {
+                    Some(else_statements) => {
+                        self.write(&format!("goto {}", end));
+                        self.write(&format!("label {}", skip));
+                        self.statements(else_statements);
+                        self.write(&format!("label {}", end));
+                    }
+                    None => self.write(&format!("label {}", skip)),
+                }
+            }
+            Statement::While {
+                condition,
+                statements,
+                ..
+            } => {
+                let n = self.label();
+                let start = self.label_name("WHILE_EXP", n);
+                let end = self.label_name("WHILE_END", n);
+                self.write(&format!("label {}", start));
+                self.expression(condition);
+                self.write("not");
+                self.write(&format!("if-goto {}", end));
+                self.loop_body(statements, &start, &end);
+                self.write(&format!("goto {}", start));
+                self.write(&format!("label {}", end));
+            }
+            Statement::For {
+                init,
+                condition,
+                update,
+                statements,
+                ..
+            } => {
+                let n = self.label();
+                let start = self.label_name("FOR_EXP", n);
+                let (next, end) = (self.label_name("FOR_INC", n), self.label_name("FOR_END", n));
+                if let Some(init) = init {
+                    self.statement(init);
+                }
+                self.write(&format!("label {}", start));
+                if let Some(condition) = condition {
+                    self.expression(condition);
+                    self.write("not");
+                    self.write(&format!("if-goto {}", end));
+                }
+                self.loop_body(statements, &next, &end);
+                self.write(&format!("label {}", next));
+                if let Some(update) = update {
+                    self.statement(update);
+                }
+                self.write(&format!("goto {}", start));
+                self.write(&format!("label {}", end));
+            }
+            Statement::Break { .. } => {
+                let end = &self.loops.last().unwrap().1;
+                let command = format!("goto {}", end);
+                self.write(&command);
+            }
+            Statement::Continue { .. } => {
+                let next = &self.loops.last().unwrap().0;
+                let command = format!("goto {}", next);
+                self.write(&command);
+            }
+            Statement::Do { call, .. } => {
+                self.call(call);
+                self.pop("temp", 0);
+            }
+            Statement::Return { value, .. } => {
+                match value {
+                    Some(value) => self.expression(value),
+                    None => self.push("constant", 0),
+                }
+                self.write("return");
+            }
+        }
+    }
+
+    // The body of a loop whose `continue` goes to `next` and `break` to
+    // `end`.
+    fn loop_body(&mut self, statements: &[Statement], next: &str, end: &str) {
+        self.loops.push((next.to_string(), end.to_string()));
+        self.statements(statements);
+        self.loops.pop();
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        self.operation(&expression.group(self.grouping));
+    }
+
+    fn operation(&mut self, operation: &Operation) {
+        match operation {
+            Operation::Term(term) => self.term(term),
+            Operation::Binary(op, _, left, right) => {
+                self.operation(left);
+                self.operation(right);
+                self.op(*op);
+            }
+        }
+    }
+
+    fn op(&mut self, op: Op) {
+        let command = match op {
+            Op::Add => "add",
+            Op::Sub => "sub",
+            Op::Mul => "call Math.multiply 2",
+            Op::Div => "call Math.divide 2",
+            Op::And => "and",
+            Op::Or => "or",
+            Op::Lt => "lt",
+            Op::Gt => "gt",
+            Op::Eq => "eq",
+        };
+        self.write(command);
+    }
+
+    fn term(&mut self, term: &Term) {
+        match term {
+            Term::Int(value, _) => self.int(*value),
+            Term::Str(value, _) => {
+                self.push("constant", value.chars().count());
+                self.write("call String.new 1");
+                for c in value.chars() {
+                    self.push("constant", c as usize);
+                    self.write("call String.appendChar 2");
+                }
+            }
+            Term::Keyword(KeywordConst::True, _) => {
+                self.push("constant", 0);
+                self.write("not");
+            }
+            Term::Keyword(KeywordConst::False, _) | Term::Keyword(KeywordConst::Null, _) => {
+                self.push("constant", 0)
+            }
+            Term::Keyword(KeywordConst::This, _) => self.push("pointer", 0),
+            Term::Var(name) => self.variable(&name.name),
+            Term::Index(name, index, _) => {
+                self.variable(&name.name);
+                self.expression(index);
+                self.write("add");
+                self.pop("pointer", 1);
+                self.push("that", 0);
+            }
+            Term::Call(call) => self.call(call),
+            Term::Paren(expression, _) => self.expression(expression),
+            Term::Unary(op, term, _) => {
+                self.term(term);
+                match op {
+                    UnaryOp::Neg => self.write("neg"),
+                    UnaryOp::Not => self.write("not"),
+                }
+            }
+        }
+    }
+
+    // Pushes a variable or the value of a constant.
+    fn variable(&mut self, name: &str) {
+        match self.table.get(name) {
+            Some(symbol) => {
+                let (segment, index) = (symbol.kind.segment(), symbol.index);
+                self.push(segment, index);
+            }
+            None => self.int(self.constants[name]),
+        }
+    }
+
+    // A method gets its object as argument 0.
+    fn call(&mut self, call: &Call) {
+        let (class, object) = match &call.receiver {
+            None => {
+                let method = self.class.subroutines.iter().any(|subroutine| {
+                    subroutine.name.name == call.name.name
+                        && subroutine.kind == SubroutineKind::Method
+                });
+                if method {
+                    self.push("pointer", 0);
+                }
+                (self.class.name.name.clone(), method)
+            }
+            Some(receiver) => match self.table.get(&receiver.name) {
+                Some(symbol) => {
+                    let class = symbol.var_type.to_string();
+                    self.variable(&receiver.name);
+                    (class, true)
+                }
+                None => (receiver.name.clone(), false),
+            },
+        };
+        for argument in &call.arguments {
+            self.expression(argument);
+        }
+        let command = format!(
+            "call {}.{} {}",
+            class,
+            call.name.name,
+            call.arguments.len() + object as usize
+        );
+        self.write(&command);
+    }
+}
@@ -1,405 +1,302 @@
-use super::tkzr::{KeyWord, TokenType, Tokenizer};
-use std::fs::File;
-use std::path::Path;
+use super::ast::*;
+use super::parser::{self, ParseError};
+use super::tkzr::escape_xml;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
 use xml::writer::{EmitterConfig, EventWriter, XmlEvent};
 
+/// Writes the parse tree of a class as the XML of project 10.
 pub struct CompilationEngine {
-    tkzr: Tokenizer,
-    writer: EventWriter<File>,
+    source: String,
+    output_path: PathBuf,
 }
 
 impl CompilationEngine {
     pub fn new(path: &Path) -> Self {
-        let tkzr = Tokenizer::new(path);
+        let source = fs::read_to_string(path).unwrap();
         let mut output_path = path.to_path_buf();
         output_path.set_extension("xml");
-        let file = File::create(&output_path).unwrap();
+        CompilationEngine {
+            source,
+            output_path,
+        }
+    }
+
+    /// Parses the class and writes its parse tree next to the source.
+    /// Returns every syntax error instead if there are any, and writes
+    /// nothing.
+    pub fn compile_class(&mut self) -> Vec<ParseError> {
+        let class = match parser::parse(&self.source) {
+            Ok(class) => class,
+            Err(errors) => return errors,
+        };
+        let file = File::create(&self.output_path).unwrap();
         let mut config = EmitterConfig::new();
         config.perform_escaping = false;
         let writer = config
             .write_document_declaration(false)
             .perform_indent(true)
             .create_writer(file);
-        CompilationEngine { tkzr, writer }
+        XmlWriter { writer }.class(&class);
+        Vec::new()
     }
+}
 
-    // 'class' className '{' classVarDec* subroutineDec* '}'
-    pub fn compile_class(&mut self) {
-        self.tkzr.advance();
-        self.write_start_event("class");
-        self.compile_key_word();
-        self.compile_identifier();
-        self.compile_symbol();
-        while let TokenType::KeyWord(key_word) = self.tkzr.token_type() {
-            match key_word {
-                KeyWord::Static | KeyWord::Field => {
-                    self.compile_class_var_dec();
-                }
-                KeyWord::Function | KeyWord::Method | KeyWord::Constructor => {
-                    self.compile_subroutine_dec();
-                }
-                _ => break,
-            }
-        }
-        self.compile_symbol();
-        self.write_end_event();
-    }
+struct XmlWriter {
+    writer: EventWriter<File>,
+}
 
+impl XmlWriter {
     fn write_start_event(&mut self, name: &str) {
         let event: XmlEvent = XmlEvent::start_element(name).into();
         self.writer.write(event).unwrap();
     }
 
-    fn write_characters(&mut self, s: &str) {
-        let event: XmlEvent = XmlEvent::characters(s);
-        self.writer.write(event).unwrap();
-    }
-
     fn write_end_event(&mut self) {
         let event: XmlEvent = XmlEvent::end_element().into();
         self.writer.write(event).unwrap();
     }
 
-    // ('static' | 'field') type varName (',' varName)* ';'
-    fn compile_class_var_dec(&mut self) {
-        self.write_start_event("classVarDec");
-        self.compile_key_word();
-        self.compile_type();
-        loop {
-            self.compile_identifier();
-            if let TokenType::Symbol(s) = self.tkzr.token_type() {
-                if s == ";" {
-                    break;
-                }
-            }
-            self.compile_symbol();
-        }
-        self.compile_symbol();
+    // A terminal element, e.g. `<symbol> &lt; </symbol>`.
+    fn leaf(&mut self, element: &str, text: &str) {
+        self.write_start_event(element);
+        let text = format!(" {} ", escape_xml(text));
+        self.writer.write(XmlEvent::characters(&text)).unwrap();
         self.write_end_event();
     }
 
-    // type: 'int'|'char'|'boolean'|className
-    fn compile_type(&mut self) {
-        match self.tkzr.token_type() {
-            TokenType::KeyWord(_) => self.compile_key_word(),
-            TokenType::Identifier(_) => self.compile_identifier(),
-            _ => panic!(
-                "{:?} is invalid, KeyWord or Identifier is required",
-                self.tkzr.token_type()
-            ),
-        }
+    fn key_word(&mut self, key_word: &str) {
+        self.leaf("keyword", key_word);
     }
 
-    // ('constructor'|'function'|'method')('void'|type) subroutineName
-    fn compile_subroutine_dec(&mut self) {
-        self.write_start_event("subroutineDec");
-        self.compile_key_word();
-        self.compile_type();
-        self.compile_identifier();
-        self.compile_symbol(); // (
-        self.compile_parameter_list();
-        self.compile_symbol(); // )
-        self.compile_subroutine_body();
-        self.write_end_event();
+    fn symbol(&mut self, symbol: &str) {
+        self.leaf("symbol", symbol);
     }
 
-    // ((type varName)(',' type varName)*)?
-    fn compile_parameter_list(&mut self) {
-        self.write_start_event("parameterList");
-        loop {
-            if let TokenType::Symbol(symbol) = self.tkzr.token_type() {
-                if symbol == ")" {
-                    break;
-                }
-                self.compile_symbol();
-            }
-            self.compile_type();
-            self.compile_identifier();
-        }
-        self.write_end_event();
+    fn identifier(&mut self, name: &Name) {
+        self.leaf("identifier", &name.name);
     }
 
-    // '{' varDec* statements '}'
-    fn compile_subroutine_body(&mut self) {
-        self.write_start_event("subroutineBody");
-        self.compile_symbol();
-        while let TokenType::KeyWord(key_word) = self.tkzr.token_type() {
-            match key_word {
-                KeyWord::Var => self.compile_var_dec(),
-                KeyWord::Let | KeyWord::Do | KeyWord::Return | KeyWord::If => {
-                    self.compile_statements()
-                }
-                _ => {}
-            }
+    fn var_type(&mut self, var_type: &Type) {
+        match var_type {
+            Type::Class(name) => self.leaf("identifier", name),
+            var_type => self.key_word(&var_type.to_string()),
         }
-        self.compile_symbol();
-        self.write_end_event();
     }
 
-    // 'var' type varName (',' varName)* ';'
-    fn compile_var_dec(&mut self) {
-        self.write_start_event("varDec");
-        self.compile_key_word();
-        loop {
-            if let TokenType::Symbol(s) = self.tkzr.token_type() {
-                self.compile_symbol();
-                if s == ";" {
-                    break;
-                }
-            }
-            self.compile_type();
+    // 'class' className '{' classVarDec* subroutineDec* '}'
+    fn class(&mut self, class: &Class) {
+        self.write_start_event("class");
+        self.key_word("class");
+        self.identifier(&class.name);
+        self.symbol("{");
+        for dec in &class.vars {
+            self.write_start_event("classVarDec");
+            self.key_word(match dec.kind {
+                ClassVarKind::Static => "static",
+                ClassVarKind::Field => "field",
+            });
+            self.var_type(&dec.var_type);
+            self.names(&dec.names);
+            self.write_end_event();
         }
+        for subroutine in &class.subroutines {
+            self.subroutine(subroutine);
+        }
+        self.symbol("}");
         self.write_end_event();
     }
 
-    // letStatement | ifStatement | whileStatement | doStatement | returnStatement
-    fn compile_statements(&mut self) {
-        self.write_start_event("statements");
-        while let TokenType::KeyWord(key_word) = self.tkzr.token_type() {
-            match key_word {
-                KeyWord::If => self.compile_if(),
-                KeyWord::Let => self.compile_let(),
-                KeyWord::Do => self.compile_do(),
-                KeyWord::Return => self.compile_return(),
-                KeyWord::While => self.compile_while(),
-                _ => break,
+    // varName (',' varName)* ';'
+    fn names(&mut self, names: &[Name]) {
+        for (i, name) in names.iter().enumerate() {
+            if i > 0 {
+                self.symbol(",");
             }
+            self.identifier(name);
         }
-        self.write_end_event();
+        self.symbol(";");
     }
 
-    // 'let' varName('[' expression ']')? '=' expression ';'
-    fn compile_let(&mut self) {
-        self.write_start_event("letStatement");
-        self.compile_key_word();
-        // Todo(zhoufan): 2d array?
-        if self.tkzr.next_token() == Some("[".to_string()) {
-            self.compile_array();
-        } else {
-            self.compile_identifier();
+    // ('constructor' | 'function' | 'method') ('void' | type) subroutineName
+    // '(' parameterList ')' subroutineBody
+    fn subroutine(&mut self, subroutine: &Subroutine) {
+        self.write_start_event("subroutineDec");
+        self.key_word(&subroutine.kind.to_string());
+        match &subroutine.return_type {
+            Some(return_type) => self.var_type(return_type),
+            None => self.key_word("void"),
+        }
+        self.identifier(&subroutine.name);
+        self.symbol("(");
+        self.write_start_event("parameterList");
+        for (i, parameter) in subroutine.parameters.iter().enumerate() {
+            if i > 0 {
+                self.symbol(",");
+            }
+            self.var_type(&parameter.var_type);
+            self.identifier(&parameter.name);
         }
-        self.compile_symbol();
-        self.compile_expression();
-        self.compile_symbol();
-        self.write_end_event();
-    }
-
-    // 'do' sobroutineCall ';'
-    fn compile_do(&mut self) {
-        self.write_start_event("doStatement");
-        self.compile_key_word();
-        self.compile_subroutine_call();
-        self.compile_symbol();
         self.write_end_event();
-    }
+        self.symbol(")");
 
-    // 'return' expression? ';'
-    fn compile_return(&mut self) {
-        self.write_start_event("returnStatement");
-        self.compile_key_word();
-        match self.tkzr.token_type() {
-            TokenType::KeyWord(_) => self.compile_expression(),
-            TokenType::Identifier(_) => self.compile_expression(),
-            _ => {}
+        self.write_start_event("subroutineBody");
+        self.symbol("{");
+        for dec in &subroutine.locals {
+            self.write_start_event("varDec");
+            self.key_word("var");
+            self.var_type(&dec.var_type);
+            self.names(&dec.names);
+            self.write_end_event();
         }
-        self.compile_symbol();
+        self.statements(&subroutine.statements);
+        self.symbol("}");
         self.write_end_event();
-    }
-
-    // 'while' '(' expression ')' '{' statements '}'
-    fn compile_while(&mut self) {
-        self.write_start_event("whileStatement");
-        self.compile_key_word();
-        self.compile_symbol();
-        self.compile_expression();
-        self.compile_symbol();
-        self.compile_symbol();
-        self.compile_statements();
-        self.compile_symbol();
         self.write_end_event();
     }
 
-    // 'if' '(' expression ')' '{' statements '}' ('else' '{' statements '}')?
-    fn compile_if(&mut self) {
-        self.write_start_event("ifStatement");
-        self.compile_key_word();
-        self.compile_symbol();
-        self.compile_expression();
-        self.compile_symbol();
-        self.compile_symbol();
-        self.compile_statements();
-        self.compile_symbol();
-        if let TokenType::KeyWord(key_word) = self.tkzr.token_type() {
-            if key_word == KeyWord::Else {
-                self.compile_key_word();
-                self.compile_symbol();
-                self.compile_statements();
-                self.compile_symbol();
-            }
+    fn statements(&mut self, statements: &[Statement]) {
+        self.write_start_event("statements");
+        for statement in statements {
+            self.statement(statement);
         }
         self.write_end_event();
     }
 
-    // term(op term)*
-    fn compile_expression(&mut self) {
-        self.write_start_event("expression");
-        self.compile_term();
-        while let TokenType::Symbol(symbol) = self.tkzr.token_type() {
-            if symbol == "*"
-                || symbol == "/"
-                || symbol == "|"
-                || symbol == "+"
-                || symbol == "&lt;"
-                || symbol == "&amp;"
-                || symbol == "&gt;"
-                || symbol == "-"
-                || symbol == "="
-            {
-                self.compile_symbol();
-                self.compile_term();
-            } else {
-                break;
-            }
-        }
-        self.write_end_event();
+    // '{' statements '}'
+    fn block(&mut self, statements: &[Statement]) {
+        self.symbol("{");
+        self.statements(statements);
+        self.symbol("}");
     }
 
-    // integerConstant | stringConstant | keywordConstant | varName |
-    // varName '[' expression ']' | subroutineCall | '(' expression ')' | unaryOp term
-    fn compile_term(&mut self) {
-        self.write_start_event("term");
-        match self.tkzr.token_type() {
-            TokenType::IntConst(_) => self.compile_int(),
-            TokenType::StringConst(_) => self.compile_string(),
-            TokenType::KeyWord(key_word) => match key_word {
-                KeyWord::This | KeyWord::True | KeyWord::False | KeyWord::Null => {
-                    self.compile_key_word();
-                }
-                _ => panic!("{:?} is invalid in term", self.tkzr.token_type()),
-            },
-            TokenType::Identifier(_) => {
-                if self.tkzr.next_token() == Some(".".to_string()) {
-                    self.compile_subroutine_call();
-                } else if self.tkzr.next_token() == Some("[".to_string()) {
-                    self.compile_array();
-                } else {
-                    self.compile_identifier();
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            // Only standard Jack is compiled to XML, which has no compound
+            // assignment.
+            Statement::Let {
+                target,
+                index,
+                value,
+                ..
+            } => {
+                self.write_start_event("letStatement");
+                self.key_word("let");
+                self.identifier(target);
+                if let Some(index) = index {
+                    self.symbol("[");
+                    self.expression(index);
+                    self.symbol("]");
                 }
+                self.symbol("=");
+                self.expression(value);
+                self.symbol(";");
             }
-            TokenType::Symbol(symbol) => {
-                if symbol == "(" {
-                    self.compile_symbol();
-                    self.compile_expression();
-                    self.compile_symbol();
-                } else if symbol == "-" || symbol == "~" {
-                    self.compile_symbol();
-                    self.compile_term();
+            Statement::If {
+                condition,
+                statements,
+                else_statements,
+                ..
+            } => {
+                self.write_start_event("ifStatement");
+                self.key_word("if");
+                self.symbol("(");
+                self.expression(condition);
+                self.symbol(")");
+                self.block(statements);
+                if let Some(statements) = else_statements {
+                    self.key_word("else");
+                    self.block(statements);
                 }
             }
-        }
-        self.write_end_event();
-    }
-
-    // (expression( ',' expression)*)?
-    fn compile_expression_list(&mut self) {
-        self.write_start_event("expressionList");
-        loop {
-            if let TokenType::Symbol(symbol) = self.tkzr.token_type() {
-                if symbol == ")" {
-                    break;
-                }
-                if symbol == "," {
-                    // Todo(zhoufan): why if
-                    self.compile_symbol();
+            Statement::While {
+                condition,
+                statements,
+                ..
+            } => {
+                self.write_start_event("whileStatement");
+                self.key_word("while");
+                self.symbol("(");
+                self.expression(condition);
+                self.symbol(")");
+                self.block(statements);
+            }
+            Statement::Do { call, .. } => {
+                self.write_start_event("doStatement");
+                self.key_word("do");
+                self.call(call);
+                self.symbol(";");
+            }
+            Statement::Return { value, .. } => {
+                self.write_start_event("returnStatement");
+                self.key_word("return");
+                if let Some(value) = value {
+                    self.expression(value);
                 }
+                self.symbol(";");
+            }
+            Statement::For { .. } | Statement::Break { .. } | Statement::Continue { .. } => {
+                unreachable!("the extended dialect has no XML parse tree")
             }
-            self.compile_expression();
         }
         self.write_end_event();
     }
 
-    // subroutineName '(' expressionList ')' | (className | varName) '.' subroutineName '(' expressionList ')'
-    fn compile_subroutine_call(&mut self) {
-        self.compile_identifier();
-        if self.tkzr.current_token == "." {
-            self.compile_symbol();
-            self.compile_identifier();
+    // term (op term)*
+    fn expression(&mut self, expression: &Expression) {
+        self.write_start_event("expression");
+        self.term(&expression.term);
+        for (op, _, term) in &expression.rest {
+            self.symbol(&op.to_string());
+            self.term(term);
         }
-        self.compile_symbol();
-        self.compile_expression_list();
-        self.compile_symbol();
-    }
-
-    fn compile_array(&mut self) {
-        self.compile_identifier();
-        self.compile_symbol();
-        self.compile_expression();
-        self.compile_symbol();
+        self.write_end_event();
     }
 
-    fn compile_key_word(&mut self) {
-        if let TokenType::KeyWord(key_word) = self.tkzr.token_type() {
-            self.write_start_event("keyword");
-            let key_word = format!("{}", key_word);
-            self.write_characters(&key_word);
-            self.write_end_event();
-            if self.tkzr.has_more_commands() {
-                self.tkzr.advance();
+    fn term(&mut self, term: &Term) {
+        self.write_start_event("term");
+        match term {
+            Term::Int(value, _) => self.leaf("integerConstant", &value.to_string()),
+            Term::Str(value, _) => self.leaf("stringConstant", value),
+            Term::Keyword(constant, _) => self.key_word(&constant.to_string()),
+            Term::Var(name) => self.identifier(name),
+            Term::Index(name, index, _) => {
+                self.identifier(name);
+                self.symbol("[");
+                self.expression(index);
+                self.symbol("]");
             }
-        } else {
-            panic!("{:?} is not a KeyWord", self.tkzr.token_type());
-        }
-    }
-
-    fn compile_symbol(&mut self) {
-        if let TokenType::Symbol(symbol) = self.tkzr.token_type() {
-            self.write_start_event("symbol");
-            self.write_characters(&symbol);
-            self.write_end_event();
-            if self.tkzr.has_more_commands() {
-                self.tkzr.advance();
+            Term::Call(call) => self.call(call),
+            Term::Paren(expression, _) => {
+                self.symbol("(");
+                self.expression(expression);
+                self.symbol(")");
             }
-        } else {
-            panic!("{:?} is not a Symbol", self.tkzr.token_type());
-        }
-    }
-
-    fn compile_int(&mut self) {
-        if let TokenType::IntConst(val) = self.tkzr.token_type() {
-            self.write_start_event("integerConstant");
-            self.write_characters(&val.to_string());
-            self.write_end_event();
-            if self.tkzr.has_more_commands() {
-                self.tkzr.advance();
+            Term::Unary(op, term, _) => {
+                self.symbol(&op.to_string());
+                self.term(term);
             }
-        } else {
-            panic!("{:?} is not a IntConst", self.tkzr.token_type());
         }
+        self.write_end_event();
     }
 
-    fn compile_string(&mut self) {
-        if let TokenType::StringConst(val) = self.tkzr.token_type() {
-            self.write_start_event("stringConstant");
-            self.write_characters(&val);
-            self.write_end_event();
-            if self.tkzr.has_more_commands() {
-                self.tkzr.advance();
-            }
-        } else {
-            panic!("{:?} is not a StringConst", self.tkzr.token_type());
+    // subroutineName '(' expressionList ')' |
+    // (className | varName) '.' subroutineName '(' expressionList ')'
+    fn call(&mut self, call: &Call) {
+        if let Some(receiver) = &call.receiver {
+            self.identifier(receiver);
+            self.symbol(".");
         }
-    }
-
-    fn compile_identifier(&mut self) {
-        if let TokenType::Identifier(identifier) = self.tkzr.token_type() {
-            self.write_start_event("identifier");
-            self.write_characters(&identifier);
-            self.write_end_event();
-            if self.tkzr.has_more_commands() {
-                self.tkzr.advance();
+        self.identifier(&call.name);
+        self.symbol("(");
+        self.write_start_event("expressionList");
+        for (i, argument) in call.arguments.iter().enumerate() {
+            if i > 0 {
+                self.symbol(",");
             }
-        } else {
-            panic!("{:?} is not a Identifier", self.tkzr.token_type());
+            self.expression(argument);
         }
+        self.write_end_event();
+        self.symbol(")");
     }
 }
@@ -0,0 +1,414 @@
+use super::ast::*;
+use super::parser::{self, ParseError};
+use super::tkzr::{self, Comment, Position, Span, Token};
+
+const INDENT: &str = "    ";
+
+// Tokens written right after what comes before them, even a comment.
+const NO_SPACE_BEFORE: [&str; 4] = [",", ";", ")", "]"];
+
+/// Formats the source of a class: one declaration or statement per line,
+/// indented by four spaces a level, with a space around binary operators
+/// and after commas. Comments are kept where they were relative to the
+/// code, and up to one blank line wherever there were some.
+///
+/// The layout comes from the parse tree while the tokens are written as in
+/// the source, so the result has the same parse tree, and formatting it
+/// again changes nothing.
+pub fn format(source: &str) -> Result<String, Vec<ParseError>> {
+    let class = parser::parse(source)?;
+    let mut printer = Printer {
+        tokens: tkzr::tokenize_partial(source).0,
+        next: 0,
+        comments: tkzr::comments(source),
+        next_comment: 0,
+        out: String::new(),
+        indent: 0,
+        continued: false,
+        line_start: true,
+        space: false,
+        last_line: 0,
+        last: String::new(),
+    };
+    printer.class(&class);
+    printer.newline();
+    printer.comments_before(None);
+    printer.newline();
+    Ok(printer.out)
+}
+
+struct Printer {
+    tokens: Vec<Token>,
+    // The next token to write.
+    next: usize,
+    comments: Vec<Comment>,
+    next_comment: usize,
+    out: String,
+    indent: usize,
+    // Whether a comment broke the current statement across lines, which
+    // indents the rest of it a level more.
+    continued: bool,
+    // Whether nothing is written on the current line yet.
+    line_start: bool,
+    // Whether to put a space before what comes next on the line.
+    space: bool,
+    // The source line the last token or comment written ends on.
+    last_line: usize,
+    // The last token or comment written.
+    last: String,
+}
+
+impl Printer {
+    // Writes a token or comment, indenting it at the start of a line.
+    fn write(&mut self, text: &str, span: Span) {
+        if self.line_start {
+            let blank_line = span.start.line > self.last_line + 1;
+            if self.last_line > 0 && blank_line && self.last != "{" && text != "}" {
+                self.out.push('\n');
+            }
+            let level = self.indent + self.continued as usize;
+            self.out.push_str(&INDENT.repeat(level));
+            self.line_start = false;
+        } else if self.space && !NO_SPACE_BEFORE.contains(&text) {
+            self.out.push(' ');
+        }
+        self.space = false;
+        self.out.push_str(text);
+        self.last_line = span.end.line;
+        self.last = text.to_string();
+    }
+
+    // Writes the next token, which must be `text`, after the comments
+    // before it.
+    fn token(&mut self, text: &str) {
+        debug_assert_eq!(self.tokens[self.next].text, text);
+        self.literal();
+    }
+
+    // Writes the next token as it is written in the source.
+    fn literal(&mut self) {
+        let token = self.tokens[self.next].clone();
+        self.comments_before(Some(token.span.start));
+        self.write(&token.text, token.span);
+        self.next += 1;
+    }
+
+    fn space(&mut self) {
+        self.space = true;
+    }
+
+    fn break_line(&mut self) {
+        if !self.line_start {
+            self.out.push('\n');
+            self.line_start = true;
+        }
+    }
+
+    // Ends a line after the comments that follow its last token in the
+    // source.
+    fn newline(&mut self) {
+        let next = self.tokens.get(self.next).map(|token| token.span.start);
+        while let Some(comment) = self.comments.get(self.next_comment).cloned() {
+            let before_next = next.is_none_or(|next| comment.span.start < next);
+            if comment.span.start.line != self.last_line || !before_next {
+                break;
+            }
+            self.next_comment += 1;
+            self.space();
+            self.comment(&comment);
+        }
+        self.break_line();
+        self.continued = false;
+    }
+
+    // Writes the comments before `end`, or all that are left. A comment
+    // that was on a line of its own stays on one.
+    fn comments_before(&mut self, end: Option<Position>) {
+        while let Some(comment) = self.comments.get(self.next_comment).cloned() {
+            if end.is_some_and(|end| comment.span.start >= end) {
+                break;
+            }
+            self.next_comment += 1;
+            let mid_line = !self.line_start;
+            let own_line = comment.span.start.line > self.last_line;
+            if own_line && mid_line {
+                self.break_line();
+                self.continued = true;
+            } else {
+                self.space();
+            }
+            self.comment(&comment);
+            if own_line || comment.text.starts_with("//") {
+                self.break_line();
+                self.continued |= mid_line;
+            } else {
+                self.space();
+            }
+        }
+    }
+
+    // Writes a comment without trailing white space, moving the lines of a
+    // block comment along with its first.
+    fn comment(&mut self, comment: &Comment) {
+        let mut lines = comment.text.lines().map(str::trim_end);
+        let first = lines.next().unwrap_or("");
+        self.write(first, comment.span);
+        let line_start = self.out.rfind('\n').map_or(0, |i| i + 1);
+        let column = self.out[line_start..].chars().count() - first.chars().count();
+        let shift = column as isize - (comment.span.start.column as isize - 1);
+        for line in lines {
+            self.out.push('\n');
+            if line.is_empty() {
+                continue;
+            }
+            if shift >= 0 {
+                self.out.push_str(&" ".repeat(shift as usize));
+                self.out.push_str(line);
+            } else {
+                let indent = line.len() - line.trim_start().len();
+                let removed = indent.min(-shift as usize);
+                self.out.push_str(&line[removed..]);
+            }
+        }
+    }
+
+    // '{' ... '}', after the contents, indented a level.
+    fn close_block(&mut self) {
+        self.newline();
+        let end = self.tokens.get(self.next).map(|token| token.span.start);
+        self.comments_before(end);
+        self.indent -= 1;
+        self.newline();
+        self.token("}");
+    }
+
+    fn name(&mut self, name: &Name) {
+        self.token(&name.name);
+    }
+
+    fn var_type(&mut self, var_type: &Type) {
+        self.token(&var_type.to_string());
+    }
+
+    // item (',' item)*
+    fn list<T>(&mut self, items: &[T], mut item: impl FnMut(&mut Self, &T)) {
+        for (i, x) in items.iter().enumerate() {
+            if i > 0 {
+                self.token(",");
+                self.space();
+            }
+            item(self, x);
+        }
+    }
+
+    // 'class' className '{' classVarDec* subroutineDec* '}'
+    fn class(&mut self, class: &Class) {
+        self.token("class");
+        self.space();
+        self.name(&class.name);
+        self.space();
+        self.token("{");
+        self.indent += 1;
+        for dec in &class.vars {
+            self.newline();
+            self.literal();
+            self.space();
+            self.var_type(&dec.var_type);
+            self.space();
+            self.list(&dec.names, Self::name);
+            self.token(";");
+        }
+        for subroutine in &class.subroutines {
+            self.newline();
+            self.subroutine(subroutine);
+        }
+        self.close_block();
+    }
+
+    // ('constructor' | 'function' | 'method') ('void' | type) subroutineName
+    // '(' parameterList ')' '{' varDec* statements '}'
+    fn subroutine(&mut self, subroutine: &Subroutine) {
+        self.token(&subroutine.kind.to_string());
+        self.space();
+        match &subroutine.return_type {
+            Some(return_type) => self.var_type(return_type),
+            None => self.token("void"),
+        }
+        self.space();
+        self.name(&subroutine.name);
+        self.token("(");
+        self.list(&subroutine.parameters, |printer, parameter| {
+            printer.var_type(&parameter.var_type);
+            printer.space();
+            printer.name(&parameter.name);
+        });
+        self.token(")");
+        self.space();
+        self.token("{");
+        self.indent += 1;
+        for dec in &subroutine.locals {
+            self.newline();
+            self.token("var");
+            self.space();
+            self.var_type(&dec.var_type);
+            self.space();
+            self.list(&dec.names, Self::name);
+            self.token(";");
+        }
+        self.statements(&subroutine.statements);
+        self.close_block();
+    }
+
+    fn statements(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.newline();
+            self.statement(statement);
+        }
+    }
+
+    // '{' statements '}'
+    fn block(&mut self, statements: &[Statement]) {
+        self.token("{");
+        self.indent += 1;
+        self.statements(statements);
+        self.close_block();
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            // Only standard Jack is formatted, which has no compound
+            // assignment.
+            Statement::Let {
+                target,
+                index,
+                value,
+                ..
+            } => {
+                self.token("let");
+                self.space();
+                self.name(target);
+                if let Some(index) = index {
+                    self.token("[");
+                    self.expression(index);
+                    self.token("]");
+                }
+                self.space();
+                self.token("=");
+                self.space();
+                self.expression(value);
+                self.token(";");
+            }
+            Statement::If {
+                condition,
+                statements,
+                else_statements,
+                ..
+            } => {
+                self.token("if");
+                self.space();
+                self.token("(");
+                self.expression(condition);
+                self.token(")");
+                self.space();
+                self.block(statements);
+                if let Some(statements) = else_statements {
+                    // A comment on a line of its own or a line comment
+                    // after the `}` before `else` puts it on a new line,
+                    // like a statement.
+                    let start = self.tokens[self.next].span.start;
+                    let own_line = self.comments[self.next_comment..]
+                        .iter()
+                        .take_while(|comment| comment.span.start < start)
+                        .any(|comment| {
+                            comment.span.start.line > self.last_line
+                                || comment.text.starts_with("//")
+                        });
+                    if own_line {
+                        self.newline();
+                        self.comments_before(Some(start));
+                    } else {
+                        self.space();
+                    }
+                    self.token("else");
+                    self.space();
+                    self.block(statements);
+                }
+            }
+            Statement::While {
+                condition,
+                statements,
+                ..
+            } => {
+                self.token("while");
+                self.space();
+                self.token("(");
+                self.expression(condition);
+                self.token(")");
+                self.space();
+                self.block(statements);
+            }
+            Statement::Do { call, .. } => {
+                self.token("do");
+                self.space();
+                self.call(call);
+                self.token(";");
+            }
+            Statement::Return { value, .. } => {
+                self.token("return");
+                if let Some(value) = value {
+                    self.space();
+                    self.expression(value);
+                }
+                self.token(";");
+            }
+            Statement::For { .. } | Statement::Break { .. } | Statement::Continue { .. } => {
+                unreachable!("only standard Jack is formatted")
+            }
+        }
+    }
+
+    // term (op term)*
+    fn expression(&mut self, expression: &Expression) {
+        self.term(&expression.term);
+        for (op, _, term) in &expression.rest {
+            self.space();
+            self.token(&op.to_string());
+            self.space();
+            self.term(term);
+        }
+    }
+
+    fn term(&mut self, term: &Term) {
+        match term {
+            Term::Int(..) | Term::Str(..) | Term::Keyword(..) => self.literal(),
+            Term::Var(name) => self.name(name),
+            Term::Index(name, index, _) => {
+                self.name(name);
+                self.token("[");
+                self.expression(index);
+                self.token("]");
+            }
+            Term::Call(call) => self.call(call),
+            Term::Paren(expression, _) => {
+                self.token("(");
+                self.expression(expression);
+                self.token(")");
+            }
+            Term::Unary(op, term, _) => {
+                self.token(&op.to_string());
+                self.term(term);
+            }
+        }
+    }
+
+    fn call(&mut self, call: &Call) {
+        if let Some(receiver) = &call.receiver {
+            self.name(receiver);
+            self.token(".");
+        }
+        self.name(&call.name);
+        self.token("(");
+        self.list(&call.arguments, Self::expression);
+        self.token(")");
+    }
+}
@@ -0,0 +1,524 @@
+use super::analyzer::{class_table, returns};
+use super::ast::*;
+use super::symbol_table::{ClassTable, Kind, Symbol, SymbolTable};
+use super::tkzr::{Position, Span};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+/// The lint rules by ID, with what each looks for.
+pub const RULES: &[(&str, &str)] = &[
+    ("unused-local", "a local variable that is never read"),
+    ("unused-parameter", "a parameter that is never read"),
+    ("unused-field", "a field or static that is never read"),
+    (
+        "read-before-let",
+        "a local variable read before any let assigns it",
+    ),
+    (
+        "unreachable-code",
+        "a statement after a return, break or continue",
+    ),
+    (
+        "infinite-loop",
+        "a while (true) or for (;;) loop with no return or break in it",
+    ),
+    (
+        "shadowed-field",
+        "a local or parameter named like a field or static",
+    ),
+    (
+        "undisposed-object",
+        "an object created into a local variable and never disposed",
+    ),
+    (
+        "precedence",
+        "an expression that operator precedence would compute differently",
+    ),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub rule: &'static str,
+    pub file: String,
+    pub span: Span,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: warning: {} [{}]",
+            self.file, self.span, self.message, self.rule
+        )
+    }
+}
+
+/// Looks for likely bugs and poor style in programs that compile, with
+/// every rule of `RULES` enabled unless disabled.
+pub struct Linter {
+    enabled: HashSet<&'static str>,
+    grouping: Grouping,
+}
+
+impl Default for Linter {
+    fn default() -> Self {
+        Linter {
+            enabled: RULES.iter().map(|(rule, _)| *rule).collect(),
+            grouping: Grouping::LeftToRight,
+        }
+    }
+}
+
+impl Linter {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Enables a rule, or every rule for `all`.
+    pub fn enable(&mut self, rule: &str) -> Result<(), String> {
+        for rule in Self::rules(rule)? {
+            self.enabled.insert(rule);
+        }
+        Ok(())
+    }
+
+    /// Disables a rule, or every rule for `all`.
+    pub fn disable(&mut self, rule: &str) -> Result<(), String> {
+        for rule in Self::rules(rule)? {
+            self.enabled.remove(rule);
+        }
+        Ok(())
+    }
+
+    fn rules(rule: &str) -> Result<Vec<&'static str>, String> {
+        let rules: Vec<&'static str> = RULES
+            .iter()
+            .map(|(id, _)| *id)
+            .filter(|id| rule == "all" || *id == rule)
+            .collect();
+        match rules.is_empty() {
+            true => Err(format!("unknown lint rule {}", rule)),
+            false => Ok(rules),
+        }
+    }
+
+    pub fn is_enabled(&self, rule: &str) -> bool {
+        self.enabled.contains(rule)
+    }
+
+    /// Lints for a compiler that groups operators by `grouping`. The
+    /// precedence rule, which warns where grouping with precedence would
+    /// give another result, only applies to grouping left to right.
+    pub fn set_grouping(&mut self, grouping: Grouping) {
+        self.grouping = grouping;
+    }
+
+    /// Lints every class of a program, given with the file it came from.
+    pub fn lint(&self, program: &[(String, Class)]) -> Vec<Warning> {
+        let mut pass = Pass {
+            linter: self,
+            classes: class_table(program),
+            file: String::new(),
+            table: SymbolTable::new(),
+            usage: BTreeMap::new(),
+            created: BTreeMap::new(),
+            warnings: Vec::new(),
+        };
+        for (file, class) in program {
+            pass.file = file.clone();
+            pass.class(class);
+        }
+        let mut warnings = pass.warnings;
+        let file_index = |file: &str| program.iter().position(|(f, _)| f == file);
+        warnings.sort_by_key(|w| (file_index(&w.file), w.span.start));
+        warnings
+    }
+}
+
+// How a variable is used, by where it is declared.
+#[derive(Default)]
+struct Usage {
+    read: bool,
+    assigned: bool,
+    disposed: bool,
+    // Whether a read before any let was reported.
+    read_early: bool,
+    // Whether it is used as a value, which may keep an object alive.
+    escapes: bool,
+}
+
+struct Pass<'a> {
+    linter: &'a Linter,
+    classes: ClassTable,
+    file: String,
+    table: SymbolTable,
+    usage: BTreeMap<Position, Usage>,
+    // The locals assigned a new object, and how it was made.
+    created: BTreeMap<Position, (Name, String)>,
+    warnings: Vec<Warning>,
+}
+
+impl Pass<'_> {
+    fn warn(&mut self, rule: &'static str, span: Span, message: String) {
+        if self.linter.is_enabled(rule) {
+            self.warnings.push(Warning {
+                rule,
+                file: self.file.clone(),
+                span,
+                message,
+            });
+        }
+    }
+
+    fn usage(&mut self, symbol: &Symbol) -> &mut Usage {
+        self.usage.entry(symbol.span.start).or_default()
+    }
+
+    fn class(&mut self, class: &Class) {
+        self.table = SymbolTable::new();
+        self.usage.clear();
+        let mut fields = Vec::new();
+        for dec in &class.vars {
+            for name in &dec.names {
+                let kind = dec.kind.into();
+                if self
+                    .table
+                    .define(&name.name, &dec.var_type, kind, name.span)
+                    .is_none()
+                {
+                    fields.push((name.clone(), kind));
+                }
+            }
+        }
+        for subroutine in &class.subroutines {
+            self.subroutine(subroutine);
+        }
+        for (name, kind) in fields {
+            if !self.usage.get(&name.span.start).is_some_and(|u| u.read) {
+                let message = unused(kind, &name.name, self.usage.get(&name.span.start));
+                self.warn("unused-field", name.span, message);
+            }
+        }
+    }
+
+    fn subroutine(&mut self, subroutine: &Subroutine) {
+        self.table.start_subroutine(subroutine.kind);
+        self.created.clear();
+        let mut locals = Vec::new();
+        let names = subroutine
+            .parameters
+            .iter()
+            .map(|parameter| (&parameter.name, &parameter.var_type, Kind::Argument))
+            .chain(subroutine.locals.iter().flat_map(|dec| {
+                dec.names
+                    .iter()
+                    .map(move |name| (name, &dec.var_type, Kind::Var))
+            }));
+        for (name, var_type, kind) in names {
+            if let Some(field) = self.table.get(&name.name).cloned() {
+                if field.kind == Kind::Static || field.kind == Kind::Field {
+                    let message = format!(
+                        "{} {} shadows {} {} declared at {}",
+                        describe(kind),
+                        name.name,
+                        field.kind,
+                        field.name,
+                        field.span.start
+                    );
+                    self.warn("shadowed-field", name.span, message);
+                }
+            }
+            if self
+                .table
+                .define(&name.name, var_type, kind, name.span)
+                .is_none()
+            {
+                locals.push((name.clone(), kind));
+            }
+        }
+        self.statements(&subroutine.statements);
+
+        for (name, kind) in locals {
+            let usage = self.usage.get(&name.span.start);
+            if !usage.is_some_and(|u| u.read) {
+                let rule = match kind {
+                    Kind::Argument => "unused-parameter",
+                    _ => "unused-local",
+                };
+                let message = unused(kind, &name.name, usage);
+                self.warn(rule, name.span, message);
+            }
+        }
+        for (position, (name, constructor)) in std::mem::take(&mut self.created) {
+            let usage = &self.usage[&position];
+            if !usage.disposed && !usage.escapes {
+                let message = format!(
+                    "{} is created with {} but never disposed",
+                    name.name, constructor
+                );
+                self.warn("undisposed-object", name.span, message);
+            }
+        }
+    }
+
+    fn statements(&mut self, statements: &[Statement]) {
+        for (i, statement) in statements.iter().enumerate() {
+            self.statement(statement);
+            let end = match statement {
+                Statement::Break { .. } => "break",
+                Statement::Continue { .. } => "continue",
+                _ if returns(std::slice::from_ref(statement)) => "return",
+                _ => continue,
+            };
+            if let Some(next) = statements.get(i + 1) {
+                let message = format!("unreachable statement after {}", end);
+                self.warn("unreachable-code", next.span(), message);
+            }
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Let {
+                target,
+                index,
+                op,
+                value,
+            } => {
+                let symbol = self.table.get(&target.name).cloned();
+                if index.is_some() || op.is_some() {
+                    self.read(target);
+                }
+                if let Some(index) = index {
+                    self.expression(index);
+                }
+                self.expression(value);
+                if let (Some(symbol), None) = (symbol, index) {
+                    self.usage(&symbol).assigned = true;
+                    if symbol.kind == Kind::Var {
+                        if let Some(constructor) = self.constructor(value) {
+                            self.created
+                                .insert(symbol.span.start, (target.clone(), constructor));
+                        }
+                    }
+                }
+            }
+            Statement::If {
+                condition,
+                statements,
+                else_statements,
+                ..
+            } => {
+                self.expression(condition);
+                self.statements(statements);
+                if let Some(statements) = else_statements {
+                    self.statements(statements);
+                }
+            }
+            Statement::While {
+                span,
+                condition,
+                statements,
+            } => {
+                if is_true(condition) && !leaves_loop(statements) {
+                    let message = "while (true) loop has no return to leave it".to_string();
+                    self.warn("infinite-loop", *span, message);
+                }
+                self.expression(condition);
+                self.statements(statements);
+            }
+            Statement::For {
+                span,
+                init,
+                condition,
+                update,
+                statements,
+            } => {
+                if let Some(init) = init {
+                    self.statement(init);
+                }
+                if condition.as_ref().is_none_or(is_true) && !leaves_loop(statements) {
+                    let message = "for (;;) loop has no return or break to leave it".to_string();
+                    self.warn("infinite-loop", *span, message);
+                }
+                if let Some(condition) = condition {
+                    self.expression(condition);
+                }
+                self.statements(statements);
+                if let Some(update) = update {
+                    self.statement(update);
+                }
+            }
+            Statement::Do { call, .. } => self.call(call),
+            Statement::Return { value, .. } => {
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+            }
+            Statement::Break { .. } | Statement::Continue { .. } => {}
+        }
+    }
+
+    // The constructor a value is a call of, e.g. `Square.new`.
+    fn constructor(&self, value: &Expression) -> Option<String> {
+        let call = match (&value.term, value.rest.is_empty()) {
+            (Term::Call(call), true) => call,
+            _ => return None,
+        };
+        let class = call.receiver.as_ref()?;
+        if self.table.get(&class.name).is_some() {
+            return None;
+        }
+        let kind = self.classes.subroutine(&class.name, &call.name.name)?.kind;
+        if kind == SubroutineKind::Constructor || call.name.name == "new" {
+            Some(format!("{}.{}", class.name, call.name.name))
+        } else {
+            None
+        }
+    }
+
+    // Records a read of a variable, if it is one.
+    fn read(&mut self, name: &Name) {
+        let symbol = match self.table.get(&name.name) {
+            Some(symbol) => symbol.clone(),
+            None => return,
+        };
+        let usage = self.usage(&symbol);
+        usage.read = true;
+        // Only the first such read is reported.
+        if symbol.kind == Kind::Var && !usage.assigned && !usage.read_early {
+            usage.read_early = true;
+            let message = format!("{} is read before any let assigns it", name.name);
+            self.warn("read-before-let", name.span, message);
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        self.term(&expression.term);
+        let mut previous: Option<Op> = None;
+        let mut reported = false;
+        for (op, span, term) in &expression.rest {
+            if let Some(previous) = previous {
+                // The first place where precedence would group differently.
+                let left_to_right = self.linter.grouping == Grouping::LeftToRight;
+                if left_to_right && !reported && op.precedence() > previous.precedence() {
+                    reported = true;
+                    let message = format!(
+                        "{} is applied after {}, as Jack evaluates operators left to right; \
+                         add parentheses",
+                        op, previous
+                    );
+                    self.warn("precedence", *span, message);
+                }
+            }
+            previous = Some(*op);
+            self.term(term);
+        }
+    }
+
+    fn term(&mut self, term: &Term) {
+        match term {
+            Term::Var(name) => {
+                self.read(name);
+                if let Some(symbol) = self.table.get(&name.name).cloned() {
+                    self.usage(&symbol).escapes = true;
+                }
+            }
+            Term::Index(name, index, _) => {
+                self.read(name);
+                self.expression(index);
+            }
+            Term::Call(call) => self.call(call),
+            Term::Paren(expression, _) => self.expression(expression),
+            Term::Unary(_, term, _) => self.term(term),
+            Term::Int(..) | Term::Str(..) | Term::Keyword(..) => {}
+        }
+    }
+
+    fn call(&mut self, call: &Call) {
+        if let Some(receiver) = &call.receiver {
+            self.read(receiver);
+            if call.name.name == "dispose" {
+                if let Some(symbol) = self.table.get(&receiver.name).cloned() {
+                    self.usage(&symbol).disposed = true;
+                }
+            }
+        }
+        for argument in &call.arguments {
+            self.expression(argument);
+        }
+    }
+}
+
+fn describe(kind: Kind) -> &'static str {
+    match kind {
+        Kind::Static => "static",
+        Kind::Field => "field",
+        Kind::Argument => "parameter",
+        Kind::Var => "local",
+    }
+}
+
+fn unused(kind: Kind, name: &str, usage: Option<&Usage>) -> String {
+    match usage {
+        Some(usage) if usage.assigned => {
+            format!("{} {} is assigned but never read", describe(kind), name)
+        }
+        _ => format!("{} {} is never used", describe(kind), name),
+    }
+}
+
+// Whether a condition is `true`, maybe in parentheses.
+fn is_true(condition: &Expression) -> bool {
+    if !condition.rest.is_empty() {
+        return false;
+    }
+    match &condition.term {
+        Term::Keyword(KeywordConst::True, _) => true,
+        Term::Paren(expression, _) => is_true(expression),
+        _ => false,
+    }
+}
+
+fn contains_return(statements: &[Statement]) -> bool {
+    statements.iter().any(|statement| match statement {
+        Statement::Return { .. } => true,
+        Statement::If {
+            statements,
+            else_statements,
+            ..
+        } => {
+            contains_return(statements)
+                || else_statements
+                    .as_ref()
+                    .is_some_and(|statements| contains_return(statements))
+        }
+        Statement::While { statements, .. } | Statement::For { statements, .. } => {
+            contains_return(statements)
+        }
+        _ => false,
+    })
+}
+
+// Whether a loop over `statements` can be left, by a `return` anywhere in
+// them or a `break` outside any inner loop.
+fn leaves_loop(statements: &[Statement]) -> bool {
+    statements.iter().any(|statement| match statement {
+        Statement::Return { .. } | Statement::Break { .. } => true,
+        Statement::If {
+            statements,
+            else_statements,
+            ..
+        } => {
+            leaves_loop(statements)
+                || else_statements
+                    .as_ref()
+                    .is_some_and(|statements| leaves_loop(statements))
+        }
+        Statement::While { statements, .. } | Statement::For { statements, .. } => {
+            contains_return(statements)
+        }
+        _ => false,
+    })
+}
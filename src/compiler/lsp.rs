@@ -0,0 +1,780 @@
+use super::analyzer;
+use super::ast::*;
+use super::parser::{self, ParseError};
+use super::symbol_table::{ClassTable, Kind, SubroutineInfo, Symbol, SymbolTable};
+use super::tkzr::{Position, Span};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+type Response = Result<Value, (i64, String)>;
+
+/// Reads the body of one message framed by a `Content-Length` header,
+/// `None` at the end of the input.
+pub fn read_message(reader: &mut impl BufRead) -> io::Result<Option<Vec<u8>>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse().ok();
+        }
+    }
+    let length = length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length header")
+    })?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+pub fn write_message(writer: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+/// Serves one client over JSON-RPC until it sends `exit` or closes its
+/// input. Returns whether it asked the server to shut down first.
+pub fn run(mut reader: impl BufRead, mut writer: impl Write) -> io::Result<bool> {
+    let mut server = Server::new();
+    while let Some(body) = read_message(&mut reader)? {
+        let replies = match serde_json::from_slice(&body) {
+            Ok(message) => server.handle(&message),
+            Err(e) => vec![response(Value::Null, Err((PARSE_ERROR, e.to_string())))],
+        };
+        for reply in &replies {
+            write_message(&mut writer, reply)?;
+        }
+        if server.exited {
+            break;
+        }
+    }
+    Ok(server.shut_down)
+}
+
+/// A language server for Jack. It keeps the text of the open documents and
+/// answers every request from a fresh parse of the program a document
+/// belongs to: the open documents and `.jack` files in its directory.
+#[derive(Default)]
+pub struct Server {
+    // The text of every open document by URI.
+    documents: HashMap<String, String>,
+    // Whether to report type errors too, from the `strict` initialization
+    // option.
+    strict: bool,
+    shut_down: bool,
+    exited: bool,
+}
+
+// A document parsed along with the rest of its program.
+struct Program {
+    // The syntax errors in the document.
+    errors: Vec<ParseError>,
+    // Every class by URI, the document's first.
+    files: Vec<(String, Class)>,
+    classes: ClassTable,
+}
+
+/// What a name in the source refers to.
+enum Target {
+    Class(String),
+    Subroutine(String, String),
+    Variable(Symbol),
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Handles a request or notification, returns the response and
+    /// notifications to send back.
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = match message["method"].as_str() {
+            Some(method) => method,
+            // A response, the server sends no requests.
+            None => return Vec::new(),
+        };
+        let params = &message["params"];
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            None => return self.notification(method, params),
+        };
+        let result = match method {
+            _ if self.shut_down => Err((INVALID_REQUEST, "the server is shut down".to_string())),
+            "initialize" => {
+                self.strict = params["initializationOptions"]["strict"]
+                    .as_bool()
+                    .unwrap_or(false);
+                Ok(json!({
+                    "capabilities": {
+                        "textDocumentSync": 1,
+                        "definitionProvider": true,
+                        "hoverProvider": true,
+                        "completionProvider": { "triggerCharacters": ["."] },
+                        "documentSymbolProvider": true,
+                    },
+                    "serverInfo": { "name": "jack-lsp" },
+                }))
+            }
+            "shutdown" => {
+                self.shut_down = true;
+                Ok(Value::Null)
+            }
+            "textDocument/definition" => self.definition(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/completion" => self.completion(params),
+            "textDocument/documentSymbol" => self.document_symbols(params),
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method {}", method))),
+        };
+        vec![response(id, result)]
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = match params["textDocument"]["uri"].as_str() {
+            Some(uri) => uri.to_string(),
+            None => {
+                if method == "exit" {
+                    self.exited = true;
+                }
+                return Vec::new();
+            }
+        };
+        let text = match method {
+            "textDocument/didOpen" => params["textDocument"]["text"].as_str(),
+            // With full sync the last change holds the whole text.
+            "textDocument/didChange" => params["contentChanges"]
+                .as_array()
+                .and_then(|changes| changes.last())
+                .and_then(|change| change["text"].as_str()),
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![publish_diagnostics(&uri, Vec::new())];
+            }
+            _ => None,
+        };
+        match text {
+            Some(text) => {
+                self.documents.insert(uri.clone(), text.to_string());
+                self.changed(&uri)
+            }
+            None => Vec::new(),
+        }
+    }
+
+    // The diagnostics of a changed document and of the other open
+    // documents of its program, which may refer to it.
+    fn changed(&self, uri: &str) -> Vec<Value> {
+        let mut uris = vec![uri.to_string()];
+        uris.extend(
+            self.siblings(uri)
+                .into_iter()
+                .filter(|sibling| self.documents.contains_key(sibling)),
+        );
+        uris.iter().map(|uri| self.diagnostics(uri)).collect()
+    }
+
+    // The syntax errors of a document or, if there are none, what the
+    // analyzer finds in it.
+    fn diagnostics(&self, uri: &str) -> Value {
+        let program = match self.program(uri) {
+            Some(program) => program,
+            None => return publish_diagnostics(uri, Vec::new()),
+        };
+        let diagnostics = if !program.errors.is_empty() {
+            program
+                .errors
+                .iter()
+                .map(|e| diagnostic(e.span, &e.message))
+                .collect()
+        } else {
+            analyzer::analyze(&program.files, self.strict)
+                .iter()
+                .filter(|d| d.file == uri)
+                .map(|d| diagnostic(d.span, &d.message))
+                .collect()
+        };
+        publish_diagnostics(uri, diagnostics)
+    }
+
+    fn program(&self, uri: &str) -> Option<Program> {
+        let (class, errors) = parser::parse_partial(self.documents.get(uri)?);
+        let mut files = vec![(uri.to_string(), class)];
+        for sibling in self.siblings(uri) {
+            let text = match self.documents.get(&sibling) {
+                Some(text) => text.clone(),
+                None => match path(&sibling).and_then(|path| fs::read_to_string(path).ok()) {
+                    Some(text) => text,
+                    None => continue,
+                },
+            };
+            files.push((sibling, parser::parse_partial(&text).0));
+        }
+        let classes = analyzer::class_table(&files);
+        Some(Program {
+            errors,
+            files,
+            classes,
+        })
+    }
+
+    // The URIs of the other `.jack` files in the directory of a document,
+    // open or on disk.
+    fn siblings(&self, uri: &str) -> Vec<String> {
+        let dir = match uri.rfind('/') {
+            Some(i) => &uri[..=i],
+            None => return Vec::new(),
+        };
+        let mut uris: Vec<String> = self
+            .documents
+            .keys()
+            .filter(|other| other.starts_with(dir) && !other[dir.len()..].contains('/'))
+            .cloned()
+            .collect();
+        if let Some(entries) = path(dir).and_then(|path| fs::read_dir(path).ok()) {
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if name.ends_with(".jack") {
+                    uris.push(format!("{}{}", dir, name));
+                }
+            }
+        }
+        uris.sort();
+        uris.dedup();
+        uris.retain(|other| other != uri);
+        uris
+    }
+
+    fn definition(&self, params: &Value) -> Response {
+        let (uri, position) = (document(params)?, position(params)?);
+        let program = match self.program(uri) {
+            Some(program) => program,
+            None => return Ok(Value::Null),
+        };
+        let (file, span) = match target_at(&program.files[0].1, position) {
+            Some(Target::Variable(symbol)) => (uri.to_string(), symbol.span),
+            Some(Target::Class(class)) => match program.classes.get(&class) {
+                Some(info) => (info.file.clone(), info.span),
+                None => return Ok(Value::Null),
+            },
+            Some(Target::Subroutine(class, name)) => {
+                match program.classes.subroutine(&class, &name) {
+                    Some(info) => (program.classes.get(&class).unwrap().file.clone(), info.span),
+                    None => return Ok(Value::Null),
+                }
+            }
+            None => return Ok(Value::Null),
+        };
+        // The OS classes have no source.
+        if file.is_empty() {
+            return Ok(Value::Null);
+        }
+        Ok(json!({ "uri": file, "range": range(span) }))
+    }
+
+    fn hover(&self, params: &Value) -> Response {
+        let (uri, position) = (document(params)?, position(params)?);
+        let program = match self.program(uri) {
+            Some(program) => program,
+            None => return Ok(Value::Null),
+        };
+        let contents = match target_at(&program.files[0].1, position) {
+            Some(Target::Variable(symbol)) => {
+                format!("{} {} {}", symbol.kind, symbol.var_type, symbol.name)
+            }
+            Some(Target::Class(class)) if program.classes.get(&class).is_some() => {
+                format!("class {}", class)
+            }
+            Some(Target::Subroutine(class, name)) => {
+                match program.classes.subroutine(&class, &name) {
+                    Some(info) => signature(&class, &name, info),
+                    None => return Ok(Value::Null),
+                }
+            }
+            _ => return Ok(Value::Null),
+        };
+        Ok(json!({ "contents": { "kind": "plaintext", "value": contents } }))
+    }
+
+    // After `ClassName.` the functions and constructors of the class, after
+    // `variable.` the methods of its class, and otherwise the variables in
+    // scope and the classes.
+    fn completion(&self, params: &Value) -> Response {
+        let (uri, position) = (document(params)?, position(params)?);
+        let program = match self.program(uri) {
+            Some(program) => program,
+            None => return Ok(json!([])),
+        };
+        let line = self.documents[uri]
+            .lines()
+            .nth(position.line - 1)
+            .unwrap_or("");
+        let before: String = line.chars().take(position.column - 1).collect();
+        let before = before.trim_end_matches(is_identifier_char);
+        let table = scope(&program.files[0].1, position);
+
+        let receiver = match before.strip_suffix('.') {
+            Some(before) => before
+                .rsplit(|c| !is_identifier_char(c))
+                .next()
+                .unwrap_or(""),
+            None => {
+                let mut items: Vec<(String, Value)> = table
+                    .symbols()
+                    .map(|symbol| {
+                        let kind = match symbol.kind {
+                            Kind::Static | Kind::Field => 5,
+                            Kind::Argument | Kind::Var => 6,
+                        };
+                        let detail = format!("{} {}", symbol.kind, symbol.var_type);
+                        (symbol.name.clone(), kind, detail)
+                    })
+                    .chain(
+                        program
+                            .classes
+                            .classes()
+                            .map(|(name, _)| (name.clone(), 7, format!("class {}", name))),
+                    )
+                    .map(|(label, kind, detail)| {
+                        let item = json!({ "label": label, "kind": kind, "detail": detail });
+                        (label, item)
+                    })
+                    .collect();
+                items.sort_by(|a, b| a.0.cmp(&b.0));
+                return Ok(items.into_iter().map(|(_, item)| item).collect());
+            }
+        };
+        let (class, methods) = match table.get(receiver) {
+            Some(Symbol {
+                var_type: Type::Class(class),
+                ..
+            }) => (class.clone(), true),
+            Some(_) => return Ok(json!([])),
+            None => (receiver.to_string(), false),
+        };
+        let info = match program.classes.get(&class) {
+            Some(info) => info,
+            None => return Ok(json!([])),
+        };
+        let mut subroutines: Vec<(&String, &SubroutineInfo)> = info
+            .subroutines
+            .iter()
+            .filter(|(_, info)| (info.kind == SubroutineKind::Method) == methods)
+            .collect();
+        subroutines.sort_by_key(|(name, _)| *name);
+        let items: Vec<Value> = subroutines
+            .into_iter()
+            .map(|(name, info)| {
+                let kind = match info.kind {
+                    SubroutineKind::Method => 2,
+                    SubroutineKind::Function => 3,
+                    SubroutineKind::Constructor => 4,
+                };
+                json!({ "label": name, "kind": kind, "detail": signature(&class, name, info) })
+            })
+            .collect();
+        Ok(json!(items))
+    }
+
+    // The class of a document with its variables and subroutines.
+    fn document_symbols(&self, params: &Value) -> Response {
+        let uri = document(params)?;
+        let program = match self.program(uri) {
+            Some(program) => program,
+            None => return Ok(json!([])),
+        };
+        let class = &program.files[0].1;
+        if class.name.name.is_empty() {
+            return Ok(json!([]));
+        }
+        let mut end = class.name.span.end;
+        let mut children = Vec::new();
+        for dec in &class.vars {
+            let kind = match dec.kind {
+                ClassVarKind::Static => 13,
+                ClassVarKind::Field => 8,
+            };
+            let detail = format!("{} {}", Kind::from(dec.kind), dec.var_type);
+            for name in &dec.names {
+                children.push(symbol(&name.name, &detail, kind, name.span, name.span, &[]));
+                end = end.max(name.span.end);
+            }
+        }
+        for subroutine in &class.subroutines {
+            let name = &subroutine.name;
+            let kind = match subroutine.kind {
+                SubroutineKind::Method => 6,
+                SubroutineKind::Function => 12,
+                SubroutineKind::Constructor => 9,
+            };
+            let detail = match program.classes.subroutine(&class.name.name, &name.name) {
+                Some(info) => signature(&class.name.name, &name.name, info),
+                None => subroutine.kind.to_string(),
+            };
+            let span = Span {
+                start: subroutine.type_span.start,
+                end: subroutine.end.end,
+            };
+            children.push(symbol(&name.name, &detail, kind, span, name.span, &[]));
+            end = end.max(span.end);
+        }
+        let span = Span {
+            start: class.name.span.start,
+            end,
+        };
+        let detail = format!("class {}", class.name.name);
+        Ok(json!([symbol(
+            &class.name.name,
+            &detail,
+            5,
+            span,
+            class.name.span,
+            &children
+        )]))
+    }
+}
+
+fn response(id: Value, result: Response) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err((code, message)) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": code, "message": message },
+        }),
+    }
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+fn diagnostic(span: Span, message: &str) -> Value {
+    json!({ "range": range(span), "severity": 1, "source": "jack", "message": message })
+}
+
+fn symbol(
+    name: &str,
+    detail: &str,
+    kind: u32,
+    span: Span,
+    selection: Span,
+    children: &[Value],
+) -> Value {
+    json!({
+        "name": name,
+        "detail": detail,
+        "kind": kind,
+        "range": range(span),
+        "selectionRange": range(selection),
+        "children": children,
+    })
+}
+
+// LSP positions count lines and characters from 0.
+fn range(span: Span) -> Value {
+    let position = |p: Position| json!({ "line": p.line - 1, "character": p.column - 1 });
+    json!({ "start": position(span.start), "end": position(span.end) })
+}
+
+fn document(params: &Value) -> Result<&str, (i64, String)> {
+    params["textDocument"]["uri"]
+        .as_str()
+        .ok_or_else(|| (INVALID_PARAMS, "missing textDocument.uri".to_string()))
+}
+
+fn position(params: &Value) -> Result<Position, (i64, String)> {
+    let position = &params["position"];
+    match (position["line"].as_u64(), position["character"].as_u64()) {
+        (Some(line), Some(character)) => Ok(Position {
+            line: line as usize + 1,
+            column: character as usize + 1,
+        }),
+        _ => Err((INVALID_PARAMS, "missing position".to_string())),
+    }
+}
+
+// The path of a `file:` URI.
+fn path(uri: &str) -> Option<PathBuf> {
+    let encoded = uri.strip_prefix("file://")?.as_bytes();
+    let mut decoded = Vec::new();
+    let mut i = 0;
+    while i < encoded.len() {
+        let escape = encoded
+            .get(i + 1..i + 3)
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (encoded[i], escape) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    Some(PathBuf::from(
+        String::from_utf8_lossy(&decoded).into_owned(),
+    ))
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+// e.g. `method void Square.moveTo(int x, int y)`.
+fn signature(class: &str, name: &str, info: &SubroutineInfo) -> String {
+    let return_type = match &info.return_type {
+        Some(return_type) => return_type.to_string(),
+        None => "void".to_string(),
+    };
+    let parameters: Vec<String> = info
+        .parameters
+        .iter()
+        .map(|(var_type, name)| format!("{} {}", var_type, name))
+        .collect();
+    format!(
+        "{} {} {}.{}({})",
+        info.kind,
+        return_type,
+        class,
+        name,
+        parameters.join(", ")
+    )
+}
+
+// Whether a position is in a name or just after it, where an editor's
+// cursor is after typing it.
+fn contains(span: Span, position: Position) -> bool {
+    span.start <= position && position <= span.end
+}
+
+// The subroutine a position is in.
+fn subroutine_at(class: &Class, position: Position) -> Option<&Subroutine> {
+    class.subroutines.iter().find(|subroutine| {
+        let span = Span {
+            start: subroutine.type_span.start,
+            end: subroutine.end.end,
+        };
+        contains(span, position)
+    })
+}
+
+// The variables in scope at a position: those of the class and, in a
+// subroutine, its own.
+fn scope(class: &Class, position: Position) -> SymbolTable {
+    let mut table = SymbolTable::new();
+    for dec in &class.vars {
+        for name in &dec.names {
+            table.define(&name.name, &dec.var_type, dec.kind.into(), name.span);
+        }
+    }
+    if let Some(subroutine) = subroutine_at(class, position) {
+        table.start_subroutine(subroutine.kind);
+        for parameter in &subroutine.parameters {
+            let name = &parameter.name;
+            table.define(&name.name, &parameter.var_type, Kind::Argument, name.span);
+        }
+        for dec in &subroutine.locals {
+            for name in &dec.names {
+                table.define(&name.name, &dec.var_type, Kind::Var, name.span);
+            }
+        }
+    }
+    table
+}
+
+// The name at a position in a class and what it refers to.
+fn target_at(class: &Class, position: Position) -> Option<Target> {
+    if contains(class.name.span, position) {
+        return Some(Target::Class(class.name.name.clone()));
+    }
+    let finder = Finder {
+        class: &class.name.name,
+        table: scope(class, position),
+        position,
+    };
+    let found = class.vars.iter().find_map(|dec| {
+        finder
+            .class_type(&dec.var_type, dec.type_span)
+            .or_else(|| dec.names.iter().find_map(|name| finder.variable(name)))
+    });
+    if found.is_some() {
+        return found;
+    }
+    let subroutine = subroutine_at(class, position)?;
+    if contains(subroutine.name.span, position) {
+        let name = subroutine.name.name.clone();
+        return Some(Target::Subroutine(class.name.name.clone(), name));
+    }
+    if let Some(return_type) = &subroutine.return_type {
+        if let Some(found) = finder.class_type(return_type, subroutine.type_span) {
+            return Some(found);
+        }
+    }
+    let found = subroutine.parameters.iter().find_map(|parameter| {
+        finder
+            .class_type(&parameter.var_type, parameter.type_span)
+            .or_else(|| finder.variable(&parameter.name))
+    });
+    if found.is_some() {
+        return found;
+    }
+    let found = subroutine.locals.iter().find_map(|dec| {
+        finder
+            .class_type(&dec.var_type, dec.type_span)
+            .or_else(|| dec.names.iter().find_map(|name| finder.variable(name)))
+    });
+    found.or_else(|| finder.statements(&subroutine.statements))
+}
+
+// Looks for the name at a position in the statements of a subroutine.
+struct Finder<'a> {
+    class: &'a str,
+    table: SymbolTable,
+    position: Position,
+}
+
+impl Finder<'_> {
+    fn class_type(&self, var_type: &Type, span: Span) -> Option<Target> {
+        match var_type {
+            Type::Class(class) if contains(span, self.position) => {
+                Some(Target::Class(class.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    fn variable(&self, name: &Name) -> Option<Target> {
+        if !contains(name.span, self.position) {
+            return None;
+        }
+        self.table.get(&name.name).cloned().map(Target::Variable)
+    }
+
+    fn statements(&self, statements: &[Statement]) -> Option<Target> {
+        statements
+            .iter()
+            .find_map(|statement| self.statement(statement))
+    }
+
+    fn statement(&self, statement: &Statement) -> Option<Target> {
+        match statement {
+            Statement::Let {
+                target,
+                index,
+                value,
+                ..
+            } => self
+                .variable(target)
+                .or_else(|| index.as_ref().and_then(|index| self.expression(index)))
+                .or_else(|| self.expression(value)),
+            Statement::If {
+                condition,
+                statements,
+                else_statements,
+                ..
+            } => self
+                .expression(condition)
+                .or_else(|| self.statements(statements))
+                .or_else(|| {
+                    else_statements
+                        .as_ref()
+                        .and_then(|statements| self.statements(statements))
+                }),
+            Statement::While {
+                condition,
+                statements,
+                ..
+            } => self
+                .expression(condition)
+                .or_else(|| self.statements(statements)),
+            Statement::Do { call, .. } => self.call(call),
+            Statement::Return { value, .. } => {
+                value.as_ref().and_then(|value| self.expression(value))
+            }
+            Statement::For {
+                init,
+                condition,
+                update,
+                statements,
+                ..
+            } => init
+                .as_ref()
+                .and_then(|init| self.statement(init))
+                .or_else(|| condition.as_ref().and_then(|c| self.expression(c)))
+                .or_else(|| update.as_ref().and_then(|update| self.statement(update)))
+                .or_else(|| self.statements(statements)),
+            Statement::Break { .. } | Statement::Continue { .. } => None,
+        }
+    }
+
+    fn expression(&self, expression: &Expression) -> Option<Target> {
+        self.term(&expression.term).or_else(|| {
+            expression
+                .rest
+                .iter()
+                .find_map(|(_, _, term)| self.term(term))
+        })
+    }
+
+    fn term(&self, term: &Term) -> Option<Target> {
+        match term {
+            Term::Var(name) => self.variable(name),
+            Term::Index(name, index, _) => self.variable(name).or_else(|| self.expression(index)),
+            Term::Call(call) => self.call(call),
+            Term::Paren(expression, _) => self.expression(expression),
+            Term::Unary(_, term, _) => self.term(term),
+            Term::Int(..) | Term::Str(..) | Term::Keyword(..) => None,
+        }
+    }
+
+    fn call(&self, call: &Call) -> Option<Target> {
+        let receiver = call
+            .receiver
+            .as_ref()
+            .map(|receiver| (receiver, self.table.get(&receiver.name)));
+        match receiver {
+            Some((receiver, symbol)) if contains(receiver.span, self.position) => {
+                return Some(match symbol {
+                    Some(symbol) => Target::Variable(symbol.clone()),
+                    None => Target::Class(receiver.name.clone()),
+                });
+            }
+            _ => {}
+        }
+        if contains(call.name.span, self.position) {
+            let class = match receiver {
+                None => self.class.to_string(),
+                Some((_, Some(symbol))) => match &symbol.var_type {
+                    Type::Class(class) => class.clone(),
+                    _ => return None,
+                },
+                Some((receiver, None)) => receiver.name.clone(),
+            };
+            return Some(Target::Subroutine(class, call.name.name.clone()));
+        }
+        call.arguments
+            .iter()
+            .find_map(|argument| self.expression(argument))
+    }
+}
@@ -1,41 +1,154 @@
+pub mod analyzer;
+pub mod ast;
+pub mod codegen;
 pub mod compilation_engine;
+pub mod formatter;
+pub mod linter;
+pub mod lsp;
+pub mod os;
+pub mod parser;
+pub mod symbol_table;
 pub mod tkzr;
 
-use std::path::{Path, PathBuf};
+use analyzer::Diagnostic;
+use ast::{Class, Grouping};
+use compilation_engine::CompilationEngine;
+use linter::{Linter, Warning};
+use std::ffi::OsStr;
 use std::fs;
 use std::io;
-use std::ffi::OsStr;
-use compilation_engine::CompilationEngine;
+use std::path::{Path, PathBuf};
+use tkzr::Dialect;
 
 pub struct Compiler {
     files: Vec<PathBuf>,
+    dialect: Dialect,
+    grouping: Grouping,
 }
 
 impl Compiler {
     pub fn new(path: &Path) -> Self {
-        if path.is_file() {
-            let mut files = Vec::new();
-            files.push(path.to_path_buf());
-            Compiler { files }
+        let files = if path.is_file() {
+            vec![path.to_path_buf()]
         } else {
             let files = fs::read_dir(path)
                 .unwrap()
                 .map(|res| res.map(|e| e.path()))
                 .collect::<Result<Vec<_>, io::Error>>()
                 .unwrap();
-            let files = files
+            files
                 .into_iter()
                 .filter(|x| x.extension() == Some(OsStr::new("jack")))
-                .collect();
-            Compiler { files }
+                .collect()
+        };
+        Compiler {
+            files,
+            dialect: Dialect::default(),
+            grouping: Grouping::default(),
         }
     }
 
+    /// Reads the files as `dialect` rather than standard Jack. The XML of
+    /// `compile` is only written for standard Jack.
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Groups the operators of expressions by `grouping` rather than left to
+    /// right, in the VM code and for the analyzer.
+    pub fn with_grouping(mut self, grouping: Grouping) -> Self {
+        self.grouping = grouping;
+        self
+    }
+
+    /// The `.jack` files to compile.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Writes the tokens of every file as `XxxT.xml` in `out_dir`. Not next
+    /// to the source, where the course keeps parse trees under those names.
+    pub fn write_tokens(&self, out_dir: &Path) {
+        for file in &self.files {
+            let source = fs::read_to_string(file).unwrap();
+            let (tokens, errors) = tkzr::tokenize_dialect(&source, self.dialect);
+            if let Some(e) = errors.first() {
+                panic!("{}:{}", file.display(), e);
+            }
+            let name = format!("{}T.xml", file.file_stem().unwrap().to_string_lossy());
+            fs::write(out_dir.join(name), tkzr::to_xml(&tokens)).unwrap();
+        }
+    }
+
+    /// Parses every file and checks the program they make up, returns the
+    /// syntax errors and what the analyzer finds, type errors too if
+    /// `strict`.
+    pub fn analyze(&self, strict: bool) -> Vec<Diagnostic> {
+        let (program, mut errors) = self.parse();
+        errors.extend(analyzer::analyze_grouped(&program, strict, self.grouping));
+        errors
+    }
+
+    /// Compiles every file to VM code in `Xxx.vm` next to it, unless there
+    /// are errors, which are returned instead.
+    pub fn generate(&self) -> Vec<Diagnostic> {
+        let (program, mut errors) = self.parse();
+        errors.extend(analyzer::analyze_grouped(&program, false, self.grouping));
+        if !errors.is_empty() {
+            return errors;
+        }
+        for (file, class) in &program {
+            fs::write(
+                Path::new(file).with_extension("vm"),
+                codegen::generate(class, self.grouping),
+            )
+            .unwrap();
+        }
+        Vec::new()
+    }
+
+    // The classes of the files that parse, and the syntax errors of the
+    // others.
+    fn parse(&self) -> (Vec<(String, Class)>, Vec<Diagnostic>) {
+        let mut program = Vec::new();
+        let mut errors = Vec::new();
+        for file in &self.files {
+            let source = fs::read_to_string(file).unwrap();
+            let name = file.display().to_string();
+            match parser::parse_dialect(&source, self.dialect) {
+                Ok(class) => program.push((name, class)),
+                Err(e) => errors.extend(e.into_iter().map(|e| Diagnostic {
+                    file: name.clone(),
+                    span: e.span,
+                    message: e.message,
+                })),
+            }
+        }
+        (program, errors)
+    }
+
+    /// Lints the files that parse, the others are left to `analyze`.
+    pub fn lint(&self, linter: &Linter) -> Vec<Warning> {
+        let program: Vec<_> = self
+            .files
+            .iter()
+            .filter_map(|file| {
+                let source = fs::read_to_string(file).unwrap();
+                let class = parser::parse_dialect(&source, self.dialect).ok()?;
+                Some((file.display().to_string(), class))
+            })
+            .collect();
+        linter.lint(&program)
+    }
+
     pub fn compile(&self) {
         for file in self.files.clone() {
             println!("compiling {:?}", file);
             let mut engine = CompilationEngine::new(&file);
-            engine.compile_class();
+            for e in engine.compile_class() {
+                eprintln!("{}: {}", file.display(), e);
+            }
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,783 @@
+use super::ast::*;
+use super::tkzr::{self, Dialect, KeyWord, LexError, Position, Span, Token, TokenType};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub span: Span,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}", self.message, self.span)
+    }
+}
+
+impl From<LexError> for ParseError {
+    fn from(e: LexError) -> Self {
+        ParseError {
+            span: e.span,
+            message: e.message,
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, ParseError>;
+
+// How deeply blocks and expressions may nest, so that no input can run the
+// parser out of stack.
+const MAX_DEPTH: usize = 64;
+
+/// Tokenizes and parses the source of one class, returns every syntax error
+/// if there are any.
+pub fn parse(source: &str) -> std::result::Result<Class, Vec<ParseError>> {
+    parse_dialect(source, Dialect::Standard)
+}
+
+/// Like `parse`, in the given dialect.
+pub fn parse_dialect(
+    source: &str,
+    dialect: Dialect,
+) -> std::result::Result<Class, Vec<ParseError>> {
+    let (class, errors) = parse_partial_dialect(source, dialect);
+    match errors.is_empty() {
+        true => Ok(class),
+        false => Err(errors),
+    }
+}
+
+/// Parses as much of a class as there is, recovering from syntax errors,
+/// and returns it with the errors found, in source order.
+pub fn parse_partial(source: &str) -> (Class, Vec<ParseError>) {
+    parse_partial_dialect(source, Dialect::Standard)
+}
+
+/// Like `parse_partial`, in the given dialect.
+pub fn parse_partial_dialect(source: &str, dialect: Dialect) -> (Class, Vec<ParseError>) {
+    let (tokens, lex_errors) = tkzr::tokenize_dialect(source, dialect);
+    let mut parser = Parser::with_dialect(tokens, dialect);
+    let class = parser.parse_class();
+    let mut errors: Vec<ParseError> = lex_errors.into_iter().map(ParseError::from).collect();
+    errors.extend(parser.errors);
+    errors.sort_by_key(|e| e.span.start);
+    (class, errors)
+}
+
+/// A recursive descent parser from tokens to the AST of a class.
+///
+/// After a syntax error it records the error and skips ahead to the next
+/// `;`, `}`, statement or declaration, so that one mistake is reported
+/// once and the rest of the class is still parsed.
+pub struct Parser {
+    tokens: Vec<Token>,
+    next: usize,
+    depth: usize,
+    errors: Vec<ParseError>,
+    dialect: Dialect,
+}
+
+impl Parser {
+    pub fn new(tokens: Vec<Token>) -> Self {
+        Self::with_dialect(tokens, Dialect::Standard)
+    }
+
+    /// A parser for `dialect`, whose tokens must come from the same one.
+    pub fn with_dialect(tokens: Vec<Token>, dialect: Dialect) -> Self {
+        Parser {
+            tokens,
+            next: 0,
+            depth: 0,
+            errors: Vec::new(),
+            dialect,
+        }
+    }
+
+    /// The syntax errors found so far.
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next)
+    }
+
+    // The span of the next token or, at the end, an empty span after the
+    // last one.
+    fn span(&self) -> Span {
+        match self.peek() {
+            Some(token) => token.span,
+            None => {
+                let end = self
+                    .tokens
+                    .last()
+                    .map_or(Position { line: 1, column: 1 }, |token| token.span.end);
+                Span { start: end, end }
+            }
+        }
+    }
+
+    fn expected(&self, expected: &str) -> ParseError {
+        let found = match self.peek() {
+            Some(token) => format!("'{}'", token.text),
+            None => "end of file".to_string(),
+        };
+        ParseError {
+            span: self.span(),
+            message: format!("expected {}, found {}", expected, found),
+        }
+    }
+
+    fn error<T>(&self, expected: &str) -> Result<T> {
+        Err(self.expected(expected))
+    }
+
+    // Records an error, unless it repeats the last one, as when a missing
+    // `}` is noticed by every enclosing block.
+    fn report(&mut self, error: ParseError) {
+        if self.errors.last().map(|e| e.span) != Some(error.span) {
+            self.errors.push(error);
+        }
+    }
+
+    // Records `error` and skips to where parsing can resume: past a `;`, or
+    // up to a `}`, a statement or a declaration, not counting those inside
+    // braces skipped on the way. Skips at least one token if none was
+    // consumed since `start`, so that the caller cannot loop.
+    fn recover(&mut self, error: ParseError, start: usize) {
+        self.report(error);
+        if self.next == start && self.peek().is_some() {
+            self.next += 1;
+        }
+        let mut depth = 0;
+        while let Some(token) = self.peek() {
+            match &token.token_type {
+                TokenType::Symbol(s) if s == "{" => depth += 1,
+                TokenType::Symbol(s) if s == "}" => {
+                    if depth == 0 {
+                        return;
+                    }
+                    depth -= 1;
+                }
+                TokenType::Symbol(s) if s == ";" && depth == 0 => {
+                    self.next += 1;
+                    return;
+                }
+                TokenType::KeyWord(
+                    KeyWord::Let
+                    | KeyWord::If
+                    | KeyWord::While
+                    | KeyWord::Do
+                    | KeyWord::Return
+                    | KeyWord::For
+                    | KeyWord::Break
+                    | KeyWord::Continue
+                    | KeyWord::Var
+                    | KeyWord::Static
+                    | KeyWord::Field
+                    | KeyWord::Const
+                    | KeyWord::Constructor
+                    | KeyWord::Function
+                    | KeyWord::Method,
+                ) if depth == 0 => return,
+                _ => {}
+            }
+            self.next += 1;
+        }
+    }
+
+    // Whether the next token starts a class-level declaration.
+    fn at_declaration(&self) -> bool {
+        matches!(
+            self.peek().map(|token| &token.token_type),
+            Some(TokenType::KeyWord(KeyWord::Static))
+                | Some(TokenType::KeyWord(KeyWord::Field))
+                | Some(TokenType::KeyWord(KeyWord::Const))
+                | Some(TokenType::KeyWord(KeyWord::Constructor))
+                | Some(TokenType::KeyWord(KeyWord::Function))
+                | Some(TokenType::KeyWord(KeyWord::Method))
+        )
+    }
+
+    // Counts a level of nesting, fails past `MAX_DEPTH`.
+    fn enter(&mut self) -> Result<()> {
+        if self.depth >= MAX_DEPTH {
+            return Err(ParseError {
+                span: self.span(),
+                message: "code is nested too deeply".to_string(),
+            });
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.next].clone();
+        self.next += 1;
+        token
+    }
+
+    fn is_symbol(&self, symbol: &str) -> bool {
+        matches!(self.peek(), Some(Token { token_type: TokenType::Symbol(s), .. }) if s == symbol)
+    }
+
+    fn is_key_word(&self, key_word: KeyWord) -> bool {
+        matches!(self.peek(), Some(Token { token_type: TokenType::KeyWord(k), .. }) if *k == key_word)
+    }
+
+    fn symbol(&mut self, symbol: &str) -> Result<Span> {
+        if !self.is_symbol(symbol) {
+            return self.error(&format!("'{}'", symbol));
+        }
+        Ok(self.advance().span)
+    }
+
+    fn key_word(&mut self, key_word: KeyWord) -> Result<Span> {
+        if !self.is_key_word(key_word) {
+            return self.error(&format!("'{}'", key_word));
+        }
+        Ok(self.advance().span)
+    }
+
+    fn identifier(&mut self, what: &str) -> Result<Name> {
+        match self.peek() {
+            Some(Token {
+                token_type: TokenType::Identifier(name),
+                span,
+                ..
+            }) => {
+                let name = Name {
+                    name: name.clone(),
+                    span: *span,
+                };
+                self.next += 1;
+                Ok(name)
+            }
+            _ => self.error(what),
+        }
+    }
+
+    // 'class' className '{' classVarDec* subroutineDec* '}'
+    pub fn parse_class(&mut self) -> Class {
+        let mut class = Class {
+            name: Name {
+                name: String::new(),
+                span: self.span(),
+            },
+            vars: Vec::new(),
+            constants: Vec::new(),
+            subroutines: Vec::new(),
+        };
+        let header = self
+            .key_word(KeyWord::Class)
+            .and_then(|_| self.identifier("a class name"))
+            .and_then(|name| {
+                class.name = name;
+                self.symbol("{")
+            });
+        if let Err(e) = header {
+            self.report(e);
+            while self.peek().is_some() && !self.at_declaration() {
+                if self.advance().text == "{" {
+                    break;
+                }
+            }
+        }
+        loop {
+            let start = self.next;
+            let token_type = self.peek().map(|token| token.token_type.clone());
+            match token_type {
+                Some(TokenType::KeyWord(KeyWord::Static))
+                | Some(TokenType::KeyWord(KeyWord::Field)) => {
+                    if !class.subroutines.is_empty() {
+                        let e = self.expected("a subroutine declaration or '}'");
+                        self.recover(e, start);
+                        continue;
+                    }
+                    match self.class_var_dec() {
+                        Ok(dec) => class.vars.push(dec),
+                        Err(e) => self.recover(e, start),
+                    }
+                }
+                Some(TokenType::KeyWord(KeyWord::Const)) => {
+                    if !class.subroutines.is_empty() {
+                        let e = self.expected("a subroutine declaration or '}'");
+                        self.recover(e, start);
+                        continue;
+                    }
+                    match self.constant_dec() {
+                        Ok(constant) => class.constants.push(constant),
+                        Err(e) => self.recover(e, start),
+                    }
+                }
+                Some(TokenType::KeyWord(KeyWord::Constructor))
+                | Some(TokenType::KeyWord(KeyWord::Function))
+                | Some(TokenType::KeyWord(KeyWord::Method)) => match self.subroutine_dec() {
+                    Ok(subroutine) => class.subroutines.push(subroutine),
+                    Err(e) => self.recover(e, start),
+                },
+                Some(TokenType::Symbol(s)) if s == "}" => {
+                    self.advance();
+                    if self.peek().is_some() {
+                        let e = self.expected("end of file after the class");
+                        self.report(e);
+                    }
+                    return class;
+                }
+                None => {
+                    let e = self.expected("'}'");
+                    self.report(e);
+                    return class;
+                }
+                Some(_) => {
+                    let e = self.expected("a subroutine declaration or '}'");
+                    self.recover(e, start);
+                }
+            }
+        }
+    }
+
+    // ('static' | 'field') type varName (',' varName)* ';'
+    fn class_var_dec(&mut self) -> Result<ClassVarDec> {
+        let kind = match self.advance().token_type {
+            TokenType::KeyWord(KeyWord::Static) => ClassVarKind::Static,
+            _ => ClassVarKind::Field,
+        };
+        let (var_type, type_span) = self.var_type()?;
+        let names = self.names()?;
+        Ok(ClassVarDec {
+            kind,
+            var_type,
+            type_span,
+            names,
+        })
+    }
+
+    // 'const' type constName '=' expression ';'
+    fn constant_dec(&mut self) -> Result<Constant> {
+        self.advance();
+        let (var_type, type_span) = self.var_type()?;
+        let name = self.identifier("a constant name")?;
+        self.symbol("=")?;
+        let value = self.expression()?;
+        self.symbol(";")?;
+        Ok(Constant {
+            var_type,
+            type_span,
+            name,
+            value,
+        })
+    }
+
+    // varName (',' varName)* ';'
+    fn names(&mut self) -> Result<Vec<Name>> {
+        let mut names = vec![self.identifier("a variable name")?];
+        while self.is_symbol(",") {
+            self.advance();
+            names.push(self.identifier("a variable name")?);
+        }
+        self.symbol(";")?;
+        Ok(names)
+    }
+
+    // 'int' | 'char' | 'boolean' | className
+    fn var_type(&mut self) -> Result<(Type, Span)> {
+        let var_type = match self.peek().map(|token| &token.token_type) {
+            Some(TokenType::KeyWord(KeyWord::Int)) => Type::Int,
+            Some(TokenType::KeyWord(KeyWord::Char)) => Type::Char,
+            Some(TokenType::KeyWord(KeyWord::Boolean)) => Type::Boolean,
+            Some(TokenType::Identifier(name)) => Type::Class(name.clone()),
+            _ => return self.error("a type"),
+        };
+        Ok((var_type, self.advance().span))
+    }
+
+    // ('constructor' | 'function' | 'method') ('void' | type) subroutineName
+    // '(' parameterList ')' subroutineBody
+    fn subroutine_dec(&mut self) -> Result<Subroutine> {
+        let kind = match self.advance().token_type {
+            TokenType::KeyWord(KeyWord::Constructor) => SubroutineKind::Constructor,
+            TokenType::KeyWord(KeyWord::Function) => SubroutineKind::Function,
+            _ => SubroutineKind::Method,
+        };
+        let (return_type, type_span) = if self.is_key_word(KeyWord::Void) {
+            (None, self.advance().span)
+        } else {
+            let (var_type, span) = self.var_type()?;
+            (Some(var_type), span)
+        };
+        let name = self.identifier("a subroutine name")?;
+        self.symbol("(")?;
+        let mut parameters = Vec::new();
+        if !self.is_symbol(")") {
+            loop {
+                let (var_type, type_span) = self.var_type()?;
+                let name = self.identifier("a parameter name")?;
+                parameters.push(Parameter {
+                    var_type,
+                    type_span,
+                    name,
+                });
+                if !self.is_symbol(",") {
+                    break;
+                }
+                self.advance();
+            }
+        }
+        self.symbol(")")?;
+        self.symbol("{")?;
+        let mut locals = Vec::new();
+        while self.is_key_word(KeyWord::Var) {
+            let start = self.next;
+            self.advance();
+            let dec = self.var_type().and_then(|(var_type, type_span)| {
+                Ok(VarDec {
+                    var_type,
+                    type_span,
+                    names: self.names()?,
+                })
+            });
+            match dec {
+                Ok(dec) => locals.push(dec),
+                Err(e) => self.recover(e, start),
+            }
+        }
+        let statements = self.statements();
+        let end = self.span();
+        if self.is_symbol("}") {
+            self.advance();
+        } else {
+            let e = self.expected("a statement or '}'");
+            self.report(e);
+        }
+        Ok(Subroutine {
+            kind,
+            return_type,
+            type_span,
+            name,
+            parameters,
+            locals,
+            statements,
+            end,
+        })
+    }
+
+    // Parses statements up to a `}`, a declaration or the end, recovering
+    // from errors in between.
+    fn statements(&mut self) -> Vec<Statement> {
+        let mut statements = Vec::new();
+        loop {
+            let start = self.next;
+            let statement = match self.peek().map(|token| &token.token_type) {
+                Some(TokenType::KeyWord(KeyWord::Let)) => self.let_statement(),
+                Some(TokenType::KeyWord(KeyWord::If)) => self.if_statement(),
+                Some(TokenType::KeyWord(KeyWord::While)) => self.while_statement(),
+                Some(TokenType::KeyWord(KeyWord::Do)) => self.do_statement(),
+                Some(TokenType::KeyWord(KeyWord::Return)) => self.return_statement(),
+                Some(TokenType::KeyWord(KeyWord::For)) => self.for_statement(),
+                Some(TokenType::KeyWord(KeyWord::Break)) => {
+                    let span = self.advance().span;
+                    self.symbol(";").map(|_| Statement::Break { span })
+                }
+                Some(TokenType::KeyWord(KeyWord::Continue)) => {
+                    let span = self.advance().span;
+                    self.symbol(";").map(|_| Statement::Continue { span })
+                }
+                Some(TokenType::Symbol(s)) if s == "}" => return statements,
+                None => return statements,
+                _ if self.at_declaration() => return statements,
+                _ => self.error("a statement or '}'"),
+            };
+            match statement {
+                Ok(statement) => statements.push(statement),
+                Err(e) => self.recover(e, start),
+            }
+        }
+    }
+
+    // '{' statements '}'
+    fn block(&mut self) -> Result<Vec<Statement>> {
+        self.symbol("{")?;
+        self.enter()?;
+        let statements = self.statements();
+        self.depth -= 1;
+        if !self.is_symbol("}") {
+            return self.error("a statement or '}'");
+        }
+        self.advance();
+        Ok(statements)
+    }
+
+    // 'let' assignment ';'
+    fn let_statement(&mut self) -> Result<Statement> {
+        self.advance();
+        let statement = self.assignment()?;
+        self.symbol(";")?;
+        Ok(statement)
+    }
+
+    // varName ('[' expression ']')? ('=' | '+=' | '-=' | ...) expression,
+    // the compound operators being the extended dialect's.
+    fn assignment(&mut self) -> Result<Statement> {
+        let target = self.identifier("a variable name")?;
+        let index = if self.is_symbol("[") {
+            self.advance();
+            let index = self.expression()?;
+            self.symbol("]")?;
+            Some(index)
+        } else {
+            None
+        };
+        let op = match self.peek().map(|token| &token.token_type) {
+            Some(TokenType::Symbol(s)) if s == "=" => None,
+            Some(TokenType::Symbol(s)) if s.len() == 2 && s.ends_with('=') => {
+                Op::from_symbol(&s[..1])
+            }
+            _ => return self.error("'='"),
+        };
+        self.advance();
+        let value = self.expression()?;
+        Ok(Statement::Let {
+            target,
+            index,
+            op,
+            value,
+        })
+    }
+
+    // 'if' '(' expression ')' '{' statements '}'
+    // ('else' 'if' '(' expression ')' '{' statements '}')*
+    // ('else' '{' statements '}')?
+    //
+    // `else if` is the extended dialect's. The chain is read in a loop and
+    // nested from the last `if` back, so that it can be any length.
+    fn if_statement(&mut self) -> Result<Statement> {
+        let mut branches = Vec::new();
+        let mut else_statements = None;
+        loop {
+            let span = self.advance().span;
+            self.symbol("(")?;
+            let condition = self.expression()?;
+            self.symbol(")")?;
+            branches.push((span, condition, self.block()?));
+            if !self.is_key_word(KeyWord::Else) {
+                break;
+            }
+            self.advance();
+            if self.dialect == Dialect::Standard || !self.is_key_word(KeyWord::If) {
+                else_statements = Some(self.block()?);
+                break;
+            }
+        }
+        let mut statement = None;
+        for (span, condition, statements) in branches.into_iter().rev() {
+            let else_statements = match statement {
+                Some(statement) => Some(vec![statement]),
+                None => else_statements.take(),
+            };
+            statement = Some(Statement::If {
+                span,
+                condition,
+                statements,
+                else_statements,
+            });
+        }
+        Ok(statement.unwrap())
+    }
+
+    // 'for' '(' assignment? ';' expression? ';' assignment? ')'
+    // '{' statements '}'
+    fn for_statement(&mut self) -> Result<Statement> {
+        let span = self.advance().span;
+        self.symbol("(")?;
+        let init = match self.is_symbol(";") {
+            true => None,
+            false => Some(Box::new(self.assignment()?)),
+        };
+        self.symbol(";")?;
+        let condition = match self.is_symbol(";") {
+            true => None,
+            false => Some(self.expression()?),
+        };
+        self.symbol(";")?;
+        let update = match self.is_symbol(")") {
+            true => None,
+            false => Some(Box::new(self.assignment()?)),
+        };
+        self.symbol(")")?;
+        let statements = self.block()?;
+        Ok(Statement::For {
+            span,
+            init,
+            condition,
+            update,
+            statements,
+        })
+    }
+
+    // 'while' '(' expression ')' '{' statements '}'
+    fn while_statement(&mut self) -> Result<Statement> {
+        let span = self.advance().span;
+        self.symbol("(")?;
+        let condition = self.expression()?;
+        self.symbol(")")?;
+        let statements = self.block()?;
+        Ok(Statement::While {
+            span,
+            condition,
+            statements,
+        })
+    }
+
+    // 'do' subroutineCall ';'
+    fn do_statement(&mut self) -> Result<Statement> {
+        let span = self.advance().span;
+        let name = self.identifier("a subroutine call")?;
+        let call = self.call(name)?;
+        self.symbol(";")?;
+        Ok(Statement::Do { span, call })
+    }
+
+    // 'return' expression? ';'
+    fn return_statement(&mut self) -> Result<Statement> {
+        let span = self.advance().span;
+        let value = if self.is_symbol(";") {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.symbol(";")?;
+        Ok(Statement::Return { span, value })
+    }
+
+    // term (op term)*
+    fn expression(&mut self) -> Result<Expression> {
+        let term = self.term()?;
+        let mut rest = Vec::new();
+        while let Some(Token {
+            token_type: TokenType::Symbol(symbol),
+            ..
+        }) = self.peek()
+        {
+            let op = match Op::from_symbol(symbol) {
+                Some(op) => op,
+                None => break,
+            };
+            let span = self.advance().span;
+            rest.push((op, span, self.term()?));
+        }
+        Ok(Expression { term, rest })
+    }
+
+    // integerConstant | stringConstant | keywordConstant | varName |
+    // varName '[' expression ']' | subroutineCall | '(' expression ')' |
+    // unaryOp term
+    fn term(&mut self) -> Result<Term> {
+        self.enter()?;
+        let term = self.nested_term();
+        self.depth -= 1;
+        term
+    }
+
+    fn nested_term(&mut self) -> Result<Term> {
+        let token = match self.peek() {
+            Some(token) => token.clone(),
+            None => return self.error("an expression"),
+        };
+        let term = match token.token_type {
+            TokenType::IntConst(value) => {
+                self.advance();
+                Term::Int(value, token.span)
+            }
+            TokenType::StringConst(value) => {
+                self.advance();
+                Term::Str(value, token.span)
+            }
+            TokenType::KeyWord(key_word) => {
+                let constant = match key_word {
+                    KeyWord::True => KeywordConst::True,
+                    KeyWord::False => KeywordConst::False,
+                    KeyWord::Null => KeywordConst::Null,
+                    KeyWord::This => KeywordConst::This,
+                    _ => return self.error("an expression"),
+                };
+                self.advance();
+                Term::Keyword(constant, token.span)
+            }
+            TokenType::Identifier(_) => {
+                let name = self.identifier("a name")?;
+                match self.peek().map(|token| token.text.as_str()) {
+                    Some("[") => {
+                        self.advance();
+                        let index = self.expression()?;
+                        let end = self.symbol("]")?;
+                        let span = Span {
+                            start: name.span.start,
+                            end: end.end,
+                        };
+                        Term::Index(name, Box::new(index), span)
+                    }
+                    Some("(") | Some(".") => Term::Call(self.call(name)?),
+                    _ => Term::Var(name),
+                }
+            }
+            TokenType::Symbol(symbol) => match symbol.as_str() {
+                "(" => {
+                    self.advance();
+                    let expression = self.expression()?;
+                    let end = self.symbol(")")?;
+                    let span = Span {
+                        start: token.span.start,
+                        end: end.end,
+                    };
+                    Term::Paren(Box::new(expression), span)
+                }
+                "-" | "~" => {
+                    self.advance();
+                    let op = match symbol.as_str() {
+                        "-" => UnaryOp::Neg,
+                        _ => UnaryOp::Not,
+                    };
+                    let term = self.term()?;
+                    let span = Span {
+                        start: token.span.start,
+                        end: term.span().end,
+                    };
+                    Term::Unary(op, Box::new(term), span)
+                }
+                _ => return self.error("an expression"),
+            },
+        };
+        Ok(term)
+    }
+
+    // The rest of a subroutine call after its first name.
+    fn call(&mut self, first: Name) -> Result<Call> {
+        let (receiver, name) = if self.is_symbol(".") {
+            self.advance();
+            (Some(first), self.identifier("a subroutine name")?)
+        } else {
+            (None, first)
+        };
+        self.symbol("(")?;
+        let mut arguments = Vec::new();
+        if !self.is_symbol(")") {
+            arguments.push(self.expression()?);
+            while self.is_symbol(",") {
+                self.advance();
+                arguments.push(self.expression()?);
+            }
+        }
+        let end = self.symbol(")")?;
+        let start = receiver.as_ref().unwrap_or(&name).span.start;
+        Ok(Call {
+            receiver,
+            name,
+            arguments,
+            span: Span {
+                start,
+                end: end.end,
+            },
+        })
+    }
+}
@@ -0,0 +1,205 @@
+use super::ast::{Class, ClassVarKind, SubroutineKind, Type};
+use super::tkzr::Span;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Static,
+    Field,
+    Argument,
+    Var,
+}
+
+impl Kind {
+    /// The VM segment variables of this kind live in.
+    pub fn segment(self) -> &'static str {
+        match self {
+            Kind::Static => "static",
+            Kind::Field => "this",
+            Kind::Argument => "argument",
+            Kind::Var => "local",
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Kind::Static => write!(f, "static"),
+            Kind::Field => write!(f, "field"),
+            Kind::Argument => write!(f, "argument"),
+            Kind::Var => write!(f, "var"),
+        }
+    }
+}
+
+impl From<ClassVarKind> for Kind {
+    fn from(kind: ClassVarKind) -> Self {
+        match kind {
+            ClassVarKind::Static => Kind::Static,
+            ClassVarKind::Field => Kind::Field,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub var_type: Type,
+    pub kind: Kind,
+    pub index: usize,
+    /// Where it is declared.
+    pub span: Span,
+}
+
+/// The variables in scope in a subroutine: the statics and fields of its
+/// class and its own arguments and locals, each numbered within its kind.
+#[derive(Default)]
+pub struct SymbolTable {
+    class_scope: HashMap<String, Symbol>,
+    subroutine_scope: HashMap<String, Symbol>,
+    // Indexed by `Kind`.
+    counts: [usize; 4],
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Starts a new subroutine scope. The arguments of a method start at 1,
+    /// after `this`.
+    pub fn start_subroutine(&mut self, kind: SubroutineKind) {
+        self.subroutine_scope.clear();
+        self.counts[Kind::Argument as usize] = match kind {
+            SubroutineKind::Method => 1,
+            _ => 0,
+        };
+        self.counts[Kind::Var as usize] = 0;
+    }
+
+    /// Defines a variable, unless its name is taken in the same scope, in
+    /// which case the earlier definition is returned.
+    pub fn define(
+        &mut self,
+        name: &str,
+        var_type: &Type,
+        kind: Kind,
+        span: Span,
+    ) -> Option<&Symbol> {
+        if self.scope(kind).contains_key(name) {
+            return self.scope(kind).get(name);
+        }
+        let index = self.counts[kind as usize];
+        self.counts[kind as usize] += 1;
+        let symbol = Symbol {
+            name: name.to_string(),
+            var_type: var_type.clone(),
+            kind,
+            index,
+            span,
+        };
+        self.scope(kind).insert(name.to_string(), symbol);
+        None
+    }
+
+    fn scope(&mut self, kind: Kind) -> &mut HashMap<String, Symbol> {
+        match kind {
+            Kind::Static | Kind::Field => &mut self.class_scope,
+            Kind::Argument | Kind::Var => &mut self.subroutine_scope,
+        }
+    }
+
+    pub fn var_count(&self, kind: Kind) -> usize {
+        self.counts[kind as usize]
+    }
+
+    /// The variable a name refers to, locals and arguments first.
+    pub fn get(&self, name: &str) -> Option<&Symbol> {
+        self.subroutine_scope
+            .get(name)
+            .or_else(|| self.class_scope.get(name))
+    }
+
+    /// Every variable in scope.
+    pub fn symbols(&self) -> impl Iterator<Item = &Symbol> {
+        self.subroutine_scope.values().chain(
+            self.class_scope
+                .values()
+                .filter(move |symbol| !self.subroutine_scope.contains_key(&symbol.name)),
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubroutineInfo {
+    pub kind: SubroutineKind,
+    /// `None` for `void`.
+    pub return_type: Option<Type>,
+    pub parameters: Vec<(Type, String)>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassInfo {
+    /// The file the class is in, empty for the OS.
+    pub file: String,
+    pub span: Span,
+    pub subroutines: HashMap<String, SubroutineInfo>,
+}
+
+/// The classes of a program, its own and the OS's, and their subroutines.
+#[derive(Default)]
+pub struct ClassTable {
+    classes: HashMap<String, ClassInfo>,
+}
+
+impl ClassTable {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Adds a class, unless one of the same name exists, in which case the
+    /// existing one is returned. Only the first of several subroutines of
+    /// the same name is recorded.
+    pub fn add(&mut self, file: &str, class: &Class) -> Option<&ClassInfo> {
+        if self.classes.contains_key(&class.name.name) {
+            return self.classes.get(&class.name.name);
+        }
+        let mut subroutines = HashMap::new();
+        for subroutine in &class.subroutines {
+            subroutines
+                .entry(subroutine.name.name.clone())
+                .or_insert_with(|| SubroutineInfo {
+                    kind: subroutine.kind,
+                    return_type: subroutine.return_type.clone(),
+                    parameters: subroutine
+                        .parameters
+                        .iter()
+                        .map(|p| (p.var_type.clone(), p.name.name.clone()))
+                        .collect(),
+                    span: subroutine.name.span,
+                });
+        }
+        let info = ClassInfo {
+            file: file.to_string(),
+            span: class.name.span,
+            subroutines,
+        };
+        self.classes.insert(class.name.name.clone(), info);
+        None
+    }
+
+    pub fn get(&self, class: &str) -> Option<&ClassInfo> {
+        self.classes.get(class)
+    }
+
+    pub fn subroutine(&self, class: &str, name: &str) -> Option<&SubroutineInfo> {
+        self.classes.get(class)?.subroutines.get(name)
+    }
+
+    pub fn classes(&self) -> impl Iterator<Item = (&String, &ClassInfo)> {
+        self.classes.iter()
+    }
+}
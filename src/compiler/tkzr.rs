@@ -1,11 +1,26 @@
-use std::collections::VecDeque;
 use std::fmt;
-use std::fs::File;
-use std::io::prelude::*;
-use std::io::BufReader;
-use std::path::Path;
+use std::str::FromStr;
 
-#[derive(Debug, PartialEq)]
+// The largest integer constant; Jack has no negative literals.
+const MAX_INT: i32 = 32767;
+
+const SYMBOLS: &str = "{}()[].,;+-*/&|<>=~";
+
+// The operators that take an `=` after them for compound assignment.
+const COMPOUND: &str = "+-*/&|";
+
+/// The language a source is written in. The extended dialect adds `for`
+/// loops, `else if`, `break` and `continue`, character and hex literals,
+/// `const` class constants and compound assignment such as `+=`, all of
+/// which compile to standard VM code.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Dialect {
+    #[default]
+    Standard,
+    Extended,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum KeyWord {
     Class,
     Method,
@@ -28,6 +43,22 @@ pub enum KeyWord {
     False,
     Null,
     This,
+    // The extended dialect's.
+    For,
+    Break,
+    Continue,
+    Const,
+}
+
+impl KeyWord {
+    /// Whether only the extended dialect has it; in standard Jack it is an
+    /// identifier.
+    pub fn is_extended(self) -> bool {
+        matches!(
+            self,
+            KeyWord::For | KeyWord::Break | KeyWord::Continue | KeyWord::Const
+        )
+    }
 }
 
 impl fmt::Display for KeyWord {
@@ -54,11 +85,51 @@ impl fmt::Display for KeyWord {
             KeyWord::False => write!(f, "false"),
             KeyWord::Null => write!(f, "null"),
             KeyWord::This => write!(f, "this"),
+            KeyWord::For => write!(f, "for"),
+            KeyWord::Break => write!(f, "break"),
+            KeyWord::Continue => write!(f, "continue"),
+            KeyWord::Const => write!(f, "const"),
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
+impl FromStr for KeyWord {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let key_word = match s {
+            "class" => KeyWord::Class,
+            "constructor" => KeyWord::Constructor,
+            "function" => KeyWord::Function,
+            "method" => KeyWord::Method,
+            "field" => KeyWord::Field,
+            "static" => KeyWord::Static,
+            "var" => KeyWord::Var,
+            "int" => KeyWord::Int,
+            "char" => KeyWord::Char,
+            "boolean" => KeyWord::Boolean,
+            "void" => KeyWord::Void,
+            "true" => KeyWord::True,
+            "false" => KeyWord::False,
+            "null" => KeyWord::Null,
+            "this" => KeyWord::This,
+            "let" => KeyWord::Let,
+            "do" => KeyWord::Do,
+            "if" => KeyWord::If,
+            "else" => KeyWord::Else,
+            "while" => KeyWord::While,
+            "return" => KeyWord::Return,
+            "for" => KeyWord::For,
+            "break" => KeyWord::Break,
+            "continue" => KeyWord::Continue,
+            "const" => KeyWord::Const,
+            _ => return Err(()),
+        };
+        Ok(key_word)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum TokenType {
     KeyWord(KeyWord),
     Symbol(String),
@@ -67,208 +138,379 @@ pub enum TokenType {
     StringConst(String),
 }
 
-pub struct Tokenizer {
-    pub current_token: String,
-    tokens: VecDeque<String>,
+/// A position in a source file, both counted from 1. Columns count
+/// characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
 }
 
-impl Tokenizer {
-    pub fn new(path: &Path) -> Self {
-        let f = File::open(path).unwrap();
-        let mut reader = BufReader::new(f);
-        let tokens = Self::get_all_tokens(&mut reader);
-        Tokenizer {
-            tokens,
-            current_token: String::new(),
-        }
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
     }
+}
+
+/// Where a token is: from `start` up to, but not including, `end`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
 
-    pub fn has_more_commands(&self) -> bool {
-        !self.tokens.is_empty()
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.start)
     }
+}
 
-    fn get_all_tokens(reader: &mut BufReader<File>) -> VecDeque<String> {
-        let mut commenting = false;
-        let mut tokens = VecDeque::new();
-        loop {
-            let mut new_line = String::new();
-            let len = reader.read_line(&mut new_line).unwrap();
-            if len == 0 {
-                break;
-            }
-            new_line = new_line.trim().to_string();
-            if new_line.starts_with("/*") {
-                if !new_line.ends_with("*/") {
-                    commenting = true;
-                }
-                continue;
-            }
-            if new_line.ends_with("*/") {
-                commenting = false;
-                continue;
-            }
-            if commenting {
-                continue;
-            }
-            new_line = match new_line.find("//") {
-                Some(size) => new_line[..size].to_string(),
-                None => new_line,
-            };
-            new_line = new_line.trim().to_string();
-            if new_line.len() == 0 {
-                continue;
-            }
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    /// Symbols are kept as written, e.g. `<`, not `&lt;`.
+    pub token_type: TokenType,
+    /// The token as written, string constants with their quotes.
+    pub text: String,
+    pub span: Span,
+}
 
-            tokens.extend(Self::get_tokens_from_one_line(&new_line));
-        }
-        tokens
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexError {
+    pub span: Span,
+    pub message: String,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.span, self.message)
     }
+}
 
-    fn get_tokens_from_one_line(line: &str) -> Vec<String> {
-        let mut res = Vec::new();
-        let mut last = 0;
-        for (index, matched) in line.match_indices(|c: char| !c.is_alphanumeric()) {
-            if last != index {
-                res.push(&line[last..index]);
-            }
-            res.push(matched);
-            last = index + matched.len();
-        }
-        if last < line.len() {
-            res.push(&line[last..]);
-        }
-        let res = res
-            .iter()
-            .map(|x| x.to_string())
-            .filter(|x| x.trim().len() != 0)
-            .collect::<Vec<_>>();
-        res
+/// A `//` or `/* */` comment, as written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    pub text: String,
+    pub span: Span,
+}
+
+struct Scanner<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    position: Position,
+    dialect: Dialect,
+    // The comments skipped so far.
+    comments: Vec<Comment>,
+}
+
+impl<'a> Scanner<'a> {
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
     }
 
-    pub fn advance(&mut self) {
-        let mut current_token = self.tokens.pop_front().unwrap();
-        // There is a bug in the logic of processing strings, but I don't
-        // want to spend time in this place.
-        if current_token == "\"" {
-            let mut next_token = self.tokens.pop_front().unwrap();
-            while next_token != "\"" {
-                current_token.push_str(" ");
-                current_token.push_str(&next_token);
-                next_token = self.tokens.pop_front().unwrap();
-            }
-            current_token.push_str(&next_token);
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.position.line += 1;
+            self.position.column = 1;
+        } else {
+            self.position.column += 1;
         }
-        self.current_token = current_token;
+        Some(c)
     }
 
-    pub fn next_token(&mut self) -> Option<String> {
-        match self.tokens.front() {
-            Some(v) => Some(v.to_owned()),
-            None => None,
-        }
+    // Whether the text after the next character starts with `c`.
+    fn peek_second(&self, c: char) -> bool {
+        let mut chars = self.chars.clone();
+        chars.next();
+        chars.next() == Some(c)
     }
 
-    pub fn token_type(&self) -> TokenType {
-        let key_world = self.key_world();
-        if key_world.is_some() {
-            return TokenType::KeyWord(key_world.unwrap());
+    fn error(&self, start: Position, message: String) -> LexError {
+        LexError {
+            span: Span {
+                start,
+                end: self.position,
+            },
+            message,
         }
+    }
 
-        let symbol = self.symbol();
-        if symbol.is_some() {
-            return TokenType::Symbol(symbol.unwrap());
+    // Skips white space and comments.
+    fn skip(&mut self) -> Result<(), LexError> {
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() {
+                self.next();
+            } else if c == '/' && self.peek_second('/') {
+                let start = self.position;
+                let mut text = String::new();
+                while let Some(c) = self.peek().filter(|c| *c != '\n') {
+                    text.push(c);
+                    self.next();
+                }
+                self.comment(text, start);
+            } else if c == '/' && self.peek_second('*') {
+                let start = self.position;
+                let mut text = String::from("/*");
+                self.next();
+                self.next();
+                loop {
+                    match self.next() {
+                        Some('*') if self.peek() == Some('/') => {
+                            self.next();
+                            text.push_str("*/");
+                            break;
+                        }
+                        Some(c) => text.push(c),
+                        None => return Err(self.error(start, "unterminated comment".to_string())),
+                    }
+                }
+                self.comment(text, start);
+            } else {
+                break;
+            }
         }
+        Ok(())
+    }
 
-        let int_val = self.int_val();
-        if int_val.is_some() {
-            return TokenType::IntConst(int_val.unwrap());
+    // The rest of a character constant after its opening quote: one
+    // printable character, `\'` or `\\`, then a quote. On an error it skips
+    // to the closing quote on the same line, if there is one.
+    fn character(&mut self, text: &mut String) -> Option<char> {
+        let mut value = self.peek().filter(|c| !"'\n\r".contains(*c));
+        if let Some(c) = value {
+            text.push(c);
+            self.next();
+            if c == '\\' {
+                value = self.peek().filter(|c| *c == '\\' || *c == '\'');
+                if let Some(c) = value {
+                    text.push(c);
+                    self.next();
+                }
+            }
         }
-
-        let string_val = self.string_val();
-        if string_val.is_some() {
-            return TokenType::StringConst(string_val.unwrap());
+        match value {
+            Some(c) if self.peek() == Some('\'') && (' '..='~').contains(&c) => {
+                text.push('\'');
+                self.next();
+                Some(c)
+            }
+            _ => {
+                while let Some(c) = self.peek().filter(|c| !"\n\r".contains(*c)) {
+                    text.push(c);
+                    self.next();
+                    if c == '\'' {
+                        break;
+                    }
+                }
+                None
+            }
         }
-        return TokenType::Identifier(self.current_token.clone());
     }
 
-    fn key_world(&self) -> Option<KeyWord> {
-        if self.current_token == "class" {
-            Some(KeyWord::Class)
-        } else if self.current_token == "constructor" {
-            Some(KeyWord::Constructor)
-        } else if self.current_token == "function" {
-            Some(KeyWord::Function)
-        } else if self.current_token == "method" {
-            Some(KeyWord::Method)
-        } else if self.current_token == "field" {
-            Some(KeyWord::Field)
-        } else if self.current_token == "static" {
-            Some(KeyWord::Static)
-        } else if self.current_token == "var" {
-            Some(KeyWord::Var)
-        } else if self.current_token == "int" {
-            Some(KeyWord::Int)
-        } else if self.current_token == "char" {
-            Some(KeyWord::Char)
-        } else if self.current_token == "boolean" {
-            Some(KeyWord::Boolean)
-        } else if self.current_token == "void" {
-            Some(KeyWord::Void)
-        } else if self.current_token == "true" {
-            Some(KeyWord::True)
-        } else if self.current_token == "false" {
-            Some(KeyWord::False)
-        } else if self.current_token == "null" {
-            Some(KeyWord::Null)
-        } else if self.current_token == "this" {
-            Some(KeyWord::This)
-        } else if self.current_token == "let" {
-            Some(KeyWord::Let)
-        } else if self.current_token == "do" {
-            Some(KeyWord::Do)
-        } else if self.current_token == "if" {
-            Some(KeyWord::If)
-        } else if self.current_token == "else" {
-            Some(KeyWord::Else)
-        } else if self.current_token == "while" {
-            Some(KeyWord::While)
-        } else if self.current_token == "return" {
-            Some(KeyWord::Return)
-        } else {
-            None
-        }
+    fn comment(&mut self, text: String, start: Position) {
+        let span = Span {
+            start,
+            end: self.position,
+        };
+        self.comments.push(Comment { text, span });
     }
 
-    fn symbol(&self) -> Option<String> {
-        let symbols = vec![
-            "{", "}", "(", ")", "[", "]", ".", ",", ";", "+", "-", "*", "/", "&", "|", "<", ">",
-            "=", "~",
-        ];
-        if symbols.contains(&self.current_token.as_str()) {
-            match self.current_token.as_str() {
-                "<" => return Some("&lt;".to_string()),
-                ">" => return Some("&gt;".to_string()),
-                "\"" => return Some("&quot;".to_string()),
-                "&" => return Some("&amp;".to_string()),
-                _ => return Some(self.current_token.clone()),
-            }
+    fn token(&mut self) -> Option<Result<Token, LexError>> {
+        if let Err(e) = self.skip() {
+            return Some(Err(e));
         }
-        None
+        let start = self.position;
+        let c = self.next()?;
+        let mut text = c.to_string();
+        let token_type = if c == '"' {
+            loop {
+                match self.peek() {
+                    Some('"') => {
+                        text.push('"');
+                        self.next();
+                        break;
+                    }
+                    Some('\n') | Some('\r') | None => {
+                        return Some(Err(
+                            self.error(start, "unterminated string constant".to_string())
+                        ));
+                    }
+                    Some(c) => {
+                        text.push(c);
+                        self.next();
+                    }
+                }
+            }
+            TokenType::StringConst(text[1..text.len() - 1].to_string())
+        } else if c == '\'' && self.dialect == Dialect::Extended {
+            match self.character(&mut text) {
+                Some(c) => TokenType::IntConst(c as i16),
+                None => {
+                    let message = format!(
+                        "{} is not a valid character constant, which is one printable \
+                         character in single quotes",
+                        text
+                    );
+                    return Some(Err(self.error(start, message)));
+                }
+            }
+        } else if c.is_ascii_digit() {
+            while let Some(c) = self
+                .peek()
+                .filter(|c| c.is_ascii_alphanumeric() || *c == '_')
+            {
+                text.push(c);
+                self.next();
+            }
+            let hex = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X"));
+            if let (Some(digits), Dialect::Extended) = (hex, self.dialect) {
+                let token_type = match u16::from_str_radix(digits, 16) {
+                    Ok(value) => TokenType::IntConst(value as i16),
+                    Err(_)
+                        if !digits.is_empty() && digits.chars().all(|c| c.is_ascii_hexdigit()) =>
+                    {
+                        let message = format!(
+                            "hex constant {} is out of range, the largest is 0xFFFF",
+                            text
+                        );
+                        return Some(Err(self.error(start, message)));
+                    }
+                    Err(_) => {
+                        let message = format!("{} is not a valid hex constant", text);
+                        return Some(Err(self.error(start, message)));
+                    }
+                };
+                return Some(Ok(Token {
+                    token_type,
+                    text,
+                    span: Span {
+                        start,
+                        end: self.position,
+                    },
+                }));
+            }
+            if !text.chars().all(|c| c.is_ascii_digit()) {
+                let message = format!("{} is not a valid integer constant", text);
+                return Some(Err(self.error(start, message)));
+            }
+            match text.parse::<i32>() {
+                Ok(value) if value <= MAX_INT => TokenType::IntConst(value as i16),
+                _ => {
+                    let message = format!(
+                        "integer constant {} is out of range, the largest is {}",
+                        text, MAX_INT
+                    );
+                    return Some(Err(self.error(start, message)));
+                }
+            }
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            while let Some(c) = self
+                .peek()
+                .filter(|c| c.is_ascii_alphanumeric() || *c == '_')
+            {
+                text.push(c);
+                self.next();
+            }
+            match text.parse::<KeyWord>() {
+                Ok(key_word) if !key_word.is_extended() || self.dialect == Dialect::Extended => {
+                    TokenType::KeyWord(key_word)
+                }
+                _ => TokenType::Identifier(text.clone()),
+            }
+        } else if SYMBOLS.contains(c) {
+            if self.dialect == Dialect::Extended && COMPOUND.contains(c) && self.peek() == Some('=')
+            {
+                text.push('=');
+                self.next();
+            }
+            TokenType::Symbol(text.clone())
+        } else {
+            return Some(Err(
+                self.error(start, format!("unexpected character {:?}", c))
+            ));
+        };
+        Some(Ok(Token {
+            token_type,
+            text,
+            span: Span {
+                start,
+                end: self.position,
+            },
+        }))
     }
+}
+
+/// Splits Jack source into tokens, skipping white space and comments.
+pub fn tokenize(source: &str) -> Result<Vec<Token>, LexError> {
+    let (tokens, mut errors) = tokenize_partial(source);
+    match errors.is_empty() {
+        true => Ok(tokens),
+        false => Err(errors.remove(0)),
+    }
+}
 
-    fn int_val(&self) -> Option<i16> {
-        match self.current_token.parse::<i16>() {
-            Ok(v) => Some(v),
-            Err(_) => None,
+/// Like `tokenize`, but carries on after an error, leaving out what could
+/// not be read, and returns every error.
+pub fn tokenize_partial(source: &str) -> (Vec<Token>, Vec<LexError>) {
+    tokenize_dialect(source, Dialect::Standard)
+}
+
+/// Like `tokenize_partial`, in the given dialect.
+pub fn tokenize_dialect(source: &str, dialect: Dialect) -> (Vec<Token>, Vec<LexError>) {
+    let (tokens, _, errors) = scan(source, dialect);
+    (tokens, errors)
+}
+
+/// The comments in Jack source, in order.
+pub fn comments(source: &str) -> Vec<Comment> {
+    scan(source, Dialect::Standard).1
+}
+
+fn scan(source: &str, dialect: Dialect) -> (Vec<Token>, Vec<Comment>, Vec<LexError>) {
+    let mut scanner = Scanner {
+        chars: source.chars().peekable(),
+        position: Position { line: 1, column: 1 },
+        dialect,
+        comments: Vec::new(),
+    };
+    let mut tokens = Vec::new();
+    let mut errors = Vec::new();
+    while let Some(token) = scanner.token() {
+        match token {
+            Ok(token) => tokens.push(token),
+            Err(e) => errors.push(e),
         }
     }
+    (tokens, scanner.comments, errors)
+}
+
+/// The tokens as the `<tokens>` XML of the nand2tetris tokenizer, the
+/// `XxxT.xml` files of project 10.
+pub fn to_xml(tokens: &[Token]) -> String {
+    let mut out = String::from("<tokens>\r\n");
+    for token in tokens {
+        let (element, text) = match &token.token_type {
+            TokenType::KeyWord(key_word) => ("keyword", key_word.to_string()),
+            TokenType::Symbol(symbol) => ("symbol", symbol.clone()),
+            TokenType::Identifier(identifier) => ("identifier", identifier.clone()),
+            TokenType::IntConst(value) => ("integerConstant", value.to_string()),
+            TokenType::StringConst(value) => ("stringConstant", value.clone()),
+        };
+        out.push_str(&format!("<{0}> {1} </{0}>\r\n", element, escape_xml(&text)));
+    }
+    out.push_str("</tokens>\r\n");
+    out
+}
 
-    fn string_val(&self) -> Option<String> {
-        if self.current_token.starts_with("\"") {
-            let current_token = self.current_token.trim_start_matches("\"");
-            let current_token = current_token.trim_end_matches("\"");
-            return Some(current_token.to_string());
+pub fn escape_xml(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
         }
-        None
     }
+    out
 }
@@ -0,0 +1,464 @@
+use super::Machine;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+pub const RAM_SIZE: usize = 32768;
+pub const SCREEN: usize = 16384;
+pub const KBD: usize = 24576;
+
+/// The computation of a C-instruction, named after its mnemonic.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comp {
+    Zero,
+    One,
+    MinusOne,
+    D,
+    A,
+    M,
+    NotD,
+    NotA,
+    NotM,
+    NegD,
+    NegA,
+    NegM,
+    DPlusOne,
+    APlusOne,
+    MPlusOne,
+    DMinusOne,
+    AMinusOne,
+    MMinusOne,
+    DPlusA,
+    DPlusM,
+    DMinusA,
+    DMinusM,
+    AMinusD,
+    MMinusD,
+    DAndA,
+    DAndM,
+    DOrA,
+    DOrM,
+    /// A control-bit combination without a mnemonic, computed by the ALU
+    /// rules: the `a` bit followed by zx nx zy ny f no.
+    Alu(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Jump {
+    Null,
+    JGT,
+    JEQ,
+    JGE,
+    JLT,
+    JNE,
+    JLE,
+    JMP,
+}
+
+pub const DEST_M: u8 = 0b001;
+pub const DEST_D: u8 = 0b010;
+pub const DEST_A: u8 = 0b100;
+
+/// A decoded ROM word.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instruction {
+    A(i16),
+    C { comp: Comp, dest: u8, jump: Jump },
+}
+
+impl Instruction {
+    pub fn decode(word: u16) -> Self {
+        if word & 0x8000 == 0 {
+            return Instruction::A(word as i16);
+        }
+        let bits = ((word >> 6) & 0x7f) as u8;
+        let comp = match bits {
+            0b0101010 => Comp::Zero,
+            0b0111111 => Comp::One,
+            0b0111010 => Comp::MinusOne,
+            0b0001100 => Comp::D,
+            0b0110000 => Comp::A,
+            0b1110000 => Comp::M,
+            0b0001101 => Comp::NotD,
+            0b0110001 => Comp::NotA,
+            0b1110001 => Comp::NotM,
+            0b0001111 => Comp::NegD,
+            0b0110011 => Comp::NegA,
+            0b1110011 => Comp::NegM,
+            0b0011111 => Comp::DPlusOne,
+            0b0110111 => Comp::APlusOne,
+            0b1110111 => Comp::MPlusOne,
+            0b0001110 => Comp::DMinusOne,
+            0b0110010 => Comp::AMinusOne,
+            0b1110010 => Comp::MMinusOne,
+            0b0000010 => Comp::DPlusA,
+            0b1000010 => Comp::DPlusM,
+            0b0010011 => Comp::DMinusA,
+            0b1010011 => Comp::DMinusM,
+            0b0000111 => Comp::AMinusD,
+            0b1000111 => Comp::MMinusD,
+            0b0000000 => Comp::DAndA,
+            0b1000000 => Comp::DAndM,
+            0b0010101 => Comp::DOrA,
+            0b1010101 => Comp::DOrM,
+            _ => Comp::Alu(bits),
+        };
+        let dest = ((word >> 3) & 0b111) as u8;
+        let jump = match word & 0b111 {
+            0 => Jump::Null,
+            1 => Jump::JGT,
+            2 => Jump::JEQ,
+            3 => Jump::JGE,
+            4 => Jump::JLT,
+            5 => Jump::JNE,
+            6 => Jump::JLE,
+            _ => Jump::JMP,
+        };
+        Instruction::C { comp, dest, jump }
+    }
+}
+
+impl Comp {
+    #[inline(always)]
+    pub fn compute(self, a: i16, d: i16, m: i16) -> i16 {
+        match self {
+            Comp::Zero => 0,
+            Comp::One => 1,
+            Comp::MinusOne => -1,
+            Comp::D => d,
+            Comp::A => a,
+            Comp::M => m,
+            Comp::NotD => !d,
+            Comp::NotA => !a,
+            Comp::NotM => !m,
+            Comp::NegD => d.wrapping_neg(),
+            Comp::NegA => a.wrapping_neg(),
+            Comp::NegM => m.wrapping_neg(),
+            Comp::DPlusOne => d.wrapping_add(1),
+            Comp::APlusOne => a.wrapping_add(1),
+            Comp::MPlusOne => m.wrapping_add(1),
+            Comp::DMinusOne => d.wrapping_sub(1),
+            Comp::AMinusOne => a.wrapping_sub(1),
+            Comp::MMinusOne => m.wrapping_sub(1),
+            Comp::DPlusA => d.wrapping_add(a),
+            Comp::DPlusM => d.wrapping_add(m),
+            Comp::DMinusA => d.wrapping_sub(a),
+            Comp::DMinusM => d.wrapping_sub(m),
+            Comp::AMinusD => a.wrapping_sub(d),
+            Comp::MMinusD => m.wrapping_sub(d),
+            Comp::DAndA => d & a,
+            Comp::DAndM => d & m,
+            Comp::DOrA => d | a,
+            Comp::DOrM => d | m,
+            Comp::Alu(bits) => {
+                let mut x = d;
+                let mut y = if bits & 0x40 != 0 { m } else { a };
+                if bits & 0x20 != 0 {
+                    x = 0;
+                }
+                if bits & 0x10 != 0 {
+                    x = !x;
+                }
+                if bits & 0x08 != 0 {
+                    y = 0;
+                }
+                if bits & 0x04 != 0 {
+                    y = !y;
+                }
+                let out = if bits & 0x02 != 0 {
+                    x.wrapping_add(y)
+                } else {
+                    x & y
+                };
+                if bits & 0x01 != 0 {
+                    !out
+                } else {
+                    out
+                }
+            }
+        }
+    }
+
+    /// Whether the computation reads RAM[A].
+    pub fn reads_m(self) -> bool {
+        match self {
+            Comp::M
+            | Comp::NotM
+            | Comp::NegM
+            | Comp::MPlusOne
+            | Comp::MMinusOne
+            | Comp::DPlusM
+            | Comp::DMinusM
+            | Comp::MMinusD
+            | Comp::DAndM
+            | Comp::DOrM => true,
+            Comp::Alu(bits) => bits & 0x40 != 0,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Comp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match *self {
+            Comp::Zero => "0",
+            Comp::One => "1",
+            Comp::MinusOne => "-1",
+            Comp::D => "D",
+            Comp::A => "A",
+            Comp::M => "M",
+            Comp::NotD => "!D",
+            Comp::NotA => "!A",
+            Comp::NotM => "!M",
+            Comp::NegD => "-D",
+            Comp::NegA => "-A",
+            Comp::NegM => "-M",
+            Comp::DPlusOne => "D+1",
+            Comp::APlusOne => "A+1",
+            Comp::MPlusOne => "M+1",
+            Comp::DMinusOne => "D-1",
+            Comp::AMinusOne => "A-1",
+            Comp::MMinusOne => "M-1",
+            Comp::DPlusA => "D+A",
+            Comp::DPlusM => "D+M",
+            Comp::DMinusA => "D-A",
+            Comp::DMinusM => "D-M",
+            Comp::AMinusD => "A-D",
+            Comp::MMinusD => "M-D",
+            Comp::DAndA => "D&A",
+            Comp::DAndM => "D&M",
+            Comp::DOrA => "D|A",
+            Comp::DOrM => "D|M",
+            Comp::Alu(bits) => return write!(f, "ALU({:07b})", bits),
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Instruction::A(value) => write!(f, "@{}", value),
+            Instruction::C { comp, dest, jump } => {
+                if dest & DEST_A != 0 {
+                    write!(f, "A")?;
+                }
+                if dest & DEST_M != 0 {
+                    write!(f, "M")?;
+                }
+                if dest & DEST_D != 0 {
+                    write!(f, "D")?;
+                }
+                if dest != 0 {
+                    write!(f, "=")?;
+                }
+                write!(f, "{}", comp)?;
+                if jump != Jump::Null {
+                    write!(f, ";{:?}", jump)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Jump {
+    #[inline(always)]
+    pub fn taken(self, out: i16) -> bool {
+        match self {
+            Jump::Null => false,
+            Jump::JGT => out > 0,
+            Jump::JEQ => out == 0,
+            Jump::JGE => out >= 0,
+            Jump::JLT => out < 0,
+            Jump::JNE => out != 0,
+            Jump::JLE => out <= 0,
+            Jump::JMP => true,
+        }
+    }
+}
+
+/// The Hack computer: ROM, RAM and the A, D and PC registers.
+///
+/// Every ROM word is decoded once when the program is loaded, so a cycle is
+/// a single `match` over a pre-decoded `Instruction`. Addresses past the end
+/// of the program read as `@0`, like the zero-filled ROM of the hardware.
+pub struct CPU {
+    words: Vec<u16>,
+    rom: Vec<Instruction>,
+    pub ram: Vec<i16>,
+    pub a: i16,
+    pub d: i16,
+    pub pc: u16,
+    pub cycles: u64,
+}
+
+impl CPU {
+    pub fn new(words: Vec<u16>) -> Self {
+        let rom = words.iter().map(|w| Instruction::decode(*w)).collect();
+        CPU {
+            words,
+            rom,
+            ram: vec![0; RAM_SIZE],
+            a: 0,
+            d: 0,
+            pc: 0,
+            cycles: 0,
+        }
+    }
+
+    /// Loads a `.hack` file, one 16-character binary word per line.
+    pub fn from_hack_file(path: &Path) -> Self {
+        let text = fs::read_to_string(path).unwrap();
+        let words = text
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                u16::from_str_radix(line, 2)
+                    .unwrap_or_else(|_| panic!("{} is not a binary Hack instruction", line))
+            })
+            .collect();
+        Self::new(words)
+    }
+
+    /// The program as loaded.
+    pub fn words(&self) -> &[u16] {
+        &self.words
+    }
+
+    pub fn instruction(&self, address: u16) -> Instruction {
+        match self.rom.get(address as usize) {
+            Some(instruction) => *instruction,
+            None => Instruction::A(0),
+        }
+    }
+
+    /// Executes one instruction, returns the RAM address it wrote, if any.
+    pub fn step(&mut self) -> Option<u16> {
+        let instruction = self.instruction(self.pc);
+        self.execute(instruction)
+    }
+
+    /// Executes `instruction` as if it were at the current PC.
+    #[inline(always)]
+    pub fn execute(&mut self, instruction: Instruction) -> Option<u16> {
+        self.cycles += 1;
+        match instruction {
+            Instruction::A(value) => {
+                self.a = value;
+                self.pc = self.pc.wrapping_add(1);
+                None
+            }
+            Instruction::C { comp, dest, jump } => {
+                let address = (self.a as u16 as usize) & (RAM_SIZE - 1);
+                let out = comp.compute(self.a, self.d, self.ram[address]);
+                let mut written = None;
+                if dest & DEST_M != 0 {
+                    self.ram[address] = out;
+                    written = Some(address as u16);
+                }
+                let target = self.a as u16;
+                if dest & DEST_D != 0 {
+                    self.d = out;
+                }
+                if dest & DEST_A != 0 {
+                    self.a = out;
+                }
+                self.pc = if jump.taken(out) {
+                    target
+                } else {
+                    self.pc.wrapping_add(1)
+                };
+                written
+            }
+        }
+    }
+
+    /// Runs until the program halts or `max_cycles` more cycles have been
+    /// executed, returns the number of cycles run.
+    pub fn run(&mut self, max_cycles: u64) -> u64 {
+        let (mut a, mut d, mut pc) = (self.a, self.d, self.pc);
+        let rom = &self.rom;
+        let ram = &mut self.ram[..];
+        let mut cycles = 0;
+        while cycles < max_cycles {
+            cycles += 1;
+            let instruction = match rom.get(pc as usize) {
+                Some(instruction) => *instruction,
+                None => Instruction::A(0),
+            };
+            match instruction {
+                Instruction::A(value) => {
+                    // `@pc; 0;JMP` is the idiomatic end of a Hack program.
+                    if value as u16 == pc {
+                        if let Some(Instruction::C {
+                            jump: Jump::JMP, ..
+                        }) = rom.get(pc as usize + 1)
+                        {
+                            cycles -= 1;
+                            break;
+                        }
+                    }
+                    a = value;
+                    pc = pc.wrapping_add(1);
+                }
+                Instruction::C { comp, dest, jump } => {
+                    let address = (a as u16 as usize) & (RAM_SIZE - 1);
+                    let out = comp.compute(a, d, ram[address]);
+                    if dest & DEST_M != 0 {
+                        ram[address] = out;
+                    }
+                    let target = a as u16;
+                    if dest & DEST_D != 0 {
+                        d = out;
+                    }
+                    if dest & DEST_A != 0 {
+                        a = out;
+                    }
+                    pc = if jump.taken(out) {
+                        target
+                    } else {
+                        pc.wrapping_add(1)
+                    };
+                }
+            }
+        }
+        self.a = a;
+        self.d = d;
+        self.pc = pc;
+        self.cycles += cycles;
+        cycles
+    }
+
+    /// Whether the PC sits on an `@pc; 0;JMP` loop.
+    pub fn is_halted(&self) -> bool {
+        self.instruction(self.pc) == Instruction::A(self.pc as i16)
+            && matches!(
+                self.instruction(self.pc.wrapping_add(1)),
+                Instruction::C {
+                    jump: Jump::JMP,
+                    ..
+                }
+            )
+    }
+
+    pub fn reset(&mut self) {
+        self.pc = 0;
+        self.cycles = 0;
+    }
+}
+
+impl Machine for CPU {
+    fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    fn run(&mut self, max_cycles: u64) -> u64 {
+        CPU::run(self, max_cycles)
+    }
+
+    fn ram_mut(&mut self) -> &mut [i16] {
+        &mut self.ram
+    }
+}
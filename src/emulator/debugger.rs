@@ -0,0 +1,311 @@
+use super::cpu::{Instruction, CPU, RAM_SIZE};
+use crate::assembler::table::SymbolTalbe;
+use crate::assembler::Assembler;
+use std::collections::BTreeSet;
+use std::io::prelude::*;
+use std::path::Path;
+
+/// How many cycles `continue` and `next` run before giving up.
+pub const RUN_LIMIT: u64 = 100_000_000;
+
+const HELP: &str = "\
+break <label|addr>      stop before executing ROM[addr]
+delete <label|addr>     remove a breakpoint
+watch <symbol|addr>     stop when RAM[addr] changes
+unwatch <symbol|addr>   remove a watchpoint
+step [n]                execute n instructions
+next                    step over jumps, stop at the next instruction in ROM order
+continue                run until a breakpoint, a watchpoint or the end
+print D|A|PC|RAM[x]|RAM[x..y]|<symbol>
+x/<n> <symbol|addr>     examine n words of RAM
+info                    list breakpoints and watchpoints
+quit";
+
+/// An interactive debugger for Hack machine code.
+///
+/// Names given to `break`, `watch`, `print` and `x` are resolved with the
+/// symbol table the assembler built for the program: labels are ROM
+/// addresses and variables (including `SP`, `R0`-`R15`, `SCREEN`, `KBD`)
+/// are RAM addresses.
+pub struct Debugger {
+    cpu: CPU,
+    symbols: SymbolTalbe,
+    breakpoints: BTreeSet<u16>,
+    watchpoints: BTreeSet<u16>,
+}
+
+enum Stop {
+    Breakpoint,
+    Watchpoint(u16, i16),
+    Halted,
+    Limit,
+    Done,
+}
+
+impl Debugger {
+    pub fn new(cpu: CPU, symbols: SymbolTalbe) -> Self {
+        Debugger {
+            cpu,
+            symbols,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+        }
+    }
+
+    /// Assembles `path` and loads the result.
+    pub fn load(path: &Path) -> Self {
+        let mut assembler = Assembler::new(path);
+        assembler.run();
+        let cpu = CPU::from_hack_file(&assembler.hack_path());
+        Self::new(cpu, assembler.symbol_table().clone())
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+
+    /// Reads commands from `input` until it ends or `quit`.
+    pub fn repl<R: BufRead, W: Write>(&mut self, input: R, mut output: W) {
+        write!(output, "(hdb) ").unwrap();
+        output.flush().unwrap();
+        for line in input.lines() {
+            let line = line.unwrap();
+            if matches!(line.trim(), "quit" | "q") {
+                break;
+            }
+            let res = self.execute(&line);
+            if !res.is_empty() {
+                writeln!(output, "{}", res).unwrap();
+            }
+            write!(output, "(hdb) ").unwrap();
+            output.flush().unwrap();
+        }
+        writeln!(output).unwrap();
+    }
+
+    /// Runs one command and returns what it prints.
+    pub fn execute(&mut self, line: &str) -> String {
+        let line = line.trim();
+        let (command, arg) = match line.find(char::is_whitespace) {
+            Some(i) => (&line[..i], line[i..].trim()),
+            None => (line, ""),
+        };
+        let res = match command {
+            "" => Ok(String::new()),
+            "help" | "h" => Ok(HELP.to_string()),
+            "break" | "b" => self.rom_address(arg).map(|address| {
+                self.breakpoints.insert(address);
+                format!("breakpoint at {}", self.location(address))
+            }),
+            "delete" | "d" => {
+                self.rom_address(arg)
+                    .map(|address| match self.breakpoints.remove(&address) {
+                        true => format!("deleted breakpoint at {}", self.location(address)),
+                        false => format!("no breakpoint at {}", self.location(address)),
+                    })
+            }
+            "watch" | "w" => self.ram_address(arg).map(|address| {
+                self.watchpoints.insert(address);
+                format!(
+                    "watchpoint on RAM[{}] = {}",
+                    address, self.cpu.ram[address as usize]
+                )
+            }),
+            "unwatch" => {
+                self.ram_address(arg)
+                    .map(|address| match self.watchpoints.remove(&address) {
+                        true => format!("deleted watchpoint on RAM[{}]", address),
+                        false => format!("no watchpoint on RAM[{}]", address),
+                    })
+            }
+            "step" | "s" => match arg {
+                "" => Ok(1),
+                _ => arg
+                    .parse::<u64>()
+                    .map_err(|_| format!("{} is not a count", arg)),
+            }
+            .map(|count| {
+                let stop = self.run_until(count, |_| false);
+                self.report(stop)
+            }),
+            "next" | "n" => {
+                let next = self.cpu.pc.wrapping_add(1);
+                let stop = self.run_until(RUN_LIMIT, |cpu| cpu.pc == next);
+                Ok(self.report(stop))
+            }
+            "continue" | "c" => {
+                let stop = self.run_until(RUN_LIMIT, |_| false);
+                Ok(self.report(stop))
+            }
+            "print" | "p" => self.print(arg),
+            "info" | "i" => Ok(self.info()),
+            _ if command.starts_with("x/") || command == "x" => {
+                let count = match command.trim_start_matches('x').trim_start_matches('/') {
+                    "" => Ok(1),
+                    n => n.parse().map_err(|_| format!("{} is not a count", n)),
+                };
+                count.and_then(|count| self.examine(count, arg))
+            }
+            _ => Err(format!("unknown command {}, try help", command)),
+        };
+        match res {
+            Ok(s) => s,
+            Err(e) => format!("error: {}", e),
+        }
+    }
+
+    // Steps at least once, then until `done`, a breakpoint or a watchpoint.
+    fn run_until<F: Fn(&CPU) -> bool>(&mut self, limit: u64, done: F) -> Stop {
+        for i in 0..limit {
+            if i > 0 && self.breakpoints.contains(&self.cpu.pc) {
+                return Stop::Breakpoint;
+            }
+            if self.cpu.is_halted() {
+                return Stop::Halted;
+            }
+            let address = self.cpu.pc;
+            let old = match self.cpu.instruction(address) {
+                Instruction::C { .. } => {
+                    self.cpu.ram[(self.cpu.a as u16 as usize) & (RAM_SIZE - 1)]
+                }
+                Instruction::A(_) => 0,
+            };
+            if let Some(written) = self.cpu.step() {
+                let new = self.cpu.ram[written as usize];
+                if self.watchpoints.contains(&written) && new != old {
+                    return Stop::Watchpoint(written, old);
+                }
+            }
+            if done(&self.cpu) {
+                return Stop::Done;
+            }
+        }
+        if self.breakpoints.contains(&self.cpu.pc) {
+            return Stop::Breakpoint;
+        }
+        match limit {
+            RUN_LIMIT => Stop::Limit,
+            _ => Stop::Done,
+        }
+    }
+
+    fn report(&self, stop: Stop) -> String {
+        let mut res = match stop {
+            Stop::Breakpoint => "breakpoint\n".to_string(),
+            Stop::Watchpoint(address, old) => format!(
+                "watchpoint RAM[{}]: {} -> {}\n",
+                address, old, self.cpu.ram[address as usize]
+            ),
+            Stop::Halted => "halted\n".to_string(),
+            Stop::Limit => format!("stopped after {} cycles\n", RUN_LIMIT),
+            Stop::Done => String::new(),
+        };
+        res.push_str(&format!(
+            "{}: {}",
+            self.location(self.cpu.pc),
+            self.cpu.instruction(self.cpu.pc)
+        ));
+        res
+    }
+
+    fn print(&self, arg: &str) -> Result<String, String> {
+        match arg {
+            "A" => Ok(format!("A = {}", self.cpu.a)),
+            "D" => Ok(format!("D = {}", self.cpu.d)),
+            "PC" => Ok(format!("PC = {}", self.location(self.cpu.pc))),
+            "" => Err("print what?".to_string()),
+            _ if arg.starts_with("RAM[") && arg.ends_with(']') => {
+                let range = &arg[4..arg.len() - 1];
+                let (start, end) = match range.find("..") {
+                    Some(i) => (
+                        self.ram_address(&range[..i])?,
+                        self.ram_address(&range[i + 2..])?,
+                    ),
+                    None => {
+                        let address = self.ram_address(range)?;
+                        (address, address + 1)
+                    }
+                };
+                let res: Vec<String> = (start..end.max(start + 1))
+                    .map(|address| format!("RAM[{}] = {}", address, self.cpu.ram[address as usize]))
+                    .collect();
+                Ok(res.join("\n"))
+            }
+            _ => {
+                let address = self.ram_address(arg)?;
+                Ok(format!(
+                    "{} = RAM[{}] = {}",
+                    arg, address, self.cpu.ram[address as usize]
+                ))
+            }
+        }
+    }
+
+    fn examine(&self, count: usize, arg: &str) -> Result<String, String> {
+        let start = self.ram_address(arg)? as usize;
+        let mut lines = Vec::new();
+        for row in (start..(start + count).min(RAM_SIZE)).step_by(8) {
+            let end = (row + 8).min(start + count).min(RAM_SIZE);
+            let words: Vec<String> = self.cpu.ram[row..end]
+                .iter()
+                .map(|w| format!("0x{:04x}", *w as u16))
+                .collect();
+            lines.push(format!("{}: {}", row, words.join(" ")));
+        }
+        Ok(lines.join("\n"))
+    }
+
+    fn info(&self) -> String {
+        let mut lines = Vec::new();
+        for address in self.breakpoints.iter() {
+            lines.push(format!("breakpoint at {}", self.location(*address)));
+        }
+        for address in self.watchpoints.iter() {
+            lines.push(format!("watchpoint on RAM[{}]", address));
+        }
+        if lines.is_empty() {
+            lines.push("no breakpoints or watchpoints".to_string());
+        }
+        lines.join("\n")
+    }
+
+    // `12 (LOOP+2)`
+    fn location(&self, address: u16) -> String {
+        match self.symbols.label_before(address as i16) {
+            Some((label, base)) if base == address as i16 => format!("{} ({})", address, label),
+            Some((label, base)) => format!("{} ({}+{})", address, label, address as i16 - base),
+            None => address.to_string(),
+        }
+    }
+
+    fn rom_address(&self, arg: &str) -> Result<u16, String> {
+        if let Ok(address) = arg.parse::<u16>() {
+            return Ok(address);
+        }
+        match self.symbols.get_address(arg) {
+            Some(address) if self.symbols.is_label(arg) => Ok(*address as u16),
+            Some(_) => Err(format!("{} is a variable, not a label", arg)),
+            None => Err(format!("no label {}", arg)),
+        }
+    }
+
+    fn ram_address(&self, arg: &str) -> Result<u16, String> {
+        let arg = arg.trim();
+        let address = match arg.parse::<u16>() {
+            Ok(address) => address,
+            Err(_) => match self.symbols.get_address(arg) {
+                Some(address) if !self.symbols.is_label(arg) => *address as u16,
+                Some(_) => return Err(format!("{} is a label, not a variable", arg)),
+                None => return Err(format!("no variable {}", arg)),
+            },
+        };
+        match (address as usize) < RAM_SIZE {
+            true => Ok(address),
+            false => Err(format!("{} is outside the RAM", address)),
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use super::cpu::{CPU, RAM_SIZE};
+use super::vm_emulator::VMEmulator;
+use crate::assembler::table::SymbolTalbe;
+use crate::assembler::Assembler;
+use crate::vm::code::Code;
+use crate::vm::parser::Command;
+use crate::vm::VM;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+
+/// Hack instructions the translation may take per VM command, a generous
+/// bound that covers `call` and `return`.
+pub const CYCLES_PER_COMMAND: u64 = 100;
+
+// The stack ends and the heap starts at 2048.
+const HEAP: usize = 2048;
+
+const LCL: usize = 1;
+// The LCL of `Sys.init`, which the bootstrap calls with an empty stack.
+const TOP_FRAME: i16 = 256 + 5;
+
+/// The first difference between the two runs of a program.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Divergence {
+    /// One run halted and the other did not.
+    Halted { vm: bool, cpu: bool },
+    /// The CPU did not get back to `Sys.init` or halt within the cycles the
+    /// translation may take, while the VM emulator did after `commands`
+    /// commands.
+    Lost { commands: u64 },
+    /// A word differs at a checkpoint. `name` says which, e.g. `SP`,
+    /// `temp 3`, `static Main.vm.0`, `stack[2]` or `RAM[2048]`.
+    Word { name: String, vm: i16, cpu: i16 },
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Divergence::Halted { vm, cpu } => {
+                let state = |halted: &bool| match halted {
+                    true => "halted",
+                    false => "did not halt",
+                };
+                write!(f, "the VM emulator {}, the CPU {}", state(vm), state(cpu))
+            }
+            Divergence::Lost { commands } => write!(
+                f,
+                "the CPU did not reach the state of the VM emulator after {} commands",
+                commands
+            ),
+            Divergence::Word { name, vm, cpu } => {
+                write!(f, "{}: VM emulator {}, CPU {}", name, vm, cpu)
+            }
+        }
+    }
+}
+
+/// Runs a VM program twice, once in the VM emulator and once through
+/// `VM::translate`, the assembler and the CPU emulator, and compares them
+/// at matching checkpoints: every return to `Sys.init` and the halt.
+///
+/// The comparison covers the segment pointers, temp, every static (by name,
+/// since the assembler allocates them among its own variables), the stack
+/// from 256 up to SP and all of the RAM from the heap on. R13-R15 and the
+/// stack above SP are scratch space, and return addresses differ by nature,
+/// so those are not compared.
+pub struct Differential {
+    pub vm: VMEmulator,
+    pub cpu: CPU,
+    /// The checkpoints at which the runs were compared.
+    pub checkpoints: u64,
+    symbols: SymbolTalbe,
+    // The ROM addresses calls from the program return to.
+    returns: HashSet<u16>,
+}
+
+impl Differential {
+    /// Loads `path`, a `.vm` file or a directory of them, translating it to
+    /// `asm_path`.
+    pub fn new(path: &Path, asm_path: &Path) -> Self {
+        let mut translator = VM::with_writer(path, Box::new(Code::new(asm_path)));
+        translator.translate();
+        let mut assembler = Assembler::new(asm_path);
+        assembler.run();
+        let cpu = CPU::from_hack_file(&assembler.hack_path());
+        let symbols = assembler.symbol_table().clone();
+        let returns = symbols
+            .symbols()
+            .filter(|(symbol, _)| symbols.is_label(symbol) && symbol.contains("$ret."))
+            // Where the bootstrap's call returns to is also where the first
+            // function starts.
+            .filter(|(symbol, _)| *symbol != "Sys.init$ret.0")
+            .map(|(_, address)| address as u16)
+            .collect();
+        Differential {
+            vm: VMEmulator::new(path),
+            cpu,
+            checkpoints: 0,
+            symbols,
+            returns,
+        }
+    }
+
+    /// Runs the VM emulator for up to `max_cycles` commands, stopping both
+    /// runs at each checkpoint to compare them, and returns the first
+    /// divergence, if any. A program that is still running when the
+    /// commands are used up has no divergence so far, which says nothing if
+    /// it never got to a checkpoint; see `checkpoints`.
+    pub fn run(&mut self, max_cycles: u64) -> Option<Divergence> {
+        let mut left = max_cycles;
+        loop {
+            let commands = self.vm_checkpoint(left)?;
+            left -= commands;
+            // One more command's worth covers the bootstrap.
+            if !self.cpu_checkpoint((commands + 1).saturating_mul(CYCLES_PER_COMMAND)) {
+                return Some(Divergence::Lost {
+                    commands: self.vm.cycles,
+                });
+            }
+            self.checkpoints += 1;
+            if let Some(divergence) = self.compare() {
+                return Some(divergence);
+            }
+            if self.vm.is_halted() {
+                return None;
+            }
+        }
+    }
+
+    // Runs the VM emulator to its next checkpoint, returns the number of
+    // commands it took, or `None` if it did not get there in `max_cycles`.
+    fn vm_checkpoint(&mut self, max_cycles: u64) -> Option<u64> {
+        let vm = &mut self.vm;
+        let mut commands = 0;
+        while !vm.is_halted() {
+            if commands == max_cycles {
+                return None;
+            }
+            let returning = matches!(vm.source(vm.pc), Some((_, Command::Return)));
+            vm.step();
+            commands += 1;
+            if returning && vm.ram[LCL] == TOP_FRAME {
+                break;
+            }
+        }
+        Some(commands)
+    }
+
+    // Runs the CPU to its next checkpoint, returns whether it got there in
+    // `max_cycles`.
+    fn cpu_checkpoint(&mut self, max_cycles: u64) -> bool {
+        let cpu = &mut self.cpu;
+        for _ in 0..max_cycles {
+            if cpu.is_halted() {
+                return true;
+            }
+            cpu.step();
+            if self.returns.contains(&cpu.pc) && cpu.ram[LCL] == TOP_FRAME {
+                return true;
+            }
+        }
+        cpu.is_halted()
+    }
+
+    /// The first divergence between the current states.
+    pub fn compare(&self) -> Option<Divergence> {
+        let (vm, cpu) = (&self.vm, &self.cpu);
+        if vm.is_halted() != cpu.is_halted() {
+            return Some(Divergence::Halted {
+                vm: vm.is_halted(),
+                cpu: cpu.is_halted(),
+            });
+        }
+
+        let mut words: Vec<(String, usize, usize)> = Vec::new();
+        for (i, name) in ["SP", "LCL", "ARG", "THIS", "THAT"].iter().enumerate() {
+            words.push((name.to_string(), i, i));
+        }
+        for i in 0..8 {
+            words.push((format!("temp {}", i), 5 + i, 5 + i));
+        }
+        for (name, address) in vm.statics() {
+            if let Some(cpu_address) = self.symbols.get_address(name) {
+                words.push((format!("static {}", name), address, *cpu_address as usize));
+            }
+        }
+        // Return addresses are command indexes in one run and ROM addresses
+        // in the other, so the frames' return address slots are skipped.
+        let mut returns = Vec::new();
+        let mut frame = vm.ram[1] as u16 as usize;
+        while (256 + 5..=HEAP).contains(&frame) {
+            returns.push(frame - 5);
+            let saved = vm.ram[frame - 4] as u16 as usize;
+            if saved >= frame {
+                break;
+            }
+            frame = saved;
+        }
+        let sp = (vm.ram[0] as u16 as usize).min(HEAP);
+        for address in (256..sp.max(256)).filter(|address| !returns.contains(address)) {
+            words.push((format!("stack[{}]", address - 256), address, address));
+        }
+        // The heap is only named once it differs, as the runs are compared
+        // at every checkpoint.
+        let heap = (HEAP..RAM_SIZE)
+            .find(|address| vm.ram[*address] != cpu.ram[*address])
+            .map(|address| (format!("RAM[{}]", address), address, address));
+
+        words
+            .into_iter()
+            .chain(heap)
+            .find(|(_, vm_address, cpu_address)| vm.ram[*vm_address] != cpu.ram[*cpu_address])
+            .map(|(name, vm_address, cpu_address)| Divergence::Word {
+                name,
+                vm: vm.ram[vm_address],
+                cpu: cpu.ram[cpu_address],
+            })
+    }
+}
@@ -0,0 +1,367 @@
+use super::cpu::{CPU, RAM_SIZE};
+use std::collections::BTreeSet;
+use std::io::{self, prelude::*};
+use std::net::{TcpListener, TcpStream};
+
+/// Where the RAM starts in the debugger's byte addresses. ROM word `i` is at
+/// byte `2 * i` and RAM word `i` at `RAM_BASE + 2 * i`, both little endian.
+pub const RAM_BASE: u32 = 0x80_0000;
+
+/// Cycles `continue` runs between checks for an interrupt from the debugger.
+const SLICE: u64 = 100_000;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.nand2tetris.hack">
+    <reg name="a" bitsize="16" type="int16" regnum="0"/>
+    <reg name="d" bitsize="16" type="int16" regnum="1"/>
+    <reg name="pc" bitsize="32" type="code_ptr" regnum="2"/>
+  </feature>
+</target>
+"#;
+
+/// A stub that lets a debugger speaking the GDB remote serial protocol
+/// control a `CPU` over TCP.
+///
+/// It supports reading and writing the registers (`a`, `d` and `pc`, as
+/// described by `target.xml`) and the RAM, software and hardware
+/// breakpoints, stepping, continuing and interrupting with Ctrl-C. The PC
+/// is reported as a byte address, twice the ROM address.
+pub struct GdbServer {
+    pub cpu: CPU,
+    breakpoints: BTreeSet<u16>,
+    ack: bool,
+}
+
+enum Reply {
+    Packet(String),
+    Close,
+}
+
+impl GdbServer {
+    pub fn new(cpu: CPU) -> Self {
+        GdbServer {
+            cpu,
+            breakpoints: BTreeSet::new(),
+            ack: true,
+        }
+    }
+
+    /// Serves the first debugger that connects to `listener` until it
+    /// detaches or kills the program.
+    pub fn serve(&mut self, listener: &TcpListener) -> io::Result<()> {
+        let (stream, _) = listener.accept()?;
+        self.serve_stream(stream)
+    }
+
+    pub fn serve_stream(&mut self, mut stream: TcpStream) -> io::Result<()> {
+        self.ack = true;
+        while let Some(packet) = self.read_packet(&mut stream)? {
+            match self.handle(&packet, &mut stream)? {
+                Reply::Packet(reply) => {
+                    self.write_packet(&mut stream, &reply)?;
+                    // The reply to this one is the last packet acknowledged.
+                    if packet == "QStartNoAckMode" {
+                        self.ack = false;
+                    }
+                }
+                Reply::Close => {
+                    if packet.starts_with('D') {
+                        self.write_packet(&mut stream, "OK")?;
+                    }
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, packet: &str, stream: &mut TcpStream) -> io::Result<Reply> {
+        let reply = match packet {
+            "?" => "S05".to_string(),
+            "g" => {
+                let pc = self.cpu.pc as u32 * 2;
+                format!(
+                    "{}{}{}",
+                    hex(&self.cpu.a.to_le_bytes()),
+                    hex(&self.cpu.d.to_le_bytes()),
+                    hex(&pc.to_le_bytes())
+                )
+            }
+            "s" | "vCont;s" | "vCont;s:1" => self.step(),
+            "c" | "vCont;c" | "vCont;c:1" => self.resume(stream)?,
+            "vCont?" => "vCont;c;s".to_string(),
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            "QStartNoAckMode" => "OK".to_string(),
+            "k" | "D" => return Ok(Reply::Close),
+            _ if packet.starts_with('D') => return Ok(Reply::Close),
+            _ if packet.starts_with("qSupported") => {
+                "PacketSize=4000;qXfer:features:read+;swbreak+;hwbreak+;QStartNoAckMode+"
+                    .to_string()
+            }
+            _ if packet.starts_with("qXfer:features:read:target.xml:") => {
+                features(&packet["qXfer:features:read:target.xml:".len()..])
+            }
+            _ if packet.starts_with('H') => "OK".to_string(),
+            _ if packet.starts_with('G') => self.write_registers(&packet[1..]),
+            _ if packet.starts_with('p') => self.read_register(&packet[1..]),
+            _ if packet.starts_with('P') => self.write_register(&packet[1..]),
+            _ if packet.starts_with('m') => self.read_memory(&packet[1..]),
+            _ if packet.starts_with('M') => self.write_memory(&packet[1..]),
+            _ if packet.starts_with("Z0,") || packet.starts_with("Z1,") => {
+                self.breakpoint(&packet[3..], true)
+            }
+            _ if packet.starts_with("z0,") || packet.starts_with("z1,") => {
+                self.breakpoint(&packet[3..], false)
+            }
+            // An empty reply tells the debugger the packet is not supported.
+            _ => String::new(),
+        };
+        Ok(Reply::Packet(reply))
+    }
+
+    fn step(&mut self) -> String {
+        self.cpu.step();
+        "S05".to_string()
+    }
+
+    // Runs until a breakpoint, the end of the program or an interrupt.
+    fn resume(&mut self, stream: &mut TcpStream) -> io::Result<String> {
+        let mut first = true;
+        loop {
+            for _ in 0..SLICE {
+                if !first && self.breakpoints.contains(&self.cpu.pc) {
+                    return Ok("S05".to_string());
+                }
+                if self.cpu.is_halted() {
+                    return Ok("S05".to_string());
+                }
+                first = false;
+                self.cpu.step();
+            }
+            if interrupted(stream)? {
+                return Ok("S02".to_string());
+            }
+        }
+    }
+
+    fn read_register(&self, args: &str) -> String {
+        match usize::from_str_radix(args, 16) {
+            Ok(0) => hex(&self.cpu.a.to_le_bytes()),
+            Ok(1) => hex(&self.cpu.d.to_le_bytes()),
+            Ok(2) => hex(&(self.cpu.pc as u32 * 2).to_le_bytes()),
+            _ => "E01".to_string(),
+        }
+    }
+
+    fn write_register(&mut self, args: &str) -> String {
+        let (register, value) = match args.find('=') {
+            Some(i) => (&args[..i], unhex(&args[i + 1..])),
+            None => return "E01".to_string(),
+        };
+        match (usize::from_str_radix(register, 16), value.as_deref()) {
+            (Ok(0), Some([lo, hi])) => self.cpu.a = i16::from_le_bytes([*lo, *hi]),
+            (Ok(1), Some([lo, hi])) => self.cpu.d = i16::from_le_bytes([*lo, *hi]),
+            (Ok(2), Some([a, b, c, d])) => {
+                self.cpu.pc = (u32::from_le_bytes([*a, *b, *c, *d]) / 2) as u16
+            }
+            _ => return "E01".to_string(),
+        }
+        "OK".to_string()
+    }
+
+    fn write_registers(&mut self, args: &str) -> String {
+        match unhex(args).as_deref() {
+            Some([a0, a1, d0, d1, p0, p1, p2, p3]) => {
+                self.cpu.a = i16::from_le_bytes([*a0, *a1]);
+                self.cpu.d = i16::from_le_bytes([*d0, *d1]);
+                self.cpu.pc = (u32::from_le_bytes([*p0, *p1, *p2, *p3]) / 2) as u16;
+                "OK".to_string()
+            }
+            _ => "E01".to_string(),
+        }
+    }
+
+    fn read_memory(&self, args: &str) -> String {
+        let (address, len) = match address_length(args) {
+            Some(res) => res,
+            None => return "E01".to_string(),
+        };
+        // Reads stop at the end of the ROM or the RAM, whatever `len` asks.
+        let end = match address >= RAM_BASE {
+            true => RAM_BASE + 2 * RAM_SIZE as u32,
+            false => 2 * self.cpu.words().len() as u32,
+        };
+        if address >= end {
+            return "E01".to_string();
+        }
+        let bytes: Vec<u8> = (address..end.min(address.saturating_add(len)))
+            .map(|byte| self.word(byte).unwrap().to_le_bytes()[byte as usize % 2])
+            .collect();
+        hex(&bytes)
+    }
+
+    fn write_memory(&mut self, args: &str) -> String {
+        let (target, data) = match args.find(':') {
+            Some(i) => (&args[..i], unhex(&args[i + 1..])),
+            None => return "E01".to_string(),
+        };
+        let (address, data) = match (address_length(target), data) {
+            (Some((address, len)), Some(data)) if data.len() == len as usize => (address, data),
+            _ => return "E01".to_string(),
+        };
+        if address < RAM_BASE {
+            // The ROM is decoded when it is loaded and cannot be patched.
+            return "E02".to_string();
+        }
+        for (i, value) in data.iter().enumerate() {
+            let byte = address + i as u32;
+            let index = ((byte - RAM_BASE) / 2) as usize;
+            if index >= RAM_SIZE {
+                return "E01".to_string();
+            }
+            let mut bytes = (self.cpu.ram[index] as u16).to_le_bytes();
+            bytes[byte as usize % 2] = *value;
+            self.cpu.ram[index] = u16::from_le_bytes(bytes) as i16;
+        }
+        "OK".to_string()
+    }
+
+    fn breakpoint(&mut self, args: &str, insert: bool) -> String {
+        let address = args
+            .split(',')
+            .next()
+            .and_then(|address| u32::from_str_radix(address, 16).ok());
+        match address {
+            Some(address) if address < RAM_BASE => {
+                let pc = (address / 2) as u16;
+                match insert {
+                    true => self.breakpoints.insert(pc),
+                    false => self.breakpoints.remove(&pc),
+                };
+                "OK".to_string()
+            }
+            _ => "E01".to_string(),
+        }
+    }
+
+    // The word holding byte `address`.
+    fn word(&self, address: u32) -> Option<u16> {
+        if address >= RAM_BASE {
+            let index = ((address - RAM_BASE) / 2) as usize;
+            self.cpu.ram.get(index).map(|w| *w as u16)
+        } else {
+            self.cpu.words().get((address / 2) as usize).copied()
+        }
+    }
+
+    // Reads the next packet, acknowledging it, or `None` once the debugger
+    // hangs up. Acks and interrupts between packets are skipped.
+    fn read_packet(&self, stream: &mut TcpStream) -> io::Result<Option<String>> {
+        loop {
+            match read_byte(stream)? {
+                None => return Ok(None),
+                Some(b'$') => break,
+                Some(_) => {}
+            }
+        }
+        let mut data = Vec::new();
+        loop {
+            match read_byte(stream)? {
+                None => return Ok(None),
+                Some(b'#') => break,
+                Some(byte) => data.push(byte),
+            }
+        }
+        let mut checksum = [0; 2];
+        stream.read_exact(&mut checksum)?;
+        let expected = std::str::from_utf8(&checksum)
+            .ok()
+            .and_then(|s| u8::from_str_radix(s, 16).ok());
+        if self.ack {
+            match expected == Some(sum(&data)) {
+                true => stream.write_all(b"+")?,
+                false => {
+                    stream.write_all(b"-")?;
+                    return self.read_packet(stream);
+                }
+            }
+        }
+        Ok(Some(String::from_utf8_lossy(&data).to_string()))
+    }
+
+    fn write_packet(&self, stream: &mut TcpStream, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, sum(data.as_bytes()));
+        stream.write_all(packet.as_bytes())?;
+        stream.flush()
+    }
+}
+
+fn read_byte(stream: &mut TcpStream) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    match stream.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+// Whether the debugger sent a Ctrl-C (0x03) while the program was running.
+fn interrupted(stream: &mut TcpStream) -> io::Result<bool> {
+    stream.set_nonblocking(true)?;
+    let mut byte = [0];
+    let res = match stream.read(&mut byte) {
+        Ok(1) => Ok(byte[0] == 0x03),
+        Ok(_) => Ok(false),
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+        Err(e) => Err(e),
+    };
+    stream.set_nonblocking(false)?;
+    res
+}
+
+// `qXfer:features:read:target.xml:offset,length`
+fn features(args: &str) -> String {
+    let (offset, len) = match address_length(args) {
+        Some((offset, len)) => (offset as usize, len as usize),
+        None => return "E01".to_string(),
+    };
+    if offset >= TARGET_XML.len() {
+        return "l".to_string();
+    }
+    let end = (offset + len).min(TARGET_XML.len());
+    let more = match end < TARGET_XML.len() {
+        true => 'm',
+        false => 'l',
+    };
+    format!("{}{}", more, &TARGET_XML[offset..end])
+}
+
+// `addr,length` in hex.
+fn address_length(args: &str) -> Option<(u32, u32)> {
+    let mut parts = args.splitn(2, ',');
+    let address = u32::from_str_radix(parts.next()?, 16).ok()?;
+    let len = u32::from_str_radix(parts.next()?, 16).ok()?;
+    Some((address, len))
+}
+
+fn sum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,145 @@
+use super::cpu::KBD;
+use super::Machine;
+use std::fs;
+use std::path::Path;
+
+/// A change of the key held down: `key` goes to `KBD` once `cycle` cycles
+/// have run, 0 means no key.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyEvent {
+    pub cycle: u64,
+    pub key: i16,
+}
+
+/// A script of key presses to replay into an emulator.
+///
+/// Events are separated by `;` or newlines, and `#` starts a comment:
+///
+/// ```text
+/// at cycle 10000 press 131   # up arrow
+/// at 20000 release; at 25000 press 'q'
+/// ```
+///
+/// A key is a Hack key code, a quoted character or one of the names
+/// `newline`, `backspace`, `left`, `up`, `right`, `down`, `home`, `end`,
+/// `pageup`, `pagedown`, `insert`, `delete`, `esc`, `space` and `f1` to
+/// `f12`. A key stays pressed until the next event. Cycles are counted from
+/// the start of the program, so a replay does the same thing every run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keyboard {
+    events: Vec<KeyEvent>,
+    next: usize,
+}
+
+impl Keyboard {
+    pub fn new(mut events: Vec<KeyEvent>) -> Self {
+        events.sort_by_key(|event| event.cycle);
+        Keyboard { events, next: 0 }
+    }
+
+    pub fn parse(script: &str) -> Result<Self, String> {
+        let mut events = Vec::new();
+        for (i, line) in script.lines().enumerate() {
+            let line = match line.find('#') {
+                Some(end) => &line[..end],
+                None => line,
+            };
+            for statement in line.split(';') {
+                let words: Vec<&str> = statement.split_whitespace().collect();
+                if words.is_empty() {
+                    continue;
+                }
+                let event = parse_event(&words)
+                    .map_err(|e| format!("line {}: {}: {}", i + 1, statement.trim(), e))?;
+                events.push(event);
+            }
+        }
+        Ok(Self::new(events))
+    }
+
+    pub fn from_file(path: &Path) -> Self {
+        let script = fs::read_to_string(path).unwrap();
+        Self::parse(&script).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+    }
+
+    pub fn events(&self) -> &[KeyEvent] {
+        &self.events
+    }
+
+    /// Runs `machine` for up to `max_cycles` cycles, writing each event to
+    /// `KBD` right before the cycle it is scheduled for. Returns the number
+    /// of cycles run, which is less than `max_cycles` if the program halted.
+    pub fn run<M: Machine>(&mut self, machine: &mut M, max_cycles: u64) -> u64 {
+        let mut total = 0;
+        loop {
+            while let Some(event) = self.events.get(self.next) {
+                if event.cycle > machine.cycles() {
+                    break;
+                }
+                machine.ram_mut()[KBD] = event.key;
+                self.next += 1;
+            }
+            let mut chunk = max_cycles - total;
+            if let Some(event) = self.events.get(self.next) {
+                chunk = chunk.min(event.cycle - machine.cycles());
+            }
+            if chunk == 0 {
+                return total;
+            }
+            let cycles = machine.run(chunk);
+            total += cycles;
+            if cycles < chunk {
+                return total;
+            }
+        }
+    }
+}
+
+fn parse_event(words: &[&str]) -> Result<KeyEvent, String> {
+    let words = match words {
+        ["at", "cycle", rest @ ..] | ["at", rest @ ..] => rest,
+        _ => return Err("expected `at`".to_string()),
+    };
+    let (cycle, key) = match words {
+        [cycle, "press", key] => (cycle, parse_key(key)?),
+        [cycle, "release"] => (cycle, 0),
+        _ => return Err("expected `at <cycle> press <key>` or `at <cycle> release`".to_string()),
+    };
+    let cycle = cycle
+        .parse()
+        .map_err(|_| format!("{} is not a cycle count", cycle))?;
+    Ok(KeyEvent { cycle, key })
+}
+
+fn parse_key(key: &str) -> Result<i16, String> {
+    let code = match key.to_lowercase().as_str() {
+        "newline" | "enter" => 128,
+        "backspace" => 129,
+        "left" => 130,
+        "up" => 131,
+        "right" => 132,
+        "down" => 133,
+        "home" => 134,
+        "end" => 135,
+        "pageup" => 136,
+        "pagedown" => 137,
+        "insert" => 138,
+        "delete" => 139,
+        "esc" => 140,
+        "space" => 32,
+        name if name.len() > 1 && name.starts_with('f') && name[1..].parse::<i16>().is_ok() => {
+            match name[1..].parse::<i16>().unwrap() {
+                n @ 1..=12 => 140 + n,
+                _ => return Err(format!("unknown key {}", key)),
+            }
+        }
+        _ => {
+            let chars: Vec<char> = key.chars().collect();
+            match chars.as_slice() {
+                ['\'', c, '\''] if c.is_ascii() => *c as i16,
+                _ => key.parse().map_err(|_| format!("unknown key {}", key))?,
+            }
+        }
+    };
+    Ok(code)
+}
@@ -0,0 +1,24 @@
+pub mod cpu;
+pub mod debugger;
+pub mod differential;
+pub mod gdb;
+pub mod keyboard;
+pub mod profiler;
+pub mod recompiler;
+pub mod screen;
+pub mod snapshot;
+pub mod trace;
+pub mod vm_emulator;
+
+/// What the tools that drive an emulator from the outside, like keyboard
+/// scripts, need from it.
+pub trait Machine {
+    /// Cycles executed since the program was loaded.
+    fn cycles(&self) -> u64;
+
+    /// Runs until the program halts or `max_cycles` more cycles have been
+    /// executed, returns the number of cycles run.
+    fn run(&mut self, max_cycles: u64) -> u64;
+
+    fn ram_mut(&mut self) -> &mut [i16];
+}
@@ -0,0 +1,265 @@
+use super::cpu::CPU;
+use crate::assembler::table::SymbolTalbe;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+// Where code that is not in any VM function, or before the first label, is
+// accounted.
+const BOOTSTRAP: &str = "(bootstrap)";
+const NO_LABEL: &str = "(no label)";
+
+/// Which VM function each ROM address belongs to, read from the assembly
+/// the VM translator writes with `--annotate`.
+pub struct SourceMap {
+    functions: Vec<(u16, String)>,
+}
+
+impl SourceMap {
+    /// Reads an annotated `.asm` file. Every `// function Name n (...)`
+    /// banner starts a function at the next instruction.
+    pub fn from_asm(path: &Path) -> Self {
+        let text = fs::read_to_string(path).unwrap();
+        Self::parse(&text)
+    }
+
+    pub fn parse(asm: &str) -> Self {
+        let mut functions = Vec::new();
+        let mut address = 0;
+        for line in asm.lines() {
+            let line = line.trim();
+            if let Some(banner) = line.strip_prefix("// function ") {
+                if let Some(name) = banner.split_whitespace().next() {
+                    functions.push((address, name.to_string()));
+                }
+            }
+            let code = match line.find("//") {
+                Some(i) => line[..i].trim(),
+                None => line,
+            };
+            if !code.is_empty() && !code.starts_with('(') {
+                address += 1;
+            }
+        }
+        SourceMap { functions }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty()
+    }
+
+    /// The function the instruction at `address` belongs to.
+    pub fn function_at(&self, address: u16) -> Option<&str> {
+        match self.functions.binary_search_by_key(&address, |(a, _)| *a) {
+            Ok(i) => Some(&self.functions[i].1),
+            Err(0) => None,
+            Err(i) => Some(&self.functions[i - 1].1),
+        }
+    }
+
+    /// The function that starts at `address`, if any.
+    pub fn function_entry(&self, address: u16) -> Option<&str> {
+        self.functions
+            .binary_search_by_key(&address, |(a, _)| *a)
+            .ok()
+            .map(|i| self.functions[i].1.as_str())
+    }
+}
+
+/// Counts the cycles a program spends under each label and, with a source
+/// map, in each VM function.
+///
+/// Calls are followed by watching jumps: a jump to the start of a function
+/// that is followed by a `$ret.` label pushes it, remembering that label as
+/// where the call returns to, and a jump to a remembered return address
+/// pops back to that frame. The stacks seen this way are reported in the
+/// folded format of flamegraph tools.
+pub struct Profiler {
+    labels: Vec<String>,
+    // The index in `labels` of the label every ROM address is under.
+    label_at: Vec<usize>,
+    label_cycles: Vec<u64>,
+    // The `$ret.` labels, which follow the jump of every call.
+    returns: HashSet<u16>,
+    source_map: Option<SourceMap>,
+    function_cycles: HashMap<String, u64>,
+    // Each frame's function and the address its call returns to, if that
+    // is not also where a function starts.
+    stack: Vec<(String, Option<u16>)>,
+    stack_key: String,
+    folded: HashMap<String, u64>,
+    pub cycles: u64,
+}
+
+impl Profiler {
+    pub fn new(symbols: &SymbolTalbe, rom_size: usize, source_map: Option<SourceMap>) -> Self {
+        let is_return = |name: &str| name.contains("$ret.");
+        // At a shared address the last label names the code, so a function
+        // wins over the return label of the call before it.
+        let mut sorted: Vec<(i16, bool, &str)> = symbols
+            .symbols()
+            .filter(|(name, _)| symbols.is_label(name))
+            .map(|(name, address)| (address, !is_return(name), name))
+            .collect();
+        sorted.sort();
+        let returns = sorted
+            .iter()
+            .filter(|(_, _, name)| is_return(name))
+            .map(|(address, _, _)| *address as u16)
+            .collect();
+        let mut labels = vec![NO_LABEL.to_string()];
+        let mut label_at = Vec::with_capacity(rom_size);
+        let mut next = sorted.iter().peekable();
+        for address in 0..rom_size {
+            while let Some((_, _, name)) = next.next_if(|(a, _, _)| *a as usize <= address) {
+                labels.push(name.to_string());
+            }
+            label_at.push(labels.len() - 1);
+        }
+        let source_map = source_map.filter(|map| !map.is_empty());
+        let mut profiler = Profiler {
+            label_cycles: vec![0; labels.len()],
+            labels,
+            label_at,
+            returns,
+            source_map,
+            function_cycles: HashMap::new(),
+            stack: Vec::new(),
+            stack_key: String::new(),
+            folded: HashMap::new(),
+            cycles: 0,
+        };
+        profiler.update_stack_key();
+        profiler
+    }
+
+    /// Runs `cpu` like `CPU::run`, recording every cycle.
+    pub fn run(&mut self, cpu: &mut CPU, max_cycles: u64) -> u64 {
+        let mut cycles = 0;
+        while cycles < max_cycles && !cpu.is_halted() {
+            let pc = cpu.pc;
+            cpu.step();
+            self.record(pc, cpu.pc);
+            cycles += 1;
+        }
+        cycles
+    }
+
+    // Accounts the instruction at `pc`, after which the CPU went to `next`.
+    fn record(&mut self, pc: u16, next: u16) {
+        self.cycles += 1;
+        let label = self.label_at.get(pc as usize).copied().unwrap_or(0);
+        self.label_cycles[label] += 1;
+        let map = match self.source_map.as_ref() {
+            Some(map) => map,
+            None => return,
+        };
+        let function = map.function_at(pc).unwrap_or(BOOTSTRAP);
+        match self.function_cycles.get_mut(function) {
+            Some(cycles) => *cycles += 1,
+            None => {
+                self.function_cycles.insert(function.to_string(), 1);
+            }
+        }
+        match self.folded.get_mut(&self.stack_key) {
+            Some(cycles) => *cycles += 1,
+            None => {
+                self.folded.insert(self.stack_key.clone(), 1);
+            }
+        }
+
+        // Only a jump followed by a return label is a call, not a loop back
+        // to a label at the start of a function. The bootstrap's call falls
+        // through to the first function, so this comes before the check for
+        // a jump, and a jump back there is no return from `Sys.init`.
+        let ret = pc.wrapping_add(1);
+        let call = match self.returns.contains(&ret) {
+            true => map.function_entry(next),
+            false => None,
+        };
+        if let Some(function) = call {
+            let ret = Some(ret).filter(|ret| map.function_entry(*ret).is_none());
+            self.stack.push((function.to_string(), ret));
+        } else if next == ret {
+            return;
+        } else if let Some(i) = self.stack.iter().rposition(|(_, ret)| *ret == Some(next)) {
+            self.stack.truncate(i);
+        } else {
+            return;
+        }
+        self.update_stack_key();
+    }
+
+    fn update_stack_key(&mut self) {
+        let names: Vec<&str> = self.stack.iter().map(|(name, _)| name.as_str()).collect();
+        self.stack_key = match names.is_empty() {
+            true => BOOTSTRAP.to_string(),
+            false => names.join(";"),
+        };
+    }
+
+    /// Cycles per VM function, most expensive first, if there is a source
+    /// map.
+    pub fn functions(&self) -> Vec<(&str, u64)> {
+        let mut res: Vec<(&str, u64)> = self
+            .function_cycles
+            .iter()
+            .map(|(name, cycles)| (name.as_str(), *cycles))
+            .collect();
+        res.sort_by(|x, y| y.1.cmp(&x.1).then(x.0.cmp(y.0)));
+        res
+    }
+
+    /// Cycles per label, most expensive first.
+    pub fn labels(&self) -> Vec<(&str, u64)> {
+        let mut res: Vec<(&str, u64)> = self
+            .labels
+            .iter()
+            .zip(self.label_cycles.iter())
+            .filter(|(_, cycles)| **cycles > 0)
+            .map(|(name, cycles)| (name.as_str(), *cycles))
+            .collect();
+        res.sort_by(|x, y| y.1.cmp(&x.1).then(x.0.cmp(y.0)));
+        res
+    }
+
+    /// A table of cycles per VM function, if there is a source map, and per
+    /// label.
+    pub fn flat(&self) -> String {
+        let table = |out: &mut String, title: &str, rows: Vec<(&str, u64)>| {
+            writeln!(out, "{:>12} {:>7}  {}", "cycles", "%", title).unwrap();
+            for (name, cycles) in rows {
+                let percent = 100.0 * cycles as f64 / self.cycles.max(1) as f64;
+                writeln!(out, "{:>12} {:>6.2}%  {}", cycles, percent, name).unwrap();
+            }
+        };
+        let mut out = String::new();
+        if self.source_map.is_some() {
+            table(&mut out, "function", self.functions());
+            out.push('\n');
+        }
+        table(&mut out, "label", self.labels());
+        out
+    }
+
+    /// One `frame;frame;frame cycles` line per stack, as read by
+    /// `flamegraph.pl` and compatible tools. Without a source map every
+    /// label is a stack of its own.
+    pub fn folded(&self) -> String {
+        let mut stacks: Vec<(&str, u64)> = match self.source_map {
+            Some(_) => self
+                .folded
+                .iter()
+                .map(|(stack, cycles)| (stack.as_str(), *cycles))
+                .collect(),
+            None => self.labels(),
+        };
+        stacks.sort();
+        let mut out = String::new();
+        for (stack, cycles) in stacks {
+            writeln!(out, "{} {}", stack, cycles).unwrap();
+        }
+        out
+    }
+}
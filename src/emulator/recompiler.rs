@@ -0,0 +1,301 @@
+use super::cpu::{Comp, Instruction, Jump, DEST_A, DEST_D, DEST_M};
+use std::fmt::Write;
+use std::ops::Range;
+
+// The ROM is 32K words, so the PC wraps at 2^15 on the hardware, but the
+// emulator's PC is 16 bits wide; the generated code follows the emulator.
+const PC_RANGE: u32 = 1 << 16;
+
+/// Splits a program into basic blocks: runs of instructions that are only
+/// entered at their first instruction and only leave at their last.
+///
+/// A block starts at 0, after every jump and at every address that is
+/// loaded into A by an A-instruction, since any of those may be a jump
+/// target, e.g. the return addresses the VM translator pushes with
+/// `@RET; D=A`. Treating a constant that is never jumped to as a target
+/// only makes blocks shorter.
+pub fn basic_blocks(words: &[u16]) -> Vec<Range<u16>> {
+    let mut leaders = vec![false; words.len()];
+    for (address, word) in words.iter().enumerate() {
+        match Instruction::decode(*word) {
+            Instruction::A(value) => {
+                if let Some(leader) = leaders.get_mut(value as u16 as usize) {
+                    *leader = true;
+                }
+            }
+            Instruction::C { jump, .. } if jump != Jump::Null => {
+                if let Some(leader) = leaders.get_mut(address + 1) {
+                    *leader = true;
+                }
+            }
+            Instruction::C { .. } => {}
+        }
+    }
+    if let Some(leader) = leaders.first_mut() {
+        *leader = true;
+    }
+    let mut blocks = Vec::new();
+    for (address, leader) in leaders.iter().enumerate().skip(1) {
+        if *leader {
+            let start = blocks.last().map_or(0, |block: &Range<u16>| block.end);
+            blocks.push(start..address as u16);
+        }
+    }
+    if !words.is_empty() {
+        let start = blocks.last().map_or(0, |block| block.end);
+        blocks.push(start..words.len() as u16);
+    }
+    blocks
+}
+
+/// Translates a Hack program to a C program that runs it at native speed.
+///
+/// Every basic block becomes a label followed by straight-line C, a jump
+/// whose target is loaded by the A-instruction right before it becomes a
+/// `goto`, and any other jump goes through a `switch` over the block
+/// addresses. The RAM is an array of 32K 16-bit words laid out like the
+/// emulator's, and the program runs, like `CPU::run`, until it halts on an
+/// `@pc; 0;JMP` loop or has run the cycle limit. The limit is checked at
+/// the start of each block, so a run may overshoot it by less than a block.
+///
+/// The generated program is used as
+///
+/// ```text
+/// program [-n cycles] [-o ram.bin] [address=value]...
+/// ```
+///
+/// where the `address=value` pairs set RAM words before the run, and
+/// `-o` writes the final RAM as 32K little-endian words. It prints the
+/// cycles run and the final registers.
+pub fn recompile(words: &[u16]) -> String {
+    let blocks = basic_blocks(words);
+    let mut out = String::new();
+    out.push_str(PRELUDE);
+
+    out.push_str("dispatch:\n");
+    out.push_str("    switch (pc) {\n");
+    for block in &blocks {
+        writeln!(out, "    case {0}: goto L{0};", block.start).unwrap();
+    }
+    out.push_str("    default:\n");
+    writeln!(out, "        if (pc >= {}) goto end;", words.len()).unwrap();
+    out.push_str("        fprintf(stderr, \"jump to %u, which does not start a block\\n\", pc);\n");
+    out.push_str("        return 2;\n");
+    out.push_str("    }\n");
+
+    for block in &blocks {
+        write_block(&mut out, words, block.clone());
+    }
+
+    // Past the program the ROM reads as `@0`, until the PC wraps around.
+    out.push_str("    pc = ");
+    writeln!(out, "{};", words.len()).unwrap();
+    out.push_str("end:\n");
+    out.push_str("    if (cycles >= max) goto done;\n");
+    writeln!(out, "    cycles += {} - pc;", PC_RANGE).unwrap();
+    out.push_str("    a = 0;\n");
+    out.push_str("    pc = 0;\n");
+    out.push_str("    goto dispatch;\n");
+    out.push_str(EPILOGUE);
+    out
+}
+
+fn write_block(out: &mut String, words: &[u16], block: Range<u16>) {
+    let instructions: Vec<Instruction> = words[block.start as usize..block.end as usize]
+        .iter()
+        .map(|word| Instruction::decode(*word))
+        .collect();
+    writeln!(out, "L{}:", block.start).unwrap();
+    writeln!(
+        out,
+        "    if (cycles >= max) {{ pc = {}; goto done; }}",
+        block.start
+    )
+    .unwrap();
+    if is_halt(&instructions, block.start) {
+        writeln!(out, "    pc = {}; goto done;", block.start).unwrap();
+        return;
+    }
+    writeln!(out, "    cycles += {};", instructions.len()).unwrap();
+
+    for (i, instruction) in instructions.iter().enumerate() {
+        let address = block.start as usize + i;
+        writeln!(out, "    /* {}: {} */", address, instruction).unwrap();
+        let (comp, dest, jump) = match *instruction {
+            Instruction::A(value) => {
+                writeln!(out, "    a = {};", value).unwrap();
+                continue;
+            }
+            Instruction::C { comp, dest, jump } => (comp, dest, jump),
+        };
+        // The A-instruction before a jump usually names its target.
+        let target = match i.checked_sub(1).map(|i| instructions[i]) {
+            Some(Instruction::A(value)) => Some(value as u16),
+            _ => None,
+        };
+        out.push_str("    {\n");
+        if dest != 0 || !matches!(jump, Jump::Null | Jump::JMP) {
+            writeln!(out, "        int16_t out = (int16_t)({});", comp_expr(comp)).unwrap();
+        }
+        if jump != Jump::Null && target.is_none() {
+            out.push_str("        uint16_t target = (uint16_t)a;\n");
+        }
+        if dest & DEST_M != 0 {
+            out.push_str("        M = out;\n");
+        }
+        if dest & DEST_D != 0 {
+            out.push_str("        d = out;\n");
+        }
+        if dest & DEST_A != 0 {
+            out.push_str("        a = out;\n");
+        }
+        let goto = match target {
+            Some(target) if (target as usize) < words.len() => format!("goto L{};", target),
+            Some(target) => format!("{{ pc = {}; goto end; }}", target),
+            None => "{ pc = target; goto dispatch; }".to_string(),
+        };
+        match jump_condition(jump) {
+            Some("1") => writeln!(out, "        {}", goto).unwrap(),
+            Some(condition) => writeln!(out, "        if ({}) {}", condition, goto).unwrap(),
+            None => {}
+        }
+        out.push_str("    }\n");
+    }
+}
+
+// Whether the block is the `@pc; 0;JMP` loop programs end with.
+fn is_halt(instructions: &[Instruction], start: u16) -> bool {
+    match instructions {
+        [Instruction::A(value), Instruction::C { jump, .. }, ..] => {
+            *value as u16 == start && *jump == Jump::JMP
+        }
+        _ => false,
+    }
+}
+
+fn comp_expr(comp: Comp) -> String {
+    let s = match comp {
+        Comp::Zero => "0",
+        Comp::One => "1",
+        Comp::MinusOne => "-1",
+        Comp::D => "d",
+        Comp::A => "a",
+        Comp::M => "M",
+        Comp::NotD => "~d",
+        Comp::NotA => "~a",
+        Comp::NotM => "~M",
+        Comp::NegD => "-d",
+        Comp::NegA => "-a",
+        Comp::NegM => "-M",
+        Comp::DPlusOne => "d + 1",
+        Comp::APlusOne => "a + 1",
+        Comp::MPlusOne => "M + 1",
+        Comp::DMinusOne => "d - 1",
+        Comp::AMinusOne => "a - 1",
+        Comp::MMinusOne => "M - 1",
+        Comp::DPlusA => "d + a",
+        Comp::DPlusM => "d + M",
+        Comp::DMinusA => "d - a",
+        Comp::DMinusM => "d - M",
+        Comp::AMinusD => "a - d",
+        Comp::MMinusD => "M - d",
+        Comp::DAndA => "d & a",
+        Comp::DAndM => "d & M",
+        Comp::DOrA => "d | a",
+        Comp::DOrM => "d | M",
+        Comp::Alu(bits) => {
+            let mut x = "d".to_string();
+            let mut y = if bits & 0x40 != 0 { "M" } else { "a" }.to_string();
+            if bits & 0x20 != 0 {
+                x = "0".to_string();
+            }
+            if bits & 0x10 != 0 {
+                x = format!("~{}", x);
+            }
+            if bits & 0x08 != 0 {
+                y = "0".to_string();
+            }
+            if bits & 0x04 != 0 {
+                y = format!("~{}", y);
+            }
+            let out = match bits & 0x02 != 0 {
+                true => format!("({}) + ({})", x, y),
+                false => format!("({}) & ({})", x, y),
+            };
+            return match bits & 0x01 != 0 {
+                true => format!("~({})", out),
+                false => out,
+            };
+        }
+    };
+    s.to_string()
+}
+
+fn jump_condition(jump: Jump) -> Option<&'static str> {
+    match jump {
+        Jump::Null => None,
+        Jump::JGT => Some("out > 0"),
+        Jump::JEQ => Some("out == 0"),
+        Jump::JGE => Some("out >= 0"),
+        Jump::JLT => Some("out < 0"),
+        Jump::JNE => Some("out != 0"),
+        Jump::JLE => Some("out <= 0"),
+        Jump::JMP => Some("1"),
+    }
+}
+
+const PRELUDE: &str = r#"/* Generated from a Hack program by the nand2tetris recompiler. */
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#define RAM_SIZE 32768
+#define M ram[(uint16_t)a & (RAM_SIZE - 1)]
+
+static int16_t ram[RAM_SIZE];
+
+int main(int argc, char **argv) {
+    int16_t a = 0, d = 0;
+    uint16_t pc = 0;
+    uint64_t cycles = 0, max = 10000000;
+    const char *dump = NULL;
+    for (int i = 1; i < argc; i++) {
+        char *eq = strchr(argv[i], '=');
+        if (strcmp(argv[i], "-n") == 0 && i + 1 < argc) {
+            max = strtoull(argv[++i], NULL, 10);
+        } else if (strcmp(argv[i], "-o") == 0 && i + 1 < argc) {
+            dump = argv[++i];
+        } else if (eq != NULL) {
+            long address = strtol(argv[i], NULL, 10);
+            if (address < 0 || address >= RAM_SIZE) {
+                fprintf(stderr, "%s: no such RAM address\n", argv[i]);
+                return 2;
+            }
+            ram[address] = (int16_t)strtol(eq + 1, NULL, 10);
+        } else {
+            fprintf(stderr, "usage: %s [-n cycles] [-o ram.bin] [address=value]...\n", argv[0]);
+            return 2;
+        }
+    }
+
+"#;
+
+const EPILOGUE: &str = r#"done:
+    printf("ran %llu cycles, PC = %u, A = %d, D = %d\n", (unsigned long long)cycles, pc, a, d);
+    if (dump != NULL) {
+        FILE *f = fopen(dump, "wb");
+        if (f == NULL) {
+            perror(dump);
+            return 1;
+        }
+        for (int i = 0; i < RAM_SIZE; i++) {
+            uint16_t word = (uint16_t)ram[i];
+            fputc(word & 0xff, f);
+            fputc(word >> 8, f);
+        }
+        fclose(f);
+    }
+    return 0;
+}
+"#;
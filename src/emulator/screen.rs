@@ -0,0 +1,127 @@
+use super::cpu::SCREEN;
+
+pub const WIDTH: usize = 512;
+pub const HEIGHT: usize = 256;
+/// 16-bit words per screen row.
+pub const ROW_WORDS: usize = WIDTH / 16;
+
+/// Whether the pixel at column `x`, row `y` is black.
+///
+/// Row `y` starts at `RAM[SCREEN + 32 * y]` and the least significant bit of
+/// each word is its leftmost pixel.
+pub fn pixel(ram: &[i16], x: usize, y: usize) -> bool {
+    let word = ram[SCREEN + y * ROW_WORDS + x / 16] as u16;
+    word & (1 << (x % 16)) != 0
+}
+
+/// Encodes the screen as a binary (`P4`) PBM image, where 1 is black.
+pub fn to_pbm(ram: &[i16]) -> Vec<u8> {
+    let mut out = format!("P4\n{} {}\n", WIDTH, HEIGHT).into_bytes();
+    for y in 0..HEIGHT {
+        out.extend(row_bytes(ram, y));
+    }
+    out
+}
+
+/// Encodes the screen as a 1-bit grayscale PNG image.
+///
+/// The pixels are stored in uncompressed deflate blocks, which keeps the
+/// encoder free of dependencies at the cost of a ~17KB file.
+pub fn to_png(ram: &[i16]) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(HEIGHT * (1 + WIDTH / 8));
+    for y in 0..HEIGHT {
+        // Filter type 0, and 0 is black in grayscale.
+        raw.push(0);
+        raw.extend(row_bytes(ram, y).map(|byte| !byte));
+    }
+
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(0xffff).peekable();
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        zlib.push(last as u8);
+        zlib.extend(&len.to_le_bytes());
+        zlib.extend(&(!len).to_le_bytes());
+        zlib.extend(block);
+    }
+    zlib.extend(&adler32(&raw).to_be_bytes());
+
+    let mut header = Vec::new();
+    header.extend(&(WIDTH as u32).to_be_bytes());
+    header.extend(&(HEIGHT as u32).to_be_bytes());
+    // Bit depth 1, grayscale, deflate, no filtering, no interlace.
+    header.extend(&[1, 0, 0, 0, 0]);
+
+    let mut out = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+    png_chunk(&mut out, b"IHDR", &header);
+    png_chunk(&mut out, b"IDAT", &zlib);
+    png_chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+/// Draws the screen with Unicode half blocks, two pixel rows per line.
+///
+/// Every `scale` x `scale` square of pixels becomes one half block that is
+/// drawn if any of its pixels is black, so `scale` 1 needs a 512 column
+/// terminal and `scale` 4 fits in 128 columns.
+pub fn to_terminal(ram: &[i16], scale: usize) -> String {
+    let black = |x: usize, y: usize| {
+        (y..(y + scale).min(HEIGHT)).any(|y| (x..(x + scale).min(WIDTH)).any(|x| pixel(ram, x, y)))
+    };
+    let mut out = String::new();
+    for y in (0..HEIGHT).step_by(2 * scale) {
+        for x in (0..WIDTH).step_by(scale) {
+            out.push(match (black(x, y), black(x, y + scale)) {
+                (false, false) => ' ',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (true, true) => '█',
+            });
+        }
+        out.push('\n');
+    }
+    out
+}
+
+// The pixels of row `y`, leftmost pixel in the most significant bit.
+fn row_bytes(ram: &[i16], y: usize) -> impl Iterator<Item = u8> + '_ {
+    let row = &ram[SCREEN + y * ROW_WORDS..SCREEN + (y + 1) * ROW_WORDS];
+    row.iter().flat_map(|word| {
+        let word = (*word as u16).reverse_bits();
+        word.to_be_bytes().to_vec()
+    })
+}
+
+fn png_chunk(out: &mut Vec<u8>, kind: &[u8], data: &[u8]) {
+    out.extend(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend(kind);
+    out.extend(data);
+    let crc = crc32(&out[start..]);
+    out.extend(&crc.to_be_bytes());
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in bytes {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
@@ -0,0 +1,224 @@
+//! Saving and restoring the complete state of an emulator.
+//!
+//! A snapshot is a text file. Blank lines and lines starting with `#` are
+//! ignored, every other line is a keyword followed by its values:
+//!
+//! ```text
+//! nand2tetris snapshot 1     first line, the format version
+//! machine cpu                `cpu` or `vm`
+//! cycles 5000000             cycles run so far
+//! a 16384                    cpu only: the A, D and PC registers
+//! d -1
+//! pc 7246
+//! rom 3                      cpu only: the number of ROM words, followed
+//! 0010 ec10 0003             by the words in hex, any number per line
+//! pc 42                      vm only: the index of the next command
+//! file Main.vm 2             vm only: a file and its number of commands,
+//! 3 push constant 7          each one preceded by its source line
+//! 4 return
+//! ram 256 0003 fffe          RAM words in hex from the given address on
+//! ```
+//!
+//! RAM words that are not listed are 0. Everything else the VM emulator
+//! needs, the segment pointers and the call stack, lives in its RAM, so it
+//! is restored along with it; `save_vm` lists the call stack in a comment
+//! for whoever reads the file. Loading a snapshot and running on gives
+//! exactly the run that was saved.
+
+use super::cpu::{CPU, RAM_SIZE};
+use super::vm_emulator::VMEmulator;
+use crate::vm::parser::SourceCommand;
+use std::fmt::Write;
+
+const HEADER: &str = "nand2tetris snapshot 1";
+const WORDS_PER_LINE: usize = 16;
+
+pub fn save_cpu(cpu: &CPU) -> String {
+    let mut out = String::new();
+    writeln!(out, "{}", HEADER).unwrap();
+    writeln!(out, "machine cpu").unwrap();
+    writeln!(out, "cycles {}", cpu.cycles).unwrap();
+    writeln!(out, "a {}", cpu.a).unwrap();
+    writeln!(out, "d {}", cpu.d).unwrap();
+    writeln!(out, "pc {}", cpu.pc).unwrap();
+    writeln!(out, "rom {}", cpu.words().len()).unwrap();
+    for line in cpu.words().chunks(WORDS_PER_LINE) {
+        let words: Vec<String> = line.iter().map(|w| format!("{:04x}", w)).collect();
+        writeln!(out, "{}", words.join(" ")).unwrap();
+    }
+    write_ram(&mut out, &cpu.ram);
+    out
+}
+
+pub fn load_cpu(text: &str) -> Result<CPU, String> {
+    let mut lines = Lines::new(text, "cpu")?;
+    let cycles = lines.value("cycles")?;
+    let a = lines.value("a")?;
+    let d = lines.value("d")?;
+    let pc = lines.value("pc")?;
+    let count: usize = lines.value("rom")?;
+    let mut words = Vec::with_capacity(count);
+    while words.len() < count {
+        let (number, line) = lines.next().ok_or("the ROM is cut short")?;
+        for word in line.split_whitespace() {
+            words.push(hex(word).map_err(|e| format!("line {}: {}", number, e))?);
+        }
+    }
+    if words.len() != count {
+        return Err(format!("expected {} ROM words, got {}", count, words.len()));
+    }
+    let mut cpu = CPU::new(words);
+    cpu.ram = lines.ram()?;
+    cpu.a = a;
+    cpu.d = d;
+    cpu.pc = pc;
+    cpu.cycles = cycles;
+    Ok(cpu)
+}
+
+pub fn save_vm(vm: &VMEmulator) -> String {
+    let mut out = String::new();
+    writeln!(out, "{}", HEADER).unwrap();
+    writeln!(out, "machine vm").unwrap();
+    writeln!(out, "cycles {}", vm.cycles).unwrap();
+    writeln!(out, "pc {}", vm.pc).unwrap();
+    writeln!(out, "# call stack: {}", vm.call_stack().join(" > ")).unwrap();
+    for (name, commands) in vm.program() {
+        writeln!(out, "file {} {}", name, commands.len()).unwrap();
+        for command in commands {
+            writeln!(out, "{} {}", command.line, command.command).unwrap();
+        }
+    }
+    write_ram(&mut out, &vm.ram);
+    out
+}
+
+pub fn load_vm(text: &str) -> Result<VMEmulator, String> {
+    let mut lines = Lines::new(text, "vm")?;
+    let cycles = lines.value("cycles")?;
+    let pc = lines.value("pc")?;
+    let mut files = Vec::new();
+    while lines.peek_keyword() == Some("file") {
+        let (number, line) = lines.next().unwrap();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (name, count) = match words.as_slice() {
+            ["file", name, count] => (name.to_string(), count),
+            _ => return Err(format!("line {}: expected `file <name> <count>`", number)),
+        };
+        let count: usize = count
+            .parse()
+            .map_err(|_| format!("line {}: {} is not a count", number, count))?;
+        let mut commands = Vec::with_capacity(count);
+        for _ in 0..count {
+            let (number, line) = lines.next().ok_or("the program is cut short")?;
+            let (source_line, command) = line.split_at(line.find(' ').unwrap_or(0));
+            let source_line = source_line
+                .parse()
+                .map_err(|_| format!("line {}: expected `<line> <command>`", number))?;
+            let command = command
+                .parse()
+                .map_err(|e| format!("line {}: {}", number, e))?;
+            commands.push(SourceCommand::new(source_line, command));
+        }
+        files.push((name, commands));
+    }
+    let mut vm = VMEmulator::from_files(files);
+    vm.ram = lines.ram()?;
+    vm.pc = pc;
+    vm.cycles = cycles;
+    Ok(vm)
+}
+
+fn write_ram(out: &mut String, ram: &[i16]) {
+    let mut address = 0;
+    while address < ram.len() {
+        if ram[address] == 0 {
+            address += 1;
+            continue;
+        }
+        let start = address;
+        while address < ram.len() && address - start < WORDS_PER_LINE && ram[address] != 0 {
+            address += 1;
+        }
+        let words: Vec<String> = ram[start..address]
+            .iter()
+            .map(|w| format!("{:04x}", *w as u16))
+            .collect();
+        writeln!(out, "ram {} {}", start, words.join(" ")).unwrap();
+    }
+}
+
+fn hex(word: &str) -> Result<u16, String> {
+    u16::from_str_radix(word, 16).map_err(|_| format!("{} is not a hex word", word))
+}
+
+// The meaningful lines of a snapshot, with their 1-based numbers.
+struct Lines<'a> {
+    lines: std::iter::Peekable<Box<dyn Iterator<Item = (usize, &'a str)> + 'a>>,
+}
+
+impl<'a> Lines<'a> {
+    fn new(text: &'a str, machine: &str) -> Result<Self, String> {
+        let lines: Box<dyn Iterator<Item = (usize, &'a str)>> = Box::new(
+            text.lines()
+                .enumerate()
+                .map(|(i, line)| (i + 1, line.trim()))
+                .filter(|(_, line)| !line.is_empty() && !line.starts_with('#')),
+        );
+        let mut lines = Lines {
+            lines: lines.peekable(),
+        };
+        match lines.next() {
+            Some((_, HEADER)) => {}
+            _ => return Err(format!("not a snapshot, expected `{}`", HEADER)),
+        }
+        let found: String = lines.value("machine")?;
+        if found != machine {
+            return Err(format!("a {} snapshot, expected a {} one", found, machine));
+        }
+        Ok(lines)
+    }
+
+    fn next(&mut self) -> Option<(usize, &'a str)> {
+        self.lines.next()
+    }
+
+    fn peek_keyword(&mut self) -> Option<&'a str> {
+        self.lines
+            .peek()
+            .and_then(|(_, line)| line.split_whitespace().next())
+    }
+
+    // The value of a `<keyword> <value>` line.
+    fn value<T: std::str::FromStr>(&mut self, keyword: &str) -> Result<T, String> {
+        let (number, line) = self
+            .next()
+            .ok_or_else(|| format!("expected `{}`, got the end of the file", keyword))?;
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (Some(word), Some(value), None) if word == keyword => value
+                .parse()
+                .map_err(|_| format!("line {}: {} is not a valid {}", number, value, keyword)),
+            _ => Err(format!("line {}: expected `{} <value>`", number, keyword)),
+        }
+    }
+
+    // The `ram` lines up to the end of the file.
+    fn ram(&mut self) -> Result<Vec<i16>, String> {
+        let mut ram = vec![0; RAM_SIZE];
+        for (number, line) in &mut self.lines {
+            let mut words = line.split_whitespace();
+            let start: usize = match (words.next(), words.next().map(|w| w.parse())) {
+                (Some("ram"), Some(Ok(start))) => start,
+                _ => return Err(format!("line {}: expected `ram <address> <words>`", number)),
+            };
+            for (i, word) in words.enumerate() {
+                let word = hex(word).map_err(|e| format!("line {}: {}", number, e))?;
+                *ram.get_mut(start + i)
+                    .ok_or_else(|| format!("line {}: past the end of the RAM", number))? =
+                    word as i16;
+            }
+        }
+        Ok(ram)
+    }
+}
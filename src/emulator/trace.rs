@@ -0,0 +1,32 @@
+use super::cpu::CPU;
+use std::io::prelude::*;
+
+/// The first line of a trace, naming its columns.
+pub const HEADER: &str = "# pc instruction A D [address=value]";
+
+/// Runs `cpu` like `CPU::run` while writing one line per instruction to
+/// `out`: its address, the instruction, A and D after executing it and, if
+/// it wrote to the RAM, the address and the value written, e.g.
+///
+/// ```text
+/// 3 M=D 0 256 0=256
+/// ```
+///
+/// Returns the number of cycles run.
+pub fn trace<W: Write>(cpu: &mut CPU, max_cycles: u64, out: &mut W) -> u64 {
+    writeln!(out, "{}", HEADER).unwrap();
+    let mut cycles = 0;
+    while cycles < max_cycles && !cpu.is_halted() {
+        let pc = cpu.pc;
+        let instruction = cpu.instruction(pc);
+        let written = cpu.step();
+        write!(out, "{} {} {} {}", pc, instruction, cpu.a, cpu.d).unwrap();
+        if let Some(address) = written {
+            write!(out, " {}={}", address, cpu.ram[address as usize]).unwrap();
+        }
+        writeln!(out).unwrap();
+        cycles += 1;
+    }
+    out.flush().unwrap();
+    cycles
+}
@@ -0,0 +1,416 @@
+use super::cpu::RAM_SIZE;
+use super::Machine;
+use crate::vm::parser::{Command, Parser, SourceCommand};
+use crate::vm::vm_files;
+use std::collections::HashMap;
+use std::path::Path;
+
+const SP: usize = 0;
+const LCL: usize = 1;
+const ARG: usize = 2;
+const THIS: usize = 3;
+const THAT: usize = 4;
+
+// A jump target that the program does not define.
+const UNDEFINED: usize = usize::MAX;
+
+#[derive(Debug, Clone, Copy)]
+enum Address {
+    Constant,
+    /// Relative to the base address held in RAM[register].
+    Based(usize),
+    /// pointer, temp and static.
+    Fixed(usize),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Push(Address, i16),
+    Pop(Address, i16),
+    Add,
+    Sub,
+    Neg,
+    Eq,
+    Gt,
+    Lt,
+    And,
+    Or,
+    Not,
+    Label,
+    Goto(usize),
+    If(usize),
+    Function(u16),
+    Call(usize, u16),
+    Return,
+}
+
+/// An interpreter for VM programs.
+///
+/// It uses the RAM layout of the Hack translation: SP, LCL, ARG, THIS and
+/// THAT at 0-4, temp at 5-12, statics from 16 in order of first use, the
+/// stack from 256, and the screen and keyboard at their Hack addresses, so
+/// the same program can be driven and inspected the same way on both
+/// emulators. Every VM command, labels included, takes one cycle. Labels
+/// are scoped to the function they appear in.
+///
+/// Like the translator, loading calls `Sys.init` if the program has one.
+/// The program halts when it returns from there or reaches a
+/// `label L; goto L` loop.
+pub struct VMEmulator {
+    ops: Vec<Op>,
+    files: Vec<String>,
+    source: Vec<(usize, SourceCommand)>,
+    functions: HashMap<String, usize>,
+    statics: HashMap<String, usize>,
+    pub ram: Vec<i16>,
+    pub pc: usize,
+    pub cycles: u64,
+}
+
+impl VMEmulator {
+    /// Loads `path`, a `.vm` file or a directory of them.
+    pub fn new(path: &Path) -> Self {
+        let files = vm_files(path)
+            .iter()
+            .map(|file| {
+                let name = file.file_name().unwrap().to_string_lossy().to_string();
+                (name, Parser::new(file).commands())
+            })
+            .collect();
+        Self::from_files(files)
+    }
+
+    /// Loads a program given as `(filename, commands)` pairs.
+    pub fn from_files(files: Vec<(String, Vec<SourceCommand>)>) -> Self {
+        let mut names = Vec::new();
+        let mut source = Vec::new();
+        for (id, (name, commands)) in files.into_iter().enumerate() {
+            names.push(name);
+            source.extend(commands.into_iter().map(|command| (id, command)));
+        }
+
+        // Function entries and labels, scoped by function.
+        let mut functions = HashMap::new();
+        let mut labels = HashMap::new();
+        let mut function = String::new();
+        for (pc, (_, command)) in source.iter().enumerate() {
+            match &command.command {
+                Command::Function(name, _) => {
+                    functions.insert(name.clone(), pc);
+                    function = name.clone();
+                }
+                Command::Label(label) => {
+                    labels.insert(format!("{}${}", function, label), pc);
+                }
+                _ => {}
+            }
+        }
+
+        let mut statics = HashMap::new();
+        let mut ops = Vec::new();
+        let mut function = String::new();
+        for (file, command) in source.iter() {
+            let mut address = |segment: &str, index: i32| match segment {
+                "constant" => Address::Constant,
+                "local" => Address::Based(LCL),
+                "argument" => Address::Based(ARG),
+                "this" => Address::Based(THIS),
+                "that" => Address::Based(THAT),
+                "pointer" => Address::Fixed(THIS + index as usize),
+                "temp" => Address::Fixed(5 + index as usize),
+                "static" => {
+                    let count = statics.len();
+                    let name = format!("{}.{}", names[*file], index);
+                    Address::Fixed(*statics.entry(name).or_insert(16 + count))
+                }
+                _ => panic!("{} is not a valid segment string", segment),
+            };
+            let label = |label: &str| {
+                *labels
+                    .get(&format!("{}${}", function, label))
+                    .unwrap_or(&UNDEFINED)
+            };
+            let op = match &command.command {
+                Command::Arithmetic(command) => match command.as_str() {
+                    "add" => Op::Add,
+                    "sub" => Op::Sub,
+                    "neg" => Op::Neg,
+                    "eq" => Op::Eq,
+                    "gt" => Op::Gt,
+                    "lt" => Op::Lt,
+                    "and" => Op::And,
+                    "or" => Op::Or,
+                    "not" => Op::Not,
+                    _ => panic!("{} is not a valid arithmetic string", command),
+                },
+                Command::Push(segment, index) => Op::Push(address(segment, *index), *index as i16),
+                Command::Pop(segment, index) => Op::Pop(address(segment, *index), *index as i16),
+                Command::Label(_) => Op::Label,
+                Command::Goto(target) => Op::Goto(label(target)),
+                Command::If(target) => Op::If(label(target)),
+                Command::Function(name, num_vars) => {
+                    function = name.clone();
+                    Op::Function(*num_vars as u16)
+                }
+                Command::Return => Op::Return,
+                Command::Call(name, num_args) => {
+                    Op::Call(*functions.get(name).unwrap_or(&UNDEFINED), *num_args as u16)
+                }
+            };
+            ops.push(op);
+        }
+
+        let mut emulator = VMEmulator {
+            ops,
+            files: names,
+            source,
+            functions,
+            statics,
+            ram: vec![0; RAM_SIZE],
+            pc: 0,
+            cycles: 0,
+        };
+        emulator.reset();
+        emulator
+    }
+
+    /// Clears the cycle count and calls `Sys.init` again, or starts over at
+    /// the first command if there is none. The rest of the RAM is kept.
+    pub fn reset(&mut self) {
+        self.cycles = 0;
+        self.ram[SP] = 256;
+        self.pc = 0;
+        if let Some(&init) = self.functions.get("Sys.init") {
+            self.call(init, 0, self.ops.len());
+        }
+    }
+
+    /// The program as loaded, as `(filename, commands)` pairs.
+    pub fn program(&self) -> Vec<(String, Vec<SourceCommand>)> {
+        let mut files: Vec<(String, Vec<SourceCommand>)> = self
+            .files
+            .iter()
+            .map(|name| (name.clone(), Vec::new()))
+            .collect();
+        for (file, command) in self.source.iter() {
+            files[*file].1.push(command.clone());
+        }
+        files
+    }
+
+    /// The static variables, e.g. `Main.vm.0`, and their addresses in
+    /// address order.
+    pub fn statics(&self) -> Vec<(&str, usize)> {
+        let mut statics: Vec<(&str, usize)> = self
+            .statics
+            .iter()
+            .map(|(name, address)| (name.as_str(), *address))
+            .collect();
+        statics.sort_by_key(|(_, address)| *address);
+        statics
+    }
+
+    /// The functions on the call stack, outermost first, found by walking
+    /// the saved LCL chain from the current frame.
+    pub fn call_stack(&self) -> Vec<String> {
+        let mut entries: Vec<(usize, &str)> = self
+            .functions
+            .iter()
+            .map(|(name, pc)| (*pc, name.as_str()))
+            .collect();
+        entries.sort();
+        let function_at = |pc: usize| {
+            entries
+                .iter()
+                .rev()
+                .find(|(entry, _)| *entry <= pc)
+                .map(|(_, name)| name.to_string())
+        };
+        let mut stack = Vec::new();
+        let mut pc = self.pc;
+        let mut frame = self.ram[LCL] as u16 as usize;
+        while pc < self.ops.len() && stack.len() < RAM_SIZE {
+            match function_at(pc) {
+                Some(name) => stack.push(name),
+                None => break,
+            }
+            if frame < 5 {
+                break;
+            }
+            pc = self.ram[frame - 5] as u16 as usize;
+            frame = self.ram[frame - 4] as u16 as usize;
+        }
+        stack.reverse();
+        stack
+    }
+
+    /// The command at `pc` and where it came from, e.g. `Main.vm:3`.
+    pub fn source(&self, pc: usize) -> Option<(String, &Command)> {
+        self.source.get(pc).map(|(file, command)| {
+            (
+                format!("{}:{}", self.files[*file], command.line),
+                &command.command,
+            )
+        })
+    }
+
+    /// Whether the program returned from `Sys.init` or sits on a
+    /// `label L; goto L` loop.
+    pub fn is_halted(&self) -> bool {
+        match self.ops.get(self.pc) {
+            None => true,
+            Some(Op::Goto(target)) => {
+                *target <= self.pc
+                    && self.ops[*target..self.pc]
+                        .iter()
+                        .all(|op| matches!(op, Op::Label))
+            }
+            Some(_) => false,
+        }
+    }
+
+    /// Runs until the program halts or `max_cycles` more commands have been
+    /// executed, returns the number of commands run.
+    pub fn run(&mut self, max_cycles: u64) -> u64 {
+        let mut cycles = 0;
+        while cycles < max_cycles && !self.is_halted() {
+            self.step();
+            cycles += 1;
+        }
+        cycles
+    }
+
+    /// Executes one command.
+    pub fn step(&mut self) {
+        let op = self.ops[self.pc];
+        self.cycles += 1;
+        self.pc += 1;
+        match op {
+            Op::Push(address, index) => {
+                let value = match address {
+                    Address::Constant => index,
+                    _ => self.ram[self.address(address, index)],
+                };
+                self.push(value);
+            }
+            Op::Pop(address, index) => {
+                let value = self.pop();
+                let address = match address {
+                    Address::Constant => self.fail("cannot pop to constant"),
+                    _ => self.address(address, index),
+                };
+                self.ram[address] = value;
+            }
+            Op::Add => self.binary(|x, y| x.wrapping_add(y)),
+            Op::Sub => self.binary(|x, y| x.wrapping_sub(y)),
+            Op::And => self.binary(|x, y| x & y),
+            Op::Or => self.binary(|x, y| x | y),
+            Op::Eq => self.binary(|x, y| -((x == y) as i16)),
+            Op::Gt => self.binary(|x, y| -((x > y) as i16)),
+            Op::Lt => self.binary(|x, y| -((x < y) as i16)),
+            Op::Neg => {
+                let x = self.pop();
+                self.push(x.wrapping_neg());
+            }
+            Op::Not => {
+                let x = self.pop();
+                self.push(!x);
+            }
+            Op::Label => {}
+            Op::Goto(target) => self.jump(target),
+            Op::If(target) => {
+                if self.pop() != 0 {
+                    self.jump(target);
+                }
+            }
+            Op::Function(num_vars) => {
+                for _ in 0..num_vars {
+                    self.push(0);
+                }
+            }
+            Op::Call(target, num_args) => {
+                if target == UNDEFINED {
+                    self.fail("call to an undefined function");
+                }
+                self.call(target, num_args, self.pc);
+            }
+            Op::Return => {
+                let frame = self.ram[LCL] as u16 as usize;
+                let ret = self.ram[frame.wrapping_sub(5) & (RAM_SIZE - 1)];
+                let value = self.pop();
+                let arg = self.ram[ARG];
+                self.ram[arg as u16 as usize & (RAM_SIZE - 1)] = value;
+                self.ram[SP] = arg.wrapping_add(1);
+                for (i, register) in [THAT, THIS, ARG, LCL].iter().enumerate() {
+                    self.ram[*register] = self.ram[frame.wrapping_sub(1 + i) & (RAM_SIZE - 1)];
+                }
+                self.pc = ret as u16 as usize;
+            }
+        }
+    }
+
+    fn call(&mut self, target: usize, num_args: u16, ret: usize) {
+        self.push(ret as i16);
+        for register in [LCL, ARG, THIS, THAT].iter() {
+            self.push(self.ram[*register]);
+        }
+        self.ram[ARG] = self.ram[SP].wrapping_sub(5 + num_args as i16);
+        self.ram[LCL] = self.ram[SP];
+        self.pc = target;
+    }
+
+    fn jump(&mut self, target: usize) {
+        if target == UNDEFINED {
+            self.fail("jump to an undefined label");
+        }
+        self.pc = target;
+    }
+
+    fn address(&self, address: Address, index: i16) -> usize {
+        let address = match address {
+            Address::Based(register) => self.ram[register].wrapping_add(index),
+            Address::Fixed(address) => address as i16,
+            Address::Constant => unreachable!(),
+        };
+        address as u16 as usize & (RAM_SIZE - 1)
+    }
+
+    fn push(&mut self, value: i16) {
+        let sp = self.ram[SP];
+        self.ram[sp as u16 as usize & (RAM_SIZE - 1)] = value;
+        self.ram[SP] = sp.wrapping_add(1);
+    }
+
+    fn pop(&mut self) -> i16 {
+        let sp = self.ram[SP].wrapping_sub(1);
+        self.ram[SP] = sp;
+        self.ram[sp as u16 as usize & (RAM_SIZE - 1)]
+    }
+
+    fn binary<F: Fn(i16, i16) -> i16>(&mut self, f: F) {
+        let y = self.pop();
+        let x = self.pop();
+        self.push(f(x, y));
+    }
+
+    fn fail(&self, message: &str) -> ! {
+        match self.source(self.pc - 1) {
+            Some((location, command)) => panic!("{}: {}: {}", location, command, message),
+            None => panic!("{}", message),
+        }
+    }
+}
+
+impl Machine for VMEmulator {
+    fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    fn run(&mut self, max_cycles: u64) -> u64 {
+        VMEmulator::run(self, max_cycles)
+    }
+
+    fn ram_mut(&mut self) -> &mut [i16] {
+        &mut self.ram
+    }
+}
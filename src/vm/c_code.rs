@@ -0,0 +1,323 @@
+use super::parser::CommandType;
+use super::writer::CodeWriter;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::path::Path;
+
+const PRELUDE: &str = r#"/* Generated by the nand2tetris VM translator. */
+#include <setjmp.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+int16_t RAM[32768];
+
+#define SP RAM[0]
+#define LCL RAM[1]
+#define ARG RAM[2]
+#define THIS RAM[3]
+#define THAT RAM[4]
+#define M(addr) RAM[(uint16_t)(addr) & 0x7fff]
+#define PUSH(v) (M(SP) = (v), SP++)
+#define POP() (SP--, M(SP))
+#define TOP M(SP - 1)
+#define BINARY(op) (SP--, TOP = (int16_t)(uint16_t)((int32_t)TOP op (int32_t)M(SP)))
+#define COMPARE(op) (SP--, TOP = (TOP op M(SP)) ? -1 : 0)
+
+/* Jumps and calls left before giving up, 0 for no limit. */
+static long long fuel = 0;
+/* Where `run` resumes once the program halts, however deep the call. */
+static jmp_buf halted;
+/* The frame being returned from, like R13 in the Hack translation. */
+static int16_t frame;
+#define HALT() longjmp(halted, 1)
+#define SPEND() do { if (fuel && !--fuel) HALT(); } while (0)
+#define JUMP(label) do { SPEND(); goto label; } while (0)
+#define CALL(f) do { SPEND(); f(); } while (0)
+"#;
+
+const EPILOGUE: &str = r#"
+int main(int argc, char **argv)
+{
+    int i;
+    for (i = 1; i + 1 < argc; i++) {
+        if (strcmp(argv[i], "-n") == 0) {
+            fuel = atoll(argv[++i]);
+        }
+    }
+    run();
+    for (i = 1; i < argc; i++) {
+        if (strcmp(argv[i], "-n") == 0) {
+            i++;
+        } else {
+            int addr = atoi(argv[i]) & 0x7fff;
+            printf("RAM[%d] = %d\n", addr, RAM[addr]);
+        }
+    }
+    return 0;
+}
+"#;
+
+/// A backend that turns a whole VM program into one portable C file.
+///
+/// RAM is an `int16_t[32768]` with the usual Hack layout (SP, LCL, ARG,
+/// THIS, THAT at 0-4, temp at 5-12, statics from 16, stack from 256), so a
+/// run can be compared word by word with the CPU emulator. Every VM
+/// function becomes a C function whose labels are C labels: `call` still
+/// pushes the saved frame onto the Hack stack but then calls the C
+/// function, and `return` restores the frame and returns from it. The
+/// bootstrap code goes into `run`.
+///
+/// The generated `main` accepts `-n <jumps>` to bound the run and a list of
+/// RAM addresses to print once the program halts. A program halts when it
+/// returns from `Sys.init` or reaches a `label L; goto L` loop.
+pub struct CCode {
+    writer: BufWriter<File>,
+    filename: String,
+    // C function ids by VM function name, and the ones the program defines.
+    functions: HashMap<String, usize>,
+    defined_functions: HashSet<String>,
+    // The C text of the functions written so far, and of `run`.
+    out: String,
+    run: String,
+    // The VM function being written, `None` for the bootstrap code, with
+    // its body and its C label ids by VM label.
+    function: Option<String>,
+    body: Vec<String>,
+    labels: HashMap<String, usize>,
+    defined: HashSet<String>,
+    statics: HashMap<String, usize>,
+    call_count: usize,
+    last_label: Option<String>,
+}
+
+impl CCode {
+    pub fn new(path: &Path) -> Self {
+        let file = File::create(path).unwrap();
+        let writer = BufWriter::new(file);
+        CCode {
+            writer,
+            filename: String::new(),
+            functions: HashMap::new(),
+            defined_functions: HashSet::new(),
+            out: String::new(),
+            run: String::new(),
+            function: None,
+            body: Vec::new(),
+            labels: HashMap::new(),
+            defined: HashSet::new(),
+            statics: HashMap::new(),
+            call_count: 0,
+            last_label: None,
+        }
+    }
+
+    fn emit(&mut self, line: String) {
+        self.body.push(format!("    {}", line));
+        self.last_label = None;
+    }
+
+    fn label(&mut self, name: &str) -> String {
+        let count = self.labels.len();
+        let id = *self.labels.entry(name.to_string()).or_insert(count);
+        format!("L{}", id)
+    }
+
+    fn define(&mut self, name: &str) {
+        let label = self.label(name);
+        self.body.push(format!("{}:; /* {} */", label, name));
+        self.defined.insert(name.to_string());
+        self.last_label = Some(name.to_string());
+    }
+
+    fn function_id(&mut self, name: &str) -> String {
+        let count = self.functions.len();
+        let id = *self.functions.entry(name.to_string()).or_insert(count);
+        format!("F{}", id)
+    }
+
+    // Turns the body written so far into a C function, ending it with a
+    // jump target for every label it uses but does not define.
+    fn close(&mut self) {
+        let mut text = match self.function.clone() {
+            Some(name) => format!(
+                "\nstatic void {}(void) /* {} */\n{{\n",
+                self.function_id(&name),
+                name
+            ),
+            None => {
+                "\nstatic void run(void)\n{\n    if (setjmp(halted)) {\n        return;\n    }\n"
+                    .to_string()
+            }
+        };
+        let last = self.body.last().map(|line| line.trim());
+        let halt = self.function.is_some() && last != Some("return;") && last != Some("HALT();");
+        for line in self.body.drain(..) {
+            text.push_str(&line);
+            text.push('\n');
+        }
+        // Falling off the end of a function halts, as running past the
+        // last command does.
+        if halt {
+            text.push_str("    HALT();\n");
+        }
+        let mut undefined: Vec<(&String, &usize)> = self
+            .labels
+            .iter()
+            .filter(|(name, _)| !self.defined.contains(*name))
+            .collect();
+        undefined.sort_by_key(|(_, id)| **id);
+        for (name, id) in undefined {
+            text.push_str(&format!(
+                "L{}:\n    fprintf(stderr, \"undefined label {}\\n\");\n    exit(1);\n",
+                id, name
+            ));
+        }
+        text.push_str("}\n");
+        match self.function {
+            Some(_) => self.out.push_str(&text),
+            None => self.run.push_str(&text),
+        }
+        self.labels.clear();
+        self.defined.clear();
+        self.last_label = None;
+    }
+
+    fn address(&mut self, segment: &str, index: i32) -> String {
+        match segment {
+            "local" => format!("M(LCL + {})", index),
+            "argument" => format!("M(ARG + {})", index),
+            "this" => format!("M(THIS + {})", index),
+            "that" => format!("M(THAT + {})", index),
+            "pointer" => match index {
+                0 => "THIS".to_string(),
+                1 => "THAT".to_string(),
+                _ => panic!("Not a valid index, must be 0 or 1 for Pointer"),
+            },
+            "temp" => format!("RAM[{}]", index + 5),
+            "static" => {
+                let name = format!("{}.{}", self.filename, index);
+                let count = self.statics.len();
+                let address = *self.statics.entry(name).or_insert(16 + count);
+                format!("RAM[{}]", address)
+            }
+            _ => panic!("{} is not a valid segment string", segment),
+        }
+    }
+}
+
+impl CodeWriter for CCode {
+    fn set_filename(&mut self, filename: &str) {
+        self.filename = filename.to_string();
+    }
+
+    fn write_init(&mut self) {
+        self.emit("SP = 256;".to_string());
+        self.write_call("Sys.init", 0);
+    }
+
+    fn write_arithmetic(&mut self, command: &str) {
+        let line = match command {
+            "add" => "BINARY(+);",
+            "sub" => "BINARY(-);",
+            "and" => "BINARY(&);",
+            "or" => "BINARY(|);",
+            "eq" => "COMPARE(==);",
+            "gt" => "COMPARE(>);",
+            "lt" => "COMPARE(<);",
+            "neg" => "TOP = (int16_t)(uint16_t)(-(int32_t)TOP);",
+            "not" => "TOP = ~TOP;",
+            _ => panic!("{} is not a valid arithmetic string", command),
+        };
+        self.emit(line.to_string());
+    }
+
+    fn write_push_pop(&mut self, command: CommandType, segment: &str, index: i32) {
+        let line = match command {
+            CommandType::PUSH if segment == "constant" => format!("PUSH({});", index),
+            CommandType::PUSH => format!("PUSH({});", self.address(segment, index)),
+            CommandType::POP => format!("{} = POP();", self.address(segment, index)),
+            _ => panic!("Invalid command, must be one of PUSH or POP!"),
+        };
+        self.emit(line);
+    }
+
+    fn write_label(&mut self, label: &str) {
+        self.define(label);
+    }
+
+    fn write_goto(&mut self, label: &str) {
+        if self.last_label.as_deref() == Some(label) {
+            self.emit("HALT();".to_string());
+        } else {
+            let label = self.label(label);
+            self.emit(format!("JUMP({});", label));
+        }
+    }
+
+    fn write_if(&mut self, label: &str) {
+        let label = self.label(label);
+        self.emit(format!("if (POP() != 0) JUMP({});", label));
+    }
+
+    fn write_function(&mut self, function_name: &str, num_vars: i32) {
+        self.close();
+        self.function = Some(function_name.to_string());
+        self.defined_functions.insert(function_name.to_string());
+        for _ in 0..num_vars {
+            self.emit("PUSH(0);".to_string());
+        }
+    }
+
+    fn write_call(&mut self, function_name: &str, num_args: i32) {
+        let id = self.call_count;
+        self.call_count += 1;
+        let function = self.function_id(function_name);
+        self.emit(format!(
+            "PUSH({}); PUSH(LCL); PUSH(ARG); PUSH(THIS); PUSH(THAT); /* call {} {} */",
+            id, function_name, num_args
+        ));
+        self.emit(format!(
+            "ARG = SP - {}; LCL = SP; CALL({});",
+            5 + num_args,
+            function
+        ));
+    }
+
+    fn write_return(&mut self) {
+        self.emit("frame = LCL; /* return */".to_string());
+        self.emit("M(ARG) = POP(); SP = ARG + 1;".to_string());
+        self.emit("THAT = M(frame - 1); THIS = M(frame - 2);".to_string());
+        self.emit("ARG = M(frame - 3); LCL = M(frame - 4);".to_string());
+        self.emit("return;".to_string());
+    }
+
+    fn finish(&mut self) {
+        self.close();
+        let mut out = String::from(PRELUDE);
+        let mut functions: Vec<(&String, &usize)> = self.functions.iter().collect();
+        functions.sort_by_key(|(_, id)| **id);
+        out.push('\n');
+        for (name, id) in functions.iter() {
+            out.push_str(&format!("static void F{}(void); /* {} */\n", id, name));
+        }
+        // Functions the program calls but never defines, e.g. OS functions
+        // that were not translated along with it.
+        for (name, id) in functions {
+            if !self.defined_functions.contains(name) {
+                out.push_str(&format!(
+                    "\nstatic void F{}(void)\n{{\n    fprintf(stderr, \"undefined function {}\\n\");\n    exit(1);\n}}\n",
+                    id, name
+                ));
+            }
+        }
+        out.push_str(&self.out);
+        out.push_str(&self.run);
+        out.push_str(EPILOGUE);
+        self.writer.write_all(out.as_bytes()).unwrap();
+        self.writer.flush().unwrap();
+    }
+}
@@ -1,4 +1,8 @@
-use super::parser::{Arithmetic, CommandType};
+// Assembly sequences are built one instruction per line so each can carry a comment.
+#![allow(clippy::vec_init_then_push)]
+
+use super::parser::{Arithmetic, Command, CommandType};
+use super::writer::CodeWriter;
 use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
@@ -31,41 +35,41 @@ impl fmt::Display for Segment {
     }
 }
 
+/// The Hack assembly backend.
 pub struct Code {
-    writer: BufWriter<File>,
+    writer: Box<dyn Write>,
     filename: String,
     label_count: i32,
     call_count: i32,
+    annotated: bool,
 }
 
 impl Code {
     pub fn new(path: &Path) -> Self {
         // let filename = path.file_stem().unwrap().to_string_lossy().to_string();
         let file = File::create(path).unwrap();
-        let writer = BufWriter::new(file);
+        Self::from_writer(Box::new(BufWriter::new(file)))
+    }
+
+    pub fn from_writer(writer: Box<dyn Write>) -> Self {
         Code {
             writer,
             filename: String::new(),
             label_count: 0,
             call_count: 0,
+            annotated: false,
         }
     }
 
-    pub fn write_arithmetic(&mut self, command: &str) {
-        let command = Self::str2arithmetic(command);
-        let res = match command {
-            Arithmetic::Add => Self::add_sub("+"),
-            Arithmetic::Sub => Self::add_sub("-"),
-            Arithmetic::Neg => Self::neg(),
-            Arithmetic::Eq => self.compare("JEQ"),
-            Arithmetic::Lt => self.compare("JLT"),
-            Arithmetic::Gt => self.compare("JGT"),
-            Arithmetic::And => Self::and_or("&"),
-            Arithmetic::Or => Self::and_or("|"),
-            Arithmetic::Not => Self::not(),
-        };
-        for mut s in res {
-            s.push_str("\r\n");
+    /// Precedes the expansion of every VM command with a
+    /// `// file.vm:line: command` comment and every function with a banner.
+    pub fn set_annotated(&mut self, annotated: bool) {
+        self.annotated = annotated;
+    }
+
+    fn write_lines(&mut self, lines: &[String]) {
+        for s in lines {
+            let s = format!("{}\r\n", s);
             self.writer.write_all(s.as_bytes()).unwrap();
         }
         self.writer.flush().unwrap();
@@ -82,7 +86,11 @@ impl Code {
         res.push("M=M-1"); // SP--
         res.push("@SP");
         res.push("A=M");
-        let asm = format!("D=M{}D", cmd);
+        // Hack only has `D+M`, not `M+D`.
+        let asm = match cmd {
+            "+" => "D=D+M".to_string(),
+            _ => format!("D=M{}D", cmd),
+        };
         res.push(&asm); // D = *SP - D
         res.push("M=D"); // *SP = D
         res.push("@SP");
@@ -152,7 +160,7 @@ impl Code {
         res.push("M=M-1");
         res.push("@SP"); // *SP = *SP & D
         res.push("A=M");
-        let asm = format!("M=M{}D", cmd);
+        let asm = format!("M=D{}M", cmd);
         res.push(&asm);
         res.push("@SP");
         res.push("M=M+1");
@@ -171,20 +179,6 @@ impl Code {
         res.iter().map(|s| s.to_string()).collect()
     }
 
-    pub fn write_push_pop(&mut self, command: CommandType, segment: &str, index: i32) {
-        let segment = Self::str2segment(segment);
-        let res = match command {
-            CommandType::PUSH => self.push(segment, index),
-            CommandType::POP => self.pop(segment, index),
-            _ => panic!("Invalid command, must be one of PUSH or POP!"),
-        };
-        for mut s in res {
-            s.push_str("\r\n");
-            self.writer.write_all(s.as_bytes()).unwrap();
-        }
-        self.writer.flush().unwrap();
-    }
-
     fn push(&self, segment: Segment, index: i32) -> Vec<String> {
         let mut res = Vec::new();
         match segment {
@@ -303,14 +297,70 @@ impl Code {
         };
         res
     }
+}
+
+impl CodeWriter for Code {
+    fn write_source(&mut self, line: usize, command: &Command) {
+        if !self.annotated {
+            return;
+        }
+        let location = format!("{}:{}", self.filename, line);
+        let lines = match command {
+            Command::Function(..) => {
+                let rule = format!("// {}", "=".repeat(60));
+                vec![
+                    String::new(),
+                    rule.clone(),
+                    format!("// {} ({})", command, location),
+                    rule,
+                ]
+            }
+            _ => vec![format!("// {}: {}", location, command)],
+        };
+        self.write_lines(&lines);
+    }
+
+    fn write_arithmetic(&mut self, command: &str) {
+        let command = Self::str2arithmetic(command);
+        let res = match command {
+            Arithmetic::Add => Self::add_sub("+"),
+            Arithmetic::Sub => Self::add_sub("-"),
+            Arithmetic::Neg => Self::neg(),
+            Arithmetic::Eq => self.compare("JEQ"),
+            Arithmetic::Lt => self.compare("JLT"),
+            Arithmetic::Gt => self.compare("JGT"),
+            Arithmetic::And => Self::and_or("&"),
+            Arithmetic::Or => Self::and_or("|"),
+            Arithmetic::Not => Self::not(),
+        };
+        for mut s in res {
+            s.push_str("\r\n");
+            self.writer.write_all(s.as_bytes()).unwrap();
+        }
+        self.writer.flush().unwrap();
+    }
 
-    pub fn write_label(&mut self, label: &str) {
+    fn write_push_pop(&mut self, command: CommandType, segment: &str, index: i32) {
+        let segment = Self::str2segment(segment);
+        let res = match command {
+            CommandType::PUSH => self.push(segment, index),
+            CommandType::POP => self.pop(segment, index),
+            _ => panic!("Invalid command, must be one of PUSH or POP!"),
+        };
+        for mut s in res {
+            s.push_str("\r\n");
+            self.writer.write_all(s.as_bytes()).unwrap();
+        }
+        self.writer.flush().unwrap();
+    }
+
+    fn write_label(&mut self, label: &str) {
         let label = format!("({})\r\n", label);
         self.writer.write_all(label.as_bytes()).unwrap();
         self.writer.flush().unwrap();
     }
 
-    pub fn write_if(&mut self, label: &str) {
+    fn write_if(&mut self, label: &str) {
         let mut res = Vec::new();
         res.push("@SP"); // SP--
         res.push("M=M-1");
@@ -328,14 +378,14 @@ impl Code {
         self.writer.flush().unwrap();
     }
 
-    pub fn write_goto(&mut self, label: &str) {
+    fn write_goto(&mut self, label: &str) {
         let label = format!("@{}\r\n", label);
         self.writer.write_all(label.as_bytes()).unwrap();
         self.writer.write_all(b"0;JMP\r\n").unwrap();
         self.writer.flush().unwrap();
     }
 
-    pub fn write_function(&mut self, function_name: &str, num_vars: i32) {
+    fn write_function(&mut self, function_name: &str, num_vars: i32) {
         let mut res = Vec::new();
         let label = format!("({})", function_name);
         res.push(label);
@@ -349,7 +399,7 @@ impl Code {
         self.writer.flush().unwrap();
     }
 
-    pub fn write_return(&mut self) {
+    fn write_return(&mut self) {
         let mut res = Vec::new();
         res.push("@LCL".to_string()); // end_frame = LCL
         res.push("D=M".to_string());
@@ -401,7 +451,7 @@ impl Code {
         self.writer.flush().unwrap();
     }
 
-    pub fn write_call(&mut self, function_name: &str, num_args: i32) {
+    fn write_call(&mut self, function_name: &str, num_args: i32) {
         let res = self.call(function_name, num_args);
         for mut s in res {
             s.push_str("\r\n");
@@ -410,6 +460,30 @@ impl Code {
         self.writer.flush().unwrap();
     }
 
+    fn write_init(&mut self) {
+        if self.annotated {
+            self.write_lines(&["// bootstrap: SP = 256, call Sys.init".to_string()]);
+        }
+        let mut res = Vec::new();
+        res.push("@256".to_string());
+        res.push("D=A".to_string());
+        res.push("@SP".to_string());
+        res.push("M=D".to_string());
+        res.extend(self.call("Sys.init", 0));
+
+        for mut s in res {
+            s.push_str("\r\n");
+            self.writer.write_all(s.as_bytes()).unwrap();
+        }
+        self.writer.flush().unwrap();
+    }
+
+    fn set_filename(&mut self, filename: &str) {
+        self.filename = filename.to_string();
+    }
+}
+
+impl Code {
     fn call(&mut self, function_name: &str, num_args: i32) -> Vec<String> {
         let mut res = Vec::new();
         let ret_addr_label = format!("{}$ret.{}", function_name, self.call_count);
@@ -455,25 +529,6 @@ impl Code {
         res
     }
 
-    pub fn write_init(&mut self) {
-        let mut res = Vec::new();
-        res.push("@256".to_string());
-        res.push("D=A".to_string());
-        res.push("@SP".to_string());
-        res.push("M=D".to_string());
-        res.extend(self.call("Sys.init", 0));
-
-        for mut s in res {
-            s.push_str("\r\n");
-            self.writer.write(s.as_bytes()).unwrap();
-        }
-        self.writer.flush().unwrap();
-    }
-
-    pub fn set_filename(&mut self, filename: &str) {
-        self.filename = filename.to_string();
-    }
-
     fn str2arithmetic(s: &str) -> Arithmetic {
         match s {
             "add" => Arithmetic::Add,
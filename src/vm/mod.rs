@@ -1,45 +1,50 @@
+pub mod c_code;
 pub mod code;
+pub mod optimizer;
 pub mod parser;
+pub mod verifier;
+pub mod wat_code;
+pub mod writer;
 
 use code::Code;
-use parser::{CommandType, Parser};
+use optimizer::Optimizer;
+use parser::{Command, CommandType, Parser};
 use std::ffi::OsStr;
 use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
+use verifier::{Report, Verifier};
+use writer::CodeWriter;
 
 pub struct VM {
-    code: code::Code,
+    code: Box<dyn CodeWriter>,
     files: Vec<PathBuf>,
+    optimizer: Option<Optimizer>,
 }
 
 impl VM {
+    /// Translates `path` (a `.vm` file or a directory of them) into Hack
+    /// assembly next to it.
     pub fn new(path: &Path) -> Self {
-        match path.is_file() {
-            true => {
-                let mut files = Vec::new();
-                files.push(path.to_path_buf());
-                let path = get_asm_path(path);
-                let code = Code::new(&path);
-                VM { code, files }
-            }
-            false => {
-                let files = fs::read_dir(path)
-                    .unwrap()
-                    .map(|res| res.map(|e| e.path()))
-                    .collect::<Result<Vec<_>, io::Error>>()
-                    .unwrap();
-                let files = files
-                    .into_iter()
-                    .filter(|x| x.extension() == Some(OsStr::new("vm")))
-                    .collect();
-                let path = get_asm_path(path);
-                let code = Code::new(&path);
-                VM { code, files }
-            }
+        let code = Code::new(&get_asm_path(path));
+        Self::with_writer(path, Box::new(code))
+    }
+
+    /// Translates `path` with another backend.
+    pub fn with_writer(path: &Path, code: Box<dyn CodeWriter>) -> Self {
+        let files = vm_files(path);
+        VM {
+            code,
+            files,
+            optimizer: None,
         }
     }
 
+    /// Runs `optimizer` over the commands of every file before translating them.
+    pub fn set_optimizer(&mut self, optimizer: Optimizer) {
+        self.optimizer = Some(optimizer);
+    }
+
     pub fn translate(&mut self) {
         self.code.write_init();
         for file in self.files.clone() {
@@ -47,54 +52,80 @@ impl VM {
             self.code.set_filename(&filename);
             self.translate_one_file(&file)
         }
+        self.code.finish();
     }
 
     fn translate_one_file(&mut self, path: &Path) {
         let mut parser = Parser::new(path);
-        while parser.has_more_commands() {
-            parser.advance();
-            match parser.command_type() {
-                CommandType::ARITHMETIC => {
-                    let command = parser.arg1();
+        let mut commands = parser.commands();
+        if let Some(optimizer) = self.optimizer.as_mut() {
+            commands = optimizer.optimize(commands);
+        }
+        for command in commands {
+            self.code.write_source(command.line, &command.command);
+            match command.command {
+                Command::Arithmetic(command) => {
                     self.code.write_arithmetic(&command);
                 }
-                CommandType::PUSH => {
-                    let segment = parser.arg1();
-                    self.code
-                        .write_push_pop(CommandType::PUSH, &segment, parser.arg2());
+                Command::Push(segment, index) => {
+                    self.code.write_push_pop(CommandType::PUSH, &segment, index);
                 }
-                CommandType::POP => {
-                    let segment = parser.arg1();
-                    self.code
-                        .write_push_pop(CommandType::POP, &segment, parser.arg2());
+                Command::Pop(segment, index) => {
+                    self.code.write_push_pop(CommandType::POP, &segment, index);
                 }
-                CommandType::LABEL => {
-                    let label = parser.arg1();
+                Command::Label(label) => {
                     self.code.write_label(&label);
                 }
-                CommandType::IF => {
-                    let label = parser.arg1();
+                Command::If(label) => {
                     self.code.write_if(&label);
                 }
-                CommandType::GOTO => {
-                    let label = parser.arg1();
+                Command::Goto(label) => {
                     self.code.write_goto(&label);
                 }
-                CommandType::FUNCTION => {
-                    self.code.write_function(&parser.arg1(), parser.arg2());
+                Command::Function(function_name, num_vars) => {
+                    self.code.write_function(&function_name, num_vars);
                 }
-                CommandType::RETURN => {
+                Command::Return => {
                     self.code.write_return();
                 }
-                CommandType::CALL => {
-                    self.code.write_call(&parser.arg1(), parser.arg2());
+                Command::Call(function_name, num_args) => {
+                    self.code.write_call(&function_name, num_args);
                 }
-                _ => {}
             }
         }
     }
 }
 
+/// Checks the program at `path` (a `.vm` file or a directory of them)
+/// without translating it, so no output file is created.
+pub fn verify(path: &Path) -> Report {
+    let mut verifier = Verifier::new();
+    for file in vm_files(path).iter() {
+        let filename = file.file_name().unwrap().to_string_lossy().to_string();
+        verifier.add_file(&filename, Parser::new(file).commands());
+    }
+    verifier.verify()
+}
+
+/// The `.vm` files of a program: `path` itself or, for a directory, the
+/// `.vm` files in it in name order.
+pub fn vm_files(path: &Path) -> Vec<PathBuf> {
+    if path.is_file() {
+        return vec![path.to_path_buf()];
+    }
+    let files = fs::read_dir(path)
+        .unwrap()
+        .map(|res| res.map(|e| e.path()))
+        .collect::<Result<Vec<_>, io::Error>>()
+        .unwrap();
+    let mut files: Vec<_> = files
+        .into_iter()
+        .filter(|x| x.extension() == Some(OsStr::new("vm")))
+        .collect();
+    files.sort();
+    files
+}
+
 fn get_asm_path(path: &Path) -> PathBuf {
     let mut path = path.to_path_buf();
     path.set_extension("asm");
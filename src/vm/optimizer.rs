@@ -0,0 +1,238 @@
+use super::parser::{Command, SourceCommand};
+
+/// Peephole optimisation passes over a stream of VM commands.
+///
+/// Every pass rewrites a short window at the tail of the already optimised
+/// output, so a rewrite can enable another one (e.g. a folded constant that
+/// feeds the next `add`). Passes never look across a `label`, which keeps
+/// them safe in the presence of jumps. The output only uses standard VM
+/// commands, so it can be fed to the translator as usual.
+pub struct Optimizer {
+    /// `push constant 2; push constant 3; add` => `push constant 5`
+    pub fold_constants: bool,
+    /// `push local 1; pop local 1` => nothing
+    pub cancel_push_pop: bool,
+    /// `lt; not; if-goto L` => `lt; if-goto SKIP; goto L; label SKIP`
+    pub invert_not_branch: bool,
+    /// `push constant 0; eq; if-goto L` => `if-goto SKIP; goto L; label SKIP`
+    /// and `lt; push constant 0; eq` => `lt; not`
+    pub fuse_zero_check: bool,
+    label_count: usize,
+}
+
+impl Optimizer {
+    /// An optimizer with every pass enabled.
+    pub fn new() -> Self {
+        Optimizer {
+            fold_constants: true,
+            cancel_push_pop: true,
+            invert_not_branch: true,
+            fuse_zero_check: true,
+            label_count: 0,
+        }
+    }
+
+    /// An optimizer with every pass disabled, to enable passes one by one.
+    pub fn none() -> Self {
+        Optimizer {
+            fold_constants: false,
+            cancel_push_pop: false,
+            invert_not_branch: false,
+            fuse_zero_check: false,
+            label_count: 0,
+        }
+    }
+
+    pub fn optimize(&mut self, commands: Vec<SourceCommand>) -> Vec<SourceCommand> {
+        let mut res = Vec::with_capacity(commands.len());
+        for command in commands {
+            res.push(command);
+            while self.reduce(&mut res) {}
+        }
+        res
+    }
+
+    fn reduce(&mut self, res: &mut Vec<SourceCommand>) -> bool {
+        (self.fold_constants && Self::fold_constants(res))
+            || (self.cancel_push_pop && Self::cancel_push_pop(res))
+            || (self.fuse_zero_check && self.fuse_zero_check(res))
+            || (self.invert_not_branch && self.invert_not_branch(res))
+    }
+
+    fn fold_constants(res: &mut Vec<SourceCommand>) -> bool {
+        let op = match res.last() {
+            Some(SourceCommand {
+                command: Command::Arithmetic(op),
+                ..
+            }) => op.clone(),
+            _ => return false,
+        };
+        let end = res.len() - 1;
+        let (rhs, rhs_len) = match constant_at(res, end) {
+            Some(c) => c,
+            None => return false,
+        };
+        let (value, start) = match op.as_str() {
+            "neg" => (rhs.wrapping_neg(), end - rhs_len),
+            "not" => (!rhs, end - rhs_len),
+            _ => {
+                let (lhs, lhs_len) = match constant_at(res, end - rhs_len) {
+                    Some(c) => c,
+                    None => return false,
+                };
+                let value = match op.as_str() {
+                    "add" => lhs.wrapping_add(rhs),
+                    "sub" => lhs.wrapping_sub(rhs),
+                    "and" => lhs & rhs,
+                    "or" => lhs | rhs,
+                    "eq" => -((lhs == rhs) as i16),
+                    "gt" => -((lhs > rhs) as i16),
+                    "lt" => -((lhs < rhs) as i16),
+                    _ => return false,
+                };
+                (value, end - rhs_len - lhs_len)
+            }
+        };
+        let folded = constant_commands(value);
+        // Only rewrite when it shrinks the window, `push constant 1; neg` is
+        // already the shortest form of -1.
+        if folded.len() >= res.len() - start {
+            return false;
+        }
+        let line = res[start].line;
+        res.truncate(start);
+        res.extend(folded.into_iter().map(|c| SourceCommand::new(line, c)));
+        true
+    }
+
+    fn cancel_push_pop(res: &mut Vec<SourceCommand>) -> bool {
+        let len = res.len();
+        if len < 2 {
+            return false;
+        }
+        match (&res[len - 2].command, &res[len - 1].command) {
+            (Command::Push(push_seg, push_idx), Command::Pop(pop_seg, pop_idx))
+                if push_seg == pop_seg && push_idx == pop_idx && push_seg != "constant" =>
+            {
+                res.truncate(len - 2);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn fuse_zero_check(&mut self, res: &mut Vec<SourceCommand>) -> bool {
+        let len = res.len();
+        if len >= 3
+            && is_push_constant(&res[len - 3].command, 0)
+            && res[len - 2].command == Command::Arithmetic("eq".to_string())
+        {
+            if let Command::If(label) = &res[len - 1].command {
+                let label = label.clone();
+                let line = res[len - 3].line;
+                res.truncate(len - 3);
+                self.branch_if_zero(res, line, &label);
+                return true;
+            }
+        }
+        if len >= 3
+            && is_boolean(&res[len - 3].command)
+            && is_push_constant(&res[len - 2].command, 0)
+            && res[len - 1].command == Command::Arithmetic("eq".to_string())
+        {
+            let line = res[len - 2].line;
+            res.truncate(len - 2);
+            res.push(SourceCommand::new(
+                line,
+                Command::Arithmetic("not".to_string()),
+            ));
+            return true;
+        }
+        false
+    }
+
+    fn invert_not_branch(&mut self, res: &mut Vec<SourceCommand>) -> bool {
+        let len = res.len();
+        // `not` is bitwise, so `not; if-goto` only means "jump if false" when
+        // the operand is known to be 0 or -1.
+        if len >= 3
+            && is_boolean(&res[len - 3].command)
+            && res[len - 2].command == Command::Arithmetic("not".to_string())
+        {
+            if let Command::If(label) = &res[len - 1].command {
+                let label = label.clone();
+                let line = res[len - 2].line;
+                res.truncate(len - 2);
+                self.branch_if_zero(res, line, &label);
+                return true;
+            }
+        }
+        false
+    }
+
+    // if-goto SKIP; goto label; label SKIP
+    fn branch_if_zero(&mut self, res: &mut Vec<SourceCommand>, line: usize, label: &str) {
+        let skip = format!("{}$skip.{}", label, self.label_count);
+        self.label_count += 1;
+        res.push(SourceCommand::new(line, Command::If(skip.clone())));
+        res.push(SourceCommand::new(line, Command::Goto(label.to_string())));
+        res.push(SourceCommand::new(line, Command::Label(skip)));
+    }
+}
+
+impl Default for Optimizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn is_push_constant(command: &Command, value: i32) -> bool {
+    match command {
+        Command::Push(segment, index) => segment == "constant" && *index == value,
+        _ => false,
+    }
+}
+
+fn is_boolean(command: &Command) -> bool {
+    match command {
+        Command::Arithmetic(op) => op == "eq" || op == "gt" || op == "lt",
+        _ => false,
+    }
+}
+
+/// The constant computed by the commands ending right before `end`, and how
+/// many commands it spans.
+fn constant_at(res: &[SourceCommand], end: usize) -> Option<(i16, usize)> {
+    let push_constant = |i: usize| match &res[i].command {
+        Command::Push(segment, index) if segment == "constant" && (0..=32767).contains(index) => {
+            Some(*index as i16)
+        }
+        _ => None,
+    };
+    if end == 0 {
+        return None;
+    }
+    match &res[end - 1].command {
+        Command::Arithmetic(op) if end >= 2 && (op == "neg" || op == "not") => {
+            let value = push_constant(end - 2)?;
+            if op == "neg" {
+                Some((value.wrapping_neg(), 2))
+            } else {
+                Some((!value, 2))
+            }
+        }
+        _ => push_constant(end - 1).map(|value| (value, 1)),
+    }
+}
+
+/// The shortest command sequence pushing `value`.
+fn constant_commands(value: i16) -> Vec<Command> {
+    let push = |value: i16| Command::Push("constant".to_string(), value as i32);
+    if value >= 0 {
+        vec![push(value)]
+    } else if value == i16::MIN {
+        vec![push(i16::MAX), Command::Arithmetic("not".to_string())]
+    } else {
+        vec![push(-value), Command::Arithmetic("neg".to_string())]
+    }
+}
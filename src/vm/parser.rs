@@ -1,8 +1,10 @@
+use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::BufReader;
 use std::io::SeekFrom;
 use std::path::Path;
+use std::str::FromStr;
 
 #[derive(Debug, PartialEq)]
 pub enum Arithmetic {
@@ -31,10 +33,80 @@ pub enum CommandType {
     WHITESPACE,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Arithmetic(String),
+    Push(String, i32),
+    Pop(String, i32),
+    Label(String),
+    Goto(String),
+    If(String),
+    Function(String, i32),
+    Return,
+    Call(String, i32),
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Arithmetic(command) => write!(f, "{}", command),
+            Command::Push(segment, index) => write!(f, "push {} {}", segment, index),
+            Command::Pop(segment, index) => write!(f, "pop {} {}", segment, index),
+            Command::Label(label) => write!(f, "label {}", label),
+            Command::Goto(label) => write!(f, "goto {}", label),
+            Command::If(label) => write!(f, "if-goto {}", label),
+            Command::Function(name, num_vars) => write!(f, "function {} {}", name, num_vars),
+            Command::Return => write!(f, "return"),
+            Command::Call(name, num_args) => write!(f, "call {} {}", name, num_args),
+        }
+    }
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    /// Parses one command in VM syntax, the inverse of `Display`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let number = |word: &str| {
+            word.parse()
+                .map_err(|_| format!("{} is not a number in `{}`", word, s))
+        };
+        match words.as_slice() {
+            ["push", segment, index] => Ok(Command::Push(segment.to_string(), number(index)?)),
+            ["pop", segment, index] => Ok(Command::Pop(segment.to_string(), number(index)?)),
+            ["label", label] => Ok(Command::Label(label.to_string())),
+            ["goto", label] => Ok(Command::Goto(label.to_string())),
+            ["if-goto", label] => Ok(Command::If(label.to_string())),
+            ["function", name, n] => Ok(Command::Function(name.to_string(), number(n)?)),
+            ["call", name, n] => Ok(Command::Call(name.to_string(), number(n)?)),
+            ["return"] => Ok(Command::Return),
+            [command @ ("add" | "sub" | "neg" | "eq" | "gt" | "lt" | "and" | "or" | "not")] => {
+                Ok(Command::Arithmetic(command.to_string()))
+            }
+            _ => Err(format!("`{}` is not a VM command", s)),
+        }
+    }
+}
+
+/// A command together with the (1-based) line of the `.vm` file it came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceCommand {
+    pub line: usize,
+    pub command: Command,
+}
+
+impl SourceCommand {
+    pub fn new(line: usize, command: Command) -> Self {
+        SourceCommand { line, command }
+    }
+}
+
 pub struct Parser {
     reader: BufReader<File>,
     pub current_command: String,
     eof: bool,
+    line: usize,
 }
 
 impl Parser {
@@ -45,6 +117,7 @@ impl Parser {
             reader,
             current_command: String::new(),
             eof: false,
+            line: 0,
         }
     }
 
@@ -61,6 +134,8 @@ impl Parser {
             let len = self.reader.read_line(&mut self.current_command).unwrap();
             if len == 0 {
                 self.eof = true;
+            } else {
+                self.line += 1;
             }
             let current_command = match self.current_command.find("//") {
                 Some(size) => &self.current_command[..size],
@@ -119,9 +194,41 @@ impl Parser {
         arg2.parse().unwrap()
     }
 
+    pub fn line_number(&self) -> usize {
+        self.line
+    }
+
+    pub fn command(&self) -> Command {
+        match self.command_type() {
+            CommandType::ARITHMETIC => Command::Arithmetic(self.arg1()),
+            CommandType::PUSH => Command::Push(self.arg1(), self.arg2()),
+            CommandType::POP => Command::Pop(self.arg1(), self.arg2()),
+            CommandType::LABEL => Command::Label(self.arg1()),
+            CommandType::GOTO => Command::Goto(self.arg1()),
+            CommandType::IF => Command::If(self.arg1()),
+            CommandType::FUNCTION => Command::Function(self.arg1(), self.arg2()),
+            CommandType::RETURN => Command::Return,
+            CommandType::CALL => Command::Call(self.arg1(), self.arg2()),
+            CommandType::WHITESPACE => panic!("whitespace is not a command"),
+        }
+    }
+
+    /// Reads every remaining command of the file.
+    pub fn commands(&mut self) -> Vec<SourceCommand> {
+        let mut res = Vec::new();
+        while self.has_more_commands() {
+            self.advance();
+            if self.command_type() != CommandType::WHITESPACE {
+                res.push(SourceCommand::new(self.line_number(), self.command()));
+            }
+        }
+        res
+    }
+
     pub fn reset(&mut self) {
         self.reader.seek(SeekFrom::Start(0)).unwrap();
         self.current_command.clear();
         self.eof = false;
+        self.line = 0;
     }
 }
@@ -0,0 +1,354 @@
+use super::parser::{Command, SourceCommand};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionInfo {
+    pub name: String,
+    pub file: String,
+    pub line: usize,
+    pub num_vars: i32,
+    /// Highest `argument` index used in the body plus one.
+    pub num_args_used: i32,
+    /// Deepest the working stack gets above the frame, ignoring callees.
+    pub max_stack_depth: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub diagnostics: Vec<Diagnostic>,
+    pub functions: Vec<FunctionInfo>,
+}
+
+impl Report {
+    pub fn is_ok(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    pub fn function(&self, name: &str) -> Option<&FunctionInfo> {
+        self.functions.iter().find(|f| f.name == name)
+    }
+}
+
+/// Static checks over a whole VM program.
+///
+/// Commands before the first `function` of a file (the bare programs of
+/// project 7) are checked as a function named after the file.
+pub struct Verifier {
+    files: Vec<(String, Vec<SourceCommand>)>,
+}
+
+struct Body<'a> {
+    file: &'a str,
+    name: String,
+    line: usize,
+    num_vars: i32,
+    commands: &'a [SourceCommand],
+}
+
+impl Verifier {
+    pub fn new() -> Self {
+        Verifier { files: Vec::new() }
+    }
+
+    pub fn add_file(&mut self, filename: &str, commands: Vec<SourceCommand>) {
+        self.files.push((filename.to_string(), commands));
+    }
+
+    pub fn verify(&self) -> Report {
+        let mut report = Report::default();
+        let bodies = self.bodies(&mut report);
+        let classes: HashSet<&str> = self
+            .files
+            .iter()
+            .map(|(file, _)| file.trim_end_matches(".vm"))
+            .collect();
+
+        for body in bodies.iter() {
+            let num_args_used = Self::check_commands(body, &mut report);
+            let max_stack_depth = Self::check_stack(body, &mut report);
+            report.functions.push(FunctionInfo {
+                name: body.name.clone(),
+                file: body.file.to_string(),
+                line: body.line,
+                num_vars: body.num_vars,
+                num_args_used,
+                max_stack_depth,
+            });
+        }
+
+        let mut call_args: HashMap<&str, (i32, &str, usize)> = HashMap::new();
+        for body in bodies.iter() {
+            for command in body.commands {
+                if let Command::Call(name, num_args) = &command.command {
+                    let error = |message: String| Diagnostic {
+                        file: body.file.to_string(),
+                        line: command.line,
+                        message,
+                    };
+                    match report.functions.iter().find(|f| &f.name == name) {
+                        Some(callee) if callee.num_args_used > *num_args => {
+                            report.diagnostics.push(error(format!(
+                                "call {} {} passes too few arguments, {} uses argument {}",
+                                name,
+                                num_args,
+                                name,
+                                callee.num_args_used - 1
+                            )));
+                        }
+                        Some(_) => {}
+                        // Functions of classes that are not part of the program
+                        // (the OS) cannot be checked.
+                        None if classes.contains(name.split('.').next().unwrap()) => {
+                            report
+                                .diagnostics
+                                .push(error(format!("call to undeclared function {}", name)));
+                        }
+                        None => {}
+                    }
+                    match call_args.get(name.as_str()) {
+                        Some((first, file, line)) if first != num_args => {
+                            report.diagnostics.push(error(format!(
+                                "call {} {} disagrees with call {} {} at {}:{}",
+                                name, num_args, name, first, file, line
+                            )));
+                        }
+                        Some(_) => {}
+                        None => {
+                            call_args.insert(name, (*num_args, body.file, command.line));
+                        }
+                    }
+                }
+            }
+        }
+        report
+    }
+
+    fn bodies(&self, report: &mut Report) -> Vec<Body<'_>> {
+        let mut bodies = Vec::new();
+        let mut declared: HashMap<&str, (&str, usize)> = HashMap::new();
+        for (file, commands) in self.files.iter() {
+            let starts: Vec<usize> = commands
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| matches!(c.command, Command::Function(..)))
+                .map(|(i, _)| i)
+                .collect();
+            let first = starts.first().copied().unwrap_or(commands.len());
+            if first > 0 {
+                bodies.push(Body {
+                    file,
+                    name: file.trim_end_matches(".vm").to_string(),
+                    line: commands[0].line,
+                    num_vars: 0,
+                    commands: &commands[..first],
+                });
+            }
+            for (i, &start) in starts.iter().enumerate() {
+                let end = starts.get(i + 1).copied().unwrap_or(commands.len());
+                if let Command::Function(name, num_vars) = &commands[start].command {
+                    let line = commands[start].line;
+                    if let Some((other_file, other_line)) = declared.get(name.as_str()) {
+                        report.diagnostics.push(Diagnostic {
+                            file: file.to_string(),
+                            line,
+                            message: format!(
+                                "function {} is already declared at {}:{}",
+                                name, other_file, other_line
+                            ),
+                        });
+                    }
+                    declared.insert(name, (file, line));
+                    if *num_vars < 0 {
+                        report.diagnostics.push(Diagnostic {
+                            file: file.to_string(),
+                            line,
+                            message: format!("function {} declares {} locals", name, num_vars),
+                        });
+                    }
+                    bodies.push(Body {
+                        file,
+                        name: name.clone(),
+                        line,
+                        num_vars: *num_vars,
+                        commands: &commands[start + 1..end],
+                    });
+                }
+            }
+        }
+        bodies
+    }
+
+    // Checks every command on its own, returns the number of arguments used.
+    fn check_commands(body: &Body, report: &mut Report) -> i32 {
+        let mut num_args_used = 0;
+        for command in body.commands {
+            let mut error = |message: String| {
+                report.diagnostics.push(Diagnostic {
+                    file: body.file.to_string(),
+                    line: command.line,
+                    message,
+                })
+            };
+            match &command.command {
+                Command::Push(segment, index) | Command::Pop(segment, index) => {
+                    let is_pop = matches!(command.command, Command::Pop(..));
+                    if let Some(max) = segment_max(segment) {
+                        if *index < 0 || *index > max {
+                            error(format!(
+                                "{} index must be between 0 and {}, found {}",
+                                segment, max, index
+                            ));
+                        }
+                    } else {
+                        error(format!("{} is not a valid segment", segment));
+                    }
+                    if is_pop && segment == "constant" {
+                        error("cannot pop to the constant segment".to_string());
+                    }
+                    if segment == "argument" {
+                        num_args_used = num_args_used.max(*index + 1);
+                    }
+                }
+                Command::Arithmetic(op) if stack_effect(&command.command).is_none() => {
+                    error(format!("{} is not a valid command", op));
+                }
+                Command::Call(name, num_args) if *num_args < 0 => {
+                    error(format!("call {} with {} arguments", name, num_args));
+                }
+                _ => {}
+            }
+        }
+        num_args_used
+    }
+
+    // Walks every path through the body, returns the deepest stack seen.
+    fn check_stack(body: &Body, report: &mut Report) -> usize {
+        let commands = body.commands;
+        let mut labels = HashMap::new();
+        for (i, command) in commands.iter().enumerate() {
+            if let Command::Label(label) = &command.command {
+                if labels.insert(label.as_str(), i).is_some() {
+                    report.diagnostics.push(Diagnostic {
+                        file: body.file.to_string(),
+                        line: command.line,
+                        message: format!("label {} is declared twice in {}", label, body.name),
+                    });
+                }
+            }
+        }
+
+        let mut heights: Vec<Option<i32>> = vec![None; commands.len() + 1];
+        let mut worklist = vec![(0, 0)];
+        let mut max_depth = 0;
+        let mut reported = HashSet::new();
+        while let Some((i, height)) = worklist.pop() {
+            if i >= commands.len() {
+                continue;
+            }
+            match heights[i] {
+                Some(h) if h == height => continue,
+                Some(h) => {
+                    if reported.insert(i) {
+                        let message = match &commands[i].command {
+                            Command::Label(label) => format!(
+                                "inconsistent stack height at label {}: {} vs {}",
+                                label, h, height
+                            ),
+                            _ => format!("inconsistent stack height: {} vs {}", h, height),
+                        };
+                        report.diagnostics.push(Diagnostic {
+                            file: body.file.to_string(),
+                            line: commands[i].line,
+                            message,
+                        });
+                    }
+                    continue;
+                }
+                None => heights[i] = Some(height),
+            }
+
+            let command = &commands[i];
+            let (pops, pushes) = stack_effect(&command.command).unwrap_or((0, 0));
+            if height < pops {
+                report.diagnostics.push(Diagnostic {
+                    file: body.file.to_string(),
+                    line: command.line,
+                    message: format!(
+                        "`{}` needs {} values but the stack holds {}",
+                        command.command, pops, height
+                    ),
+                });
+                continue;
+            }
+            let next = height - pops + pushes;
+            max_depth = max_depth.max(next);
+
+            let mut jump = |label: &str, worklist: &mut Vec<(usize, i32)>| match labels.get(label) {
+                Some(&target) => worklist.push((target, next)),
+                None => report.diagnostics.push(Diagnostic {
+                    file: body.file.to_string(),
+                    line: command.line,
+                    message: format!("label {} is not declared in {}", label, body.name),
+                }),
+            };
+            match &command.command {
+                Command::Goto(label) => jump(label, &mut worklist),
+                Command::If(label) => {
+                    jump(label, &mut worklist);
+                    worklist.push((i + 1, next));
+                }
+                Command::Return => {}
+                _ => worklist.push((i + 1, next)),
+            }
+        }
+        max_depth as usize
+    }
+}
+
+impl Default for Verifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn segment_max(segment: &str) -> Option<i32> {
+    match segment {
+        "local" | "argument" | "this" | "that" => Some(i16::MAX as i32),
+        "constant" => Some(i16::MAX as i32),
+        // RAM[16..255] is shared by the statics of every file.
+        "static" => Some(239),
+        "temp" => Some(7),
+        "pointer" => Some(1),
+        _ => None,
+    }
+}
+
+// (values popped, values pushed)
+fn stack_effect(command: &Command) -> Option<(i32, i32)> {
+    let effect = match command {
+        Command::Arithmetic(op) => match op.as_str() {
+            "add" | "sub" | "eq" | "gt" | "lt" | "and" | "or" => (2, 1),
+            "neg" | "not" => (1, 1),
+            _ => return None,
+        },
+        Command::Push(..) => (0, 1),
+        Command::Pop(..) | Command::If(_) | Command::Return => (1, 0),
+        Command::Call(_, num_args) => ((*num_args).max(0), 1),
+        Command::Label(_) | Command::Goto(_) | Command::Function(..) => (0, 0),
+    };
+    Some(effect)
+}
@@ -0,0 +1,351 @@
+use super::parser::CommandType;
+use super::writer::CodeWriter;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::path::Path;
+
+const PRELUDE: &str = r#";; Generated by the nand2tetris VM translator.
+(module
+  ;; RAM[i] lives at byte 2 * i, one page holds the 32K words of the Hack RAM.
+  (memory (export "memory") 1)
+  (global (export "screen") i32 (i32.const 32768))
+  (global (export "screen_words") i32 (i32.const 8192))
+  (global (export "keyboard") i32 (i32.const 49152))
+  (global $pc (mut i32) (i32.const 0))
+
+  (func $peek (param $addr i32) (result i32)
+    (i32.load16_s (i32.shl (i32.and (local.get $addr) (i32.const 0x7fff)) (i32.const 1))))
+
+  (func $poke (param $addr i32) (param $value i32)
+    (i32.store16 (i32.shl (i32.and (local.get $addr) (i32.const 0x7fff)) (i32.const 1))
+      (local.get $value)))
+
+  (func $push (param $value i32)
+    (call $poke (call $peek (i32.const 0)) (local.get $value))
+    (call $poke (i32.const 0) (i32.add (call $peek (i32.const 0)) (i32.const 1))))
+
+  (func $pop (result i32)
+    (call $poke (i32.const 0) (i32.sub (call $peek (i32.const 0)) (i32.const 1)))
+    (call $peek (call $peek (i32.const 0))))
+
+  ;; Runs at most `fuel` jumps and returns 1 once the program has halted, 0
+  ;; if it ran out of fuel. Calling it again resumes where it stopped.
+  (func (export "run") (param $fuel i32) (result i32)
+    (local $pc i32) (local $frame i32) (local $y i32)
+    (local.set $pc (global.get $pc))
+    block $invalid
+    loop $dispatch
+    (if (i32.eqz (local.get $fuel))
+      (then (global.set $pc (local.get $pc)) (return (i32.const 0))))
+    (local.set $fuel (i32.sub (local.get $fuel) (i32.const 1)))
+"#;
+
+const EPILOGUE: &str = r#"    end ;; $dispatch
+    end ;; $invalid
+    unreachable)
+)
+"#;
+
+enum Line {
+    Text(String),
+    PushLabel(String),
+    Goto(String),
+    If(String),
+    Halt,
+}
+
+struct Segment {
+    name: String,
+    lines: Vec<Line>,
+}
+
+/// A backend that turns a whole VM program into a WebAssembly text module.
+///
+/// The Hack RAM is the exported linear memory (word `i` at byte `2 * i`)
+/// and the `screen`, `screen_words` and `keyboard` globals tell a host page
+/// where to read the bitmap and write key codes. Wasm has no goto, so the
+/// program is split into segments at every label, function and return
+/// point, and every jump sets `$pc` and re-enters a `br_table` dispatch
+/// loop. The exported `run(fuel)` executes at most `fuel` jumps so a page
+/// can interleave execution with rendering.
+pub struct WatCode {
+    writer: BufWriter<File>,
+    segments: Vec<Segment>,
+    filename: String,
+    // The function being written, which scopes its labels.
+    function: String,
+    functions: HashSet<String>,
+    statics: HashMap<String, usize>,
+    call_count: usize,
+    last_label: Option<String>,
+}
+
+impl WatCode {
+    pub fn new(path: &Path) -> Self {
+        let file = File::create(path).unwrap();
+        let writer = BufWriter::new(file);
+        WatCode {
+            writer,
+            segments: vec![Segment {
+                name: String::new(),
+                lines: Vec::new(),
+            }],
+            filename: String::new(),
+            function: String::new(),
+            functions: HashSet::new(),
+            statics: HashMap::new(),
+            call_count: 0,
+            last_label: None,
+        }
+    }
+
+    fn emit(&mut self, line: Line) {
+        self.segments.last_mut().unwrap().lines.push(line);
+        self.last_label = None;
+    }
+
+    fn text(&mut self, text: &str) {
+        self.emit(Line::Text(text.to_string()));
+    }
+
+    fn segment(&mut self, name: &str) {
+        self.segments.push(Segment {
+            name: name.to_string(),
+            lines: Vec::new(),
+        });
+        self.last_label = Some(name.to_string());
+    }
+
+    // Labels belong to their function, so `LOOP` in two functions is two
+    // segments.
+    fn scoped(&self, label: &str) -> String {
+        format!("{}${}", self.function, label)
+    }
+
+    fn address(&mut self, segment: &str, index: i32) -> String {
+        let base = match segment {
+            "local" => 1,
+            "argument" => 2,
+            "this" => 3,
+            "that" => 4,
+            "pointer" => match index {
+                0 | 1 => return format!("(i32.const {})", 3 + index),
+                _ => panic!("Not a valid index, must be 0 or 1 for Pointer"),
+            },
+            "temp" => return format!("(i32.const {})", 5 + index),
+            "static" => {
+                let name = format!("{}.{}", self.filename, index);
+                let count = self.statics.len();
+                let address = *self.statics.entry(name).or_insert(16 + count);
+                return format!("(i32.const {})", address);
+            }
+            _ => panic!("{} is not a valid segment string", segment),
+        };
+        format!(
+            "(i32.add (call $peek (i32.const {})) (i32.const {}))",
+            base, index
+        )
+    }
+}
+
+impl CodeWriter for WatCode {
+    fn set_filename(&mut self, filename: &str) {
+        self.filename = filename.to_string();
+    }
+
+    fn write_init(&mut self) {
+        self.text("(call $poke (i32.const 0) (i32.const 256))");
+        self.write_call("Sys.init", 0);
+        self.emit(Line::Halt);
+    }
+
+    fn write_arithmetic(&mut self, command: &str) {
+        let binary = |op: &str| {
+            format!(
+                "(local.set $y (call $pop)) (call $push ({} (call $pop) (local.get $y)))",
+                op
+            )
+        };
+        let compare = |op: &str| {
+            format!(
+                "(local.set $y (call $pop)) \
+                 (call $push (i32.sub (i32.const 0) ({} (call $pop) (local.get $y))))",
+                op
+            )
+        };
+        let line = match command {
+            "add" => binary("i32.add"),
+            "sub" => binary("i32.sub"),
+            "and" => binary("i32.and"),
+            "or" => binary("i32.or"),
+            "eq" => compare("i32.eq"),
+            "gt" => compare("i32.gt_s"),
+            "lt" => compare("i32.lt_s"),
+            "neg" => "(call $push (i32.sub (i32.const 0) (call $pop)))".to_string(),
+            "not" => "(call $push (i32.xor (call $pop) (i32.const -1)))".to_string(),
+            _ => panic!("{} is not a valid arithmetic string", command),
+        };
+        self.emit(Line::Text(line));
+    }
+
+    fn write_push_pop(&mut self, command: CommandType, segment: &str, index: i32) {
+        let line = match command {
+            CommandType::PUSH if segment == "constant" => {
+                format!("(call $push (i32.const {}))", index)
+            }
+            CommandType::PUSH => {
+                format!("(call $push (call $peek {}))", self.address(segment, index))
+            }
+            CommandType::POP => format!(
+                "(local.set $y (call $pop)) (call $poke {} (local.get $y))",
+                self.address(segment, index)
+            ),
+            _ => panic!("Invalid command, must be one of PUSH or POP!"),
+        };
+        self.emit(Line::Text(line));
+    }
+
+    fn write_label(&mut self, label: &str) {
+        self.segment(&self.scoped(label));
+    }
+
+    fn write_goto(&mut self, label: &str) {
+        let label = self.scoped(label);
+        if self.last_label.as_deref() == Some(label.as_str()) {
+            self.emit(Line::Halt);
+        } else {
+            self.emit(Line::Goto(label));
+        }
+    }
+
+    fn write_if(&mut self, label: &str) {
+        self.emit(Line::If(self.scoped(label)));
+    }
+
+    fn write_function(&mut self, function_name: &str, num_vars: i32) {
+        self.function = function_name.to_string();
+        self.segment(function_name);
+        self.functions.insert(function_name.to_string());
+        for _ in 0..num_vars {
+            self.text("(call $push (i32.const 0))");
+        }
+    }
+
+    fn write_call(&mut self, function_name: &str, num_args: i32) {
+        let ret = format!("{}$ret.{}", function_name, self.call_count);
+        self.call_count += 1;
+        self.functions.insert(function_name.to_string());
+        self.emit(Line::Text(format!(
+            ";; call {} {}",
+            function_name, num_args
+        )));
+        self.emit(Line::PushLabel(ret.clone()));
+        for segment in 1..=4 {
+            self.emit(Line::Text(format!(
+                "(call $push (call $peek (i32.const {})))",
+                segment
+            )));
+        }
+        self.emit(Line::Text(format!(
+            "(call $poke (i32.const 2) (i32.sub (call $peek (i32.const 0)) (i32.const {})))",
+            5 + num_args
+        )));
+        self.text("(call $poke (i32.const 1) (call $peek (i32.const 0)))");
+        self.emit(Line::Goto(function_name.to_string()));
+        self.segment(&ret);
+    }
+
+    fn write_return(&mut self) {
+        self.text(";; return");
+        self.text("(local.set $frame (call $peek (i32.const 1)))");
+        self.text("(local.set $pc (call $peek (i32.sub (local.get $frame) (i32.const 5))))");
+        self.text(
+            "(local.set $y (call $pop)) (call $poke (call $peek (i32.const 2)) (local.get $y))",
+        );
+        self.text("(call $poke (i32.const 0) (i32.add (call $peek (i32.const 2)) (i32.const 1)))");
+        for (segment, offset) in [(4, 1), (3, 2), (2, 3), (1, 4)].iter() {
+            self.emit(Line::Text(format!(
+                "(call $poke (i32.const {}) (call $peek (i32.sub (local.get $frame) (i32.const {}))))",
+                segment, offset
+            )));
+        }
+        self.text("br $dispatch");
+    }
+
+    fn finish(&mut self) {
+        // Jump targets the program never defines, e.g. OS functions that
+        // were not translated along with it, trap when reached.
+        let mut ids: HashMap<String, usize> = HashMap::new();
+        for (id, segment) in self.segments.iter().enumerate() {
+            ids.insert(segment.name.clone(), id);
+        }
+        let mut undefined = Vec::new();
+        for segment in self.segments.iter() {
+            for line in segment.lines.iter() {
+                if let Line::Goto(label) | Line::If(label) = line {
+                    if !ids.contains_key(label) && !undefined.contains(label) {
+                        undefined.push(label.clone());
+                    }
+                }
+            }
+        }
+        for label in undefined {
+            ids.insert(label.clone(), self.segments.len());
+            let kind = match self.functions.contains(&label) {
+                true => "function",
+                false => "label",
+            };
+            self.segments.push(Segment {
+                name: label.clone(),
+                lines: vec![Line::Text(format!(
+                    ";; undefined {} {}\nunreachable",
+                    kind, label
+                ))],
+            });
+        }
+        let halt = self.segments.len();
+
+        let mut out = String::from(PRELUDE);
+        for id in (0..=halt).rev() {
+            out.push_str(&format!("    block $B{}\n", id));
+        }
+        out.push_str("    (br_table");
+        for id in 0..=halt {
+            out.push_str(&format!(" $B{}", id));
+        }
+        out.push_str(" $invalid (local.get $pc))\n");
+
+        let jump = |id: usize| format!("(local.set $pc (i32.const {})) br $dispatch", id);
+        for (id, segment) in self.segments.iter().enumerate() {
+            out.push_str(&format!("    end ;; $B{} {}\n", id, segment.name));
+            for line in segment.lines.iter() {
+                let text = match line {
+                    Line::Text(text) => text.clone(),
+                    Line::PushLabel(label) => format!("(call $push (i32.const {}))", ids[label]),
+                    Line::Goto(label) => jump(ids[label]),
+                    Line::If(label) => format!(
+                        "(if (i32.ne (call $pop) (i32.const 0)) (then {}))",
+                        jump(ids[label])
+                    ),
+                    Line::Halt => jump(halt),
+                };
+                for text in text.lines() {
+                    out.push_str("    ");
+                    out.push_str(text);
+                    out.push('\n');
+                }
+            }
+        }
+        out.push_str(&format!("    {}\n", jump(halt)));
+        out.push_str(&format!("    end ;; $B{} halt\n", halt));
+        out.push_str(&format!(
+            "    (global.set $pc (i32.const {})) (return (i32.const 1))\n",
+            halt
+        ));
+        out.push_str(EPILOGUE);
+        self.writer.write_all(out.as_bytes()).unwrap();
+        self.writer.flush().unwrap();
+    }
+}
@@ -0,0 +1,37 @@
+use super::parser::{Command, CommandType};
+
+/// A code generation target of the VM translator.
+///
+/// `VM` drives a writer with one call per VM command, so a new target only
+/// has to implement this trait. The Hack assembly backend is `code::Code`.
+pub trait CodeWriter {
+    /// Called before the commands of each `.vm` file.
+    fn set_filename(&mut self, filename: &str);
+
+    /// Bootstrap code: SP = 256, call Sys.init.
+    fn write_init(&mut self);
+
+    /// Called before each command with the line of the `.vm` file it came
+    /// from, for backends that annotate their output.
+    fn write_source(&mut self, _line: usize, _command: &Command) {}
+
+    fn write_arithmetic(&mut self, command: &str);
+
+    fn write_push_pop(&mut self, command: CommandType, segment: &str, index: i32);
+
+    fn write_label(&mut self, label: &str);
+
+    fn write_goto(&mut self, label: &str);
+
+    fn write_if(&mut self, label: &str);
+
+    fn write_function(&mut self, function_name: &str, num_vars: i32);
+
+    fn write_call(&mut self, function_name: &str, num_args: i32);
+
+    fn write_return(&mut self);
+
+    /// Called once after the last file, for targets that emit the program
+    /// as a whole.
+    fn finish(&mut self) {}
+}
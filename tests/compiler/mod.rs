@@ -1,10 +1,26 @@
 extern crate xml;
 
-use std::fs::File;
+use std::ffi::OsStr;
+use std::fs::{self, File};
 use std::io::BufReader;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
 
+use nand2tetris::assembler::Assembler;
+use nand2tetris::compiler::analyzer;
+use nand2tetris::compiler::ast::{Grouping, Statement};
+use nand2tetris::compiler::codegen;
 use nand2tetris::compiler::compilation_engine::CompilationEngine;
+use nand2tetris::compiler::formatter;
+use nand2tetris::compiler::linter::Linter;
+use nand2tetris::compiler::lsp;
+use nand2tetris::compiler::parser;
+use nand2tetris::compiler::tkzr::{self, Dialect, KeyWord, TokenType};
+use nand2tetris::compiler::Compiler;
+use nand2tetris::emulator::cpu::CPU;
+use nand2tetris::emulator::vm_emulator::VMEmulator;
+use nand2tetris::vm::VM;
+use serde_json::{json, Value};
 use xml::reader::{EventReader, XmlEvent};
 
 fn read_into_vec(path: &Path) -> Vec<String> {
@@ -114,3 +130,962 @@ fn test_array_test() {
     let other = Path::new("tests/compiler/ArrayTest/Main.xml");
     assert!(compare_two_files(one, other));
 }
+
+fn texts(source: &str) -> Vec<String> {
+    tkzr::tokenize(source)
+        .unwrap()
+        .into_iter()
+        .map(|token| token.text)
+        .collect()
+}
+
+#[test]
+fn test_tokenize_strings_and_comments() {
+    assert_eq!(
+        texts("let s = \"a  b\"; /* c */ let x=1;// d\n/** e\n */y"),
+        ["let", "s", "=", "\"a  b\"", ";", "let", "x", "=", "1", ";", "y"]
+    );
+    let tokens = tkzr::tokenize("do Output.printString(\"x < 1 // no\");").unwrap();
+    assert_eq!(
+        tokens[5].token_type,
+        TokenType::StringConst("x < 1 // no".to_string())
+    );
+    assert_eq!(texts("a_1+_b*32767"), ["a_1", "+", "_b", "*", "32767"]);
+}
+
+#[test]
+fn test_tokenize_spans() {
+    let tokens = tkzr::tokenize("class Main {\n  field int x; // x\n}").unwrap();
+    let spans: Vec<String> = tokens
+        .iter()
+        .map(|token| format!("{} {}-{}", token.text, token.span, token.span.end))
+        .collect();
+    assert_eq!(
+        spans,
+        [
+            "class 1:1-1:6",
+            "Main 1:7-1:11",
+            "{ 1:12-1:13",
+            "field 2:3-2:8",
+            "int 2:9-2:12",
+            "x 2:13-2:14",
+            "; 2:14-2:15",
+            "} 3:1-3:2",
+        ]
+    );
+}
+
+#[test]
+fn test_tokenize_errors() {
+    let error = |source: &str| tkzr::tokenize(source).unwrap_err().to_string();
+    assert_eq!(
+        error("let s = \"abc;\nlet"),
+        "1:9: unterminated string constant"
+    );
+    assert_eq!(
+        error("let x = 32768;"),
+        "1:9: integer constant 32768 is out of range, the largest is 32767"
+    );
+    assert_eq!(
+        error("let x = 99999999999;"),
+        "1:9: integer constant 99999999999 is out of range, the largest is 32767"
+    );
+    assert_eq!(
+        error("let x = 12ab;"),
+        "1:9: 12ab is not a valid integer constant"
+    );
+    assert_eq!(error("x\n  /* never closed"), "2:3: unterminated comment");
+    assert_eq!(error("let x = #;"), "1:9: unexpected character '#'");
+}
+
+// The (element, text) pairs of the elements that hold text, in order, which
+// for a parse tree are its tokens.
+fn leaves(path: &Path) -> Vec<(String, String)> {
+    let parser = EventReader::new(BufReader::new(File::open(path).unwrap()));
+    let mut open = Vec::new();
+    let mut res = Vec::new();
+    for e in parser {
+        match e.unwrap() {
+            XmlEvent::StartElement { name, .. } => open.push(name.to_string()),
+            XmlEvent::EndElement { .. } => {
+                open.pop();
+            }
+            XmlEvent::Characters(s) => {
+                res.push((open.last().unwrap().clone(), s.trim().to_string()));
+            }
+            _ => {}
+        }
+    }
+    res
+}
+
+#[test]
+fn test_tokens_xml() {
+    // The `XxxT.xml` fixtures here hold parse trees; their leaves are the
+    // token stream.
+    let out_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("tokens");
+    for dir in &["Square", "ExpressionLessSquare", "ArrayTest"] {
+        let dir = Path::new("tests/compiler").join(dir);
+        let out_dir = out_dir.join(dir.file_name().unwrap());
+        fs::create_dir_all(&out_dir).unwrap();
+        Compiler::new(&dir).write_tokens(&out_dir);
+        for entry in fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension() != Some(OsStr::new("jack")) {
+                continue;
+            }
+            let name = format!("{}T.xml", path.file_stem().unwrap().to_string_lossy());
+            let tokens = out_dir.join(&name);
+            assert!(leaves(&tokens).len() > 10);
+            assert_eq!(leaves(&tokens), leaves(&dir.join(&name)), "{}", name);
+        }
+    }
+}
+
+#[test]
+fn test_tokens_xml_escaping() {
+    let tokens = tkzr::tokenize("if (x < y & z > 0) { let s = \"a<b&c>d\"; }").unwrap();
+    let xml = tkzr::to_xml(&tokens);
+    assert!(xml.starts_with("<tokens>\r\n<keyword> if </keyword>\r\n<symbol> ( </symbol>\r\n"));
+    assert!(xml.contains("<symbol> &lt; </symbol>\r\n"));
+    assert!(xml.contains("<symbol> &amp; </symbol>\r\n"));
+    assert!(xml.contains("<symbol> &gt; </symbol>\r\n"));
+    assert!(xml.contains("<stringConstant> a&lt;b&amp;c&gt;d </stringConstant>\r\n"));
+    assert!(xml.ends_with("<symbol> } </symbol>\r\n</tokens>\r\n"));
+    assert_eq!(tkzr::escape_xml("say \"hi\""), "say &quot;hi&quot;");
+}
+
+#[test]
+fn test_analyze_clean_programs() {
+    for dir in &["Square", "ExpressionLessSquare", "ArrayTest"] {
+        let compiler = Compiler::new(&Path::new("tests/compiler").join(dir));
+        assert_eq!(compiler.analyze(false), []);
+    }
+}
+
+#[test]
+fn test_analyze_semantic_errors() {
+    let diagnostics: Vec<String> = Compiler::new(Path::new("tests/compiler/Semantic"))
+        .analyze(false)
+        .iter()
+        .map(|d| d.to_string().replace("tests/compiler/Semantic/", ""))
+        .collect();
+    assert_eq!(
+        diagnostics,
+        [
+            "Main.jack:7:20: x is already declared at 7:17",
+            "Main.jack:8:13: unknown class Circle",
+            "Main.jack:9:13: assignment to undeclared variable y",
+            "Main.jack:10:17: undeclared variable z",
+            "Main.jack:11:12: Shape.area is a method and needs an object",
+            "Main.jack:12:12: shape.grow expects 1 argument, found 2",
+            "Main.jack:13:18: unknown subroutine Shape.nope",
+            "Main.jack:14:28: this cannot be used in a function",
+            "Main.jack:15:28: field size cannot be used in a function",
+            "Main.jack:16:17: Shape.log is void and has no value",
+            "Main.jack:17:12: Main.draw is a method and cannot be called without an object \
+             from a function",
+            "Main.jack:18:12: x is an int and has no subroutines",
+            "Main.jack:19:12: unknown class or variable Nowhere",
+            "Main.jack:20:12: Math.max expects 2 arguments, found 1",
+            "Main.jack:25:9: Main.draw is void and cannot return a value",
+            "Main.jack:30:5: missing return at the end of Main.log",
+            "Main.jack:40:5: missing return at the end of Main.sign",
+            "Main.jack:47:13: Main.abs must return a value",
+            "Main.jack:49:5: missing return at the end of Main.abs",
+        ]
+    );
+}
+
+#[test]
+fn test_parse_errors() {
+    let error = |source: &str| {
+        let errors = parser::parse(source).unwrap_err();
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        errors[0].to_string()
+    };
+    assert_eq!(
+        error("class Main { function void f() { let x = ; } }"),
+        "expected an expression, found ';' at 1:42"
+    );
+    assert_eq!(
+        error("class Main { function void f() { return } }"),
+        "expected an expression, found '}' at 1:41"
+    );
+    assert_eq!(
+        error("class Main {\n  method void f() {\n    do g()\n"),
+        "expected ';', found end of file at 3:11"
+    );
+    assert_eq!(
+        error("class Main { } class"),
+        "expected end of file after the class, found 'class' at 1:16"
+    );
+    let class = parser::parse("class Main { function int f() { return -(1 + x[2]); } }").unwrap();
+    assert_eq!(class.subroutines[0].statements[0].span().start.column, 33);
+}
+
+#[test]
+fn test_parse_recovery() {
+    let source = "class Main {
+    field int x y;
+    method void f() {
+        var int a;
+        let a = ;
+        do g(;
+        while (a) { let a = a + ; }
+        if (a { return; }
+        return a;
+    }
+    method void g() {
+        let = 1;
+    }
+}
+";
+    let (class, errors) = parser::parse_partial(source);
+    let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+    assert_eq!(
+        errors,
+        [
+            "expected ';', found 'y' at 2:17",
+            "expected an expression, found ';' at 5:17",
+            "expected an expression, found ';' at 6:14",
+            "expected an expression, found ';' at 7:33",
+            "expected ')', found '{' at 8:15",
+            "expected a variable name, found '=' at 12:13",
+        ]
+    );
+    let names: Vec<&str> = class
+        .subroutines
+        .iter()
+        .map(|s| s.name.name.as_str())
+        .collect();
+    assert_eq!(names, ["f", "g"]);
+
+    // A body may start with any statement.
+    let source = "class Main { function void f() { while (true) { } return; } }";
+    let class = parser::parse(source).unwrap();
+    assert_eq!(class.subroutines[0].statements.len(), 2);
+}
+
+#[test]
+fn test_parse_malformed_input() {
+    let nested = format!(
+        "class Main {{ function void f() {{ let x = {}1; }} }}",
+        "(".repeat(10000)
+    );
+    let blocks = format!(
+        "class Main {{ function void f() {{ {} }} }}",
+        "while (1) {".repeat(10000)
+    );
+    let inputs = [
+        "",
+        "class",
+        "class Main {",
+        "class Main { function void f() {",
+        "class Main { function void f() { let x = 1",
+        "}}}} ;;; class Main { } }",
+        "class Main { method void f() { let x = 1; } var int y; }",
+        "class Main { static; field; function; method void ( }",
+        "let do while return if else { } ( ) [ ] . , ; = 1 \"x\" # @",
+        "class Main { function void f() { let a[ = \"unterminated\n; } }",
+        "class Main { /* unterminated comment",
+        &nested,
+        &blocks,
+    ];
+    for input in inputs.iter() {
+        assert!(parser::parse(input).is_err(), "{:?}", input);
+    }
+    let errors = parser::parse(&nested).unwrap_err();
+    assert_eq!(errors[0].message, "code is nested too deeply");
+}
+
+#[test]
+fn test_compilation_engine_errors() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("compilation_engine_errors");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("Main.jack");
+    fs::write(
+        &path,
+        "class Main {\n  function void main() {\n    while (x) { }\n    let = 2;\n    do ;\n  }\n}\n",
+    )
+    .unwrap();
+    let mut engine = CompilationEngine::new(&path);
+    let errors: Vec<String> = engine
+        .compile_class()
+        .iter()
+        .map(|e| e.to_string())
+        .collect();
+    assert_eq!(
+        errors,
+        [
+            "expected a variable name, found '=' at 4:9",
+            "expected a subroutine call, found ';' at 5:8",
+        ]
+    );
+    assert!(!dir.join("Main.xml").exists());
+}
+
+#[test]
+fn test_analyze_strict_types() {
+    for dir in &["Square", "ArrayTest"] {
+        let compiler = Compiler::new(&Path::new("tests/compiler").join(dir));
+        assert_eq!(compiler.analyze(true), []);
+    }
+    let compiler = Compiler::new(Path::new("tests/compiler/Types"));
+    assert_eq!(compiler.analyze(false), []);
+    let diagnostics: Vec<String> = compiler
+        .analyze(true)
+        .iter()
+        .map(|d| d.to_string().replace("tests/compiler/Types/", ""))
+        .collect();
+    assert_eq!(
+        diagnostics,
+        [
+            "Main.jack:10:17: cannot assign String to n, which is int",
+            "Main.jack:12:19: operator + needs int operands, found Point and int",
+            "Main.jack:13:22: operator < needs int operands, found String and int",
+            "Main.jack:14:13: condition of if is int, not boolean",
+            "Main.jack:15:24: operator ~ needs a boolean or int operand, found String",
+            "Main.jack:17:16: condition of while is char, not boolean",
+            "Main.jack:18:21: operator - needs an int operand, found boolean",
+            "Main.jack:20:23: cannot compare int with boolean",
+            "Main.jack:20:36: operator & needs two booleans or two ints, found int and boolean",
+            "Main.jack:21:27: argument 1 of Point.new must be int, found boolean",
+            "Main.jack:23:15: array index is boolean, not int",
+            "Main.jack:24:13: n is int, only an Array can be indexed",
+            "Main.jack:26:17: cannot assign null to n, which is int",
+            "Main.jack:27:31: argument 1 of Output.printString must be String, found int",
+            "Main.jack:32:16: Main.count must return int, found boolean",
+        ]
+    );
+}
+
+// Runs `jack-lsp` on a scripted session and returns what it sends back.
+fn lsp_session(messages: &[Value]) -> Vec<Value> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_jack-lsp"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    for message in messages {
+        lsp::write_message(&mut stdin, message).unwrap();
+    }
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let mut reader = output.stdout.as_slice();
+    let mut replies = Vec::new();
+    while let Some(body) = lsp::read_message(&mut reader).unwrap() {
+        replies.push(serde_json::from_slice(&body).unwrap());
+    }
+    replies
+}
+
+#[test]
+fn test_lsp_session() {
+    let dir = fs::canonicalize("tests/compiler/Square").unwrap();
+    let uri = format!("file://{}/Main.jack", dir.display());
+    let game_uri = format!("file://{}/SquareGame.jack", dir.display());
+    // The fixture has CRLF line endings.
+    let text = fs::read_to_string(dir.join("Main.jack"))
+        .unwrap()
+        .replace("\r\n", "\n");
+    let edited = text.replace(
+        "      return;\n    }\n\n    function void more()",
+        "      do Output.\n      do game.\n      return;\n    }\n\n    function void more()",
+    );
+    let request = |id: u32, method: &str, params: Value| json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+    let notification = |method: &str, params: Value| json!({ "jsonrpc": "2.0", "method": method, "params": params });
+    let at = |line: u32, character: u32| {
+        json!({
+            "textDocument": { "uri": uri },
+            "position": { "line": line, "character": character },
+        })
+    };
+    let replies = lsp_session(&[
+        request(1, "initialize", json!({ "capabilities": {} })),
+        notification("initialized", json!({})),
+        notification(
+            "textDocument/didOpen",
+            json!({ "textDocument": { "uri": uri, "languageId": "jack", "version": 1, "text": text } }),
+        ),
+        // `run` in `do game.run();`, `game` there and `SquareGame` in
+        // `let game = SquareGame.new();`.
+        request(2, "textDocument/definition", at(14, 15)),
+        request(3, "textDocument/definition", at(14, 9)),
+        request(4, "textDocument/definition", at(13, 17)),
+        request(5, "textDocument/hover", at(13, 29)),
+        request(6, "textDocument/hover", at(26, 16)),
+        request(
+            7,
+            "textDocument/documentSymbol",
+            json!({ "textDocument": { "uri": uri } }),
+        ),
+        notification(
+            "textDocument/didChange",
+            json!({
+                "textDocument": { "uri": uri, "version": 2 },
+                "contentChanges": [{ "text": edited }],
+            }),
+        ),
+        request(8, "textDocument/completion", at(16, 16)),
+        request(9, "textDocument/completion", at(17, 14)),
+        request(10, "textDocument/rename", at(14, 9)),
+        request(11, "shutdown", Value::Null),
+        notification("exit", Value::Null),
+    ]);
+
+    let result = |id: u32| {
+        let reply = replies.iter().find(|reply| reply["id"] == id).unwrap();
+        reply["result"].clone()
+    };
+    let range = |line: u32, start: u32, end: u32| {
+        json!({
+            "start": { "line": line, "character": start },
+            "end": { "line": line, "character": end },
+        })
+    };
+    let diagnostics: Vec<&Value> = replies
+        .iter()
+        .filter(|reply| reply["method"] == "textDocument/publishDiagnostics")
+        .map(|reply| &reply["params"])
+        .collect();
+
+    assert_eq!(result(1)["capabilities"]["definitionProvider"], true);
+    assert_eq!(diagnostics[0], &json!({ "uri": uri, "diagnostics": [] }));
+    assert_eq!(
+        result(2),
+        json!({ "uri": game_uri, "range": range(50, 15, 18) })
+    );
+    assert_eq!(result(3), json!({ "uri": uri, "range": range(12, 21, 25) }));
+    assert_eq!(
+        result(4),
+        json!({ "uri": game_uri, "range": range(18, 6, 16) })
+    );
+    assert_eq!(
+        result(5)["contents"]["value"],
+        "constructor SquareGame SquareGame.new()"
+    );
+    assert_eq!(result(6)["contents"]["value"], "var Array a");
+
+    let symbols = result(7);
+    assert_eq!(symbols[0]["name"], "Main");
+    let children: Vec<(&str, u64)> = symbols[0]["children"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|child| {
+            (
+                child["name"].as_str().unwrap(),
+                child["kind"].as_u64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(children, [("test", 13), ("main", 12), ("more", 12)]);
+
+    // The unfinished calls are syntax errors until completed.
+    let messages: Vec<&Value> = diagnostics[1]["diagnostics"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|diagnostic| &diagnostic["message"])
+        .collect();
+    assert_eq!(
+        messages,
+        [
+            "expected a subroutine name, found 'do'",
+            "expected a subroutine name, found 'return'",
+        ]
+    );
+    let labels = |id: u32| -> Vec<String> {
+        let items = result(id);
+        items
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["label"].as_str().unwrap().to_string())
+            .collect()
+    };
+    assert_eq!(
+        labels(8),
+        [
+            "backSpace",
+            "init",
+            "moveCursor",
+            "printChar",
+            "printInt",
+            "printString",
+            "println"
+        ]
+    );
+    assert_eq!(
+        result(8)[5]["detail"],
+        "function void Output.printString(String s)"
+    );
+    assert_eq!(labels(9), ["dispose", "moveSquare", "run"]);
+
+    let error = &replies.iter().find(|reply| reply["id"] == 10).unwrap()["error"];
+    assert_eq!(error["code"], -32601);
+    assert_eq!(result(11), Value::Null);
+}
+
+#[test]
+fn test_format_fixtures() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("format_fixtures");
+    fs::create_dir_all(&dir).unwrap();
+    for fixture in &[
+        "ArrayTest",
+        "ExpressionLessSquare",
+        "Semantic",
+        "Square",
+        "Types",
+    ] {
+        let compiler = Compiler::new(&Path::new("tests/compiler").join(fixture));
+        for file in compiler.files() {
+            let source = fs::read_to_string(file).unwrap();
+            let formatted = formatter::format(&source).unwrap();
+            assert_eq!(formatter::format(&formatted).unwrap(), formatted);
+
+            let comments = |source: &str| -> Vec<String> {
+                tkzr::comments(source)
+                    .iter()
+                    .map(|comment| comment.text.split_whitespace().collect())
+                    .collect()
+            };
+            assert_eq!(comments(&formatted), comments(&source));
+
+            let xml = |name: &str, source: &str| {
+                let path = dir.join(name).with_extension("jack");
+                fs::write(&path, source).unwrap();
+                assert_eq!(CompilationEngine::new(&path).compile_class(), []);
+                fs::read_to_string(path.with_extension("xml")).unwrap()
+            };
+            assert_eq!(xml("Original", &source), xml("Formatted", &formatted));
+        }
+    }
+}
+
+#[test]
+fn test_format_layout() {
+    let source = "class   Foo{
+  field int x,y;   /* trailing
+                      block */
+  method int f( int a /* first */,int b ){
+        /**
+         * Doc.
+         */
+    var int c;
+
+
+    let c = a+ /* inline */ b*(-a) ; let x = 1;
+    let y = a // mid
+      + b;
+    if(a<b){return c;}
+  // otherwise
+    else{
+       // only a comment
+    }
+    if(a){return a;} // trailing
+    else{return b;}
+    while (~(a = b)) { let a = a - 1; }
+    return
+       c;   \t
+  }
+}   // the end
+// after
+";
+    let expected = "class Foo {
+    field int x, y; /* trailing
+                       block */
+    method int f(int a /* first */, int b) {
+        /**
+         * Doc.
+         */
+        var int c;
+
+        let c = a + /* inline */ b * (-a);
+        let x = 1;
+        let y = a // mid
+            + b;
+        if (a < b) {
+            return c;
+        }
+        // otherwise
+        else {
+            // only a comment
+        }
+        if (a) {
+            return a;
+        } // trailing
+        else {
+            return b;
+        }
+        while (~(a = b)) {
+            let a = a - 1;
+        }
+        return c;
+    }
+} // the end
+// after
+";
+    assert_eq!(formatter::format(source).unwrap(), expected);
+    let errors = formatter::format("class Foo { method f() {} }").unwrap_err();
+    assert_eq!(
+        errors[0].to_string(),
+        "expected a subroutine name, found '(' at 1:21"
+    );
+}
+
+#[test]
+fn test_jackfmt_check() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("jackfmt_check");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("Main.jack");
+    let source = "class Main { function void main() { return; } }";
+    fs::write(&path, source).unwrap();
+    let jackfmt = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_jackfmt"))
+            .args(args)
+            .arg(&dir)
+            .output()
+            .unwrap()
+    };
+
+    let output = jackfmt(&["--check"]);
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        path.display().to_string()
+    );
+    assert_eq!(fs::read_to_string(&path).unwrap(), source);
+
+    assert!(jackfmt(&[]).status.success());
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        "class Main {\n    function void main() {\n        return;\n    }\n}\n"
+    );
+    assert!(jackfmt(&["--check"]).status.success());
+}
+
+#[test]
+fn test_lint_rules() {
+    let compiler = Compiler::new(Path::new("tests/compiler/Lint"));
+    let lint = |linter: &Linter| -> Vec<String> {
+        compiler
+            .lint(linter)
+            .iter()
+            .map(|w| w.to_string().replace("tests/compiler/Lint/", ""))
+            .collect()
+    };
+    let mut linter = Linter::new();
+    assert_eq!(
+        lint(&linter),
+        [
+            "Main.jack:3:15: warning: field count is never used [unused-field]",
+            "Main.jack:4:16: warning: static unused is never used [unused-field]",
+            "Main.jack:12:26: warning: parameter count shadows field count declared at 3:15 [shadowed-field]",
+            "Main.jack:12:26: warning: parameter count is never used [unused-parameter]",
+            "Main.jack:12:37: warning: parameter extra is never used [unused-parameter]",
+            "Main.jack:13:25: warning: local never is assigned but never read [unused-local]",
+            "Main.jack:14:19: warning: local copy is assigned but never read [unused-local]",
+            "Main.jack:15:13: warning: copy is created with Array.new but never disposed [undisposed-object]",
+            "Main.jack:16:17: warning: sum is read before any let assigns it [read-before-let]",
+            "Main.jack:22:24: warning: * is applied after +, as Jack evaluates operators left to right; add parentheses [precedence]",
+            "Main.jack:23:9: warning: unreachable statement after return [unreachable-code]",
+            "Main.jack:29:13: warning: main is created with Main.new but never disposed [undisposed-object]",
+            "Main.jack:32:9: warning: while (true) loop has no return to leave it [infinite-loop]",
+        ]
+    );
+
+    linter.disable("unused-field").unwrap();
+    linter.disable("unused-parameter").unwrap();
+    linter.disable("unused-local").unwrap();
+    linter.disable("undisposed-object").unwrap();
+    assert_eq!(
+        lint(&linter),
+        [
+            "Main.jack:12:26: warning: parameter count shadows field count declared at 3:15 [shadowed-field]",
+            "Main.jack:16:17: warning: sum is read before any let assigns it [read-before-let]",
+            "Main.jack:22:24: warning: * is applied after +, as Jack evaluates operators left to right; add parentheses [precedence]",
+            "Main.jack:23:9: warning: unreachable statement after return [unreachable-code]",
+            "Main.jack:32:9: warning: while (true) loop has no return to leave it [infinite-loop]",
+        ]
+    );
+    linter.disable("all").unwrap();
+    linter.enable("infinite-loop").unwrap();
+    assert_eq!(lint(&linter).len(), 1);
+    assert!(!linter.is_enabled("precedence"));
+    assert_eq!(
+        linter.enable("no-such-rule"),
+        Err("unknown lint rule no-such-rule".to_string())
+    );
+
+    // Nothing is left to warn about when compiling with precedence.
+    linter.enable("precedence").unwrap();
+    assert_eq!(
+        lint(&linter),
+        [
+            "Main.jack:22:24: warning: * is applied after +, as Jack evaluates operators left to right; add parentheses [precedence]",
+            "Main.jack:32:9: warning: while (true) loop has no return to leave it [infinite-loop]",
+        ]
+    );
+    linter.set_grouping(Grouping::Precedence);
+    assert_eq!(
+        lint(&linter),
+        ["Main.jack:32:9: warning: while (true) loop has no return to leave it [infinite-loop]"]
+    );
+
+    // The fixture still compiles cleanly.
+    assert_eq!(compiler.analyze(false), []);
+}
+
+#[test]
+fn test_tokenize_extended() {
+    let types = |source: &str, dialect: Dialect| -> Vec<TokenType> {
+        let (tokens, errors) = tkzr::tokenize_dialect(source, dialect);
+        assert_eq!(errors, []);
+        tokens.into_iter().map(|token| token.token_type).collect()
+    };
+    let source = "for x += 'a' 0x7fff 0xFFFF '\\'' '\\\\'";
+    assert_eq!(
+        types(source, Dialect::Extended),
+        [
+            TokenType::KeyWord(KeyWord::For),
+            TokenType::Identifier("x".to_string()),
+            TokenType::Symbol("+=".to_string()),
+            TokenType::IntConst(97),
+            TokenType::IntConst(32767),
+            TokenType::IntConst(-1),
+            TokenType::IntConst(39),
+            TokenType::IntConst(92),
+        ]
+    );
+    // In standard Jack the new key words are names.
+    assert_eq!(
+        types("for break += 0", Dialect::Standard),
+        [
+            TokenType::Identifier("for".to_string()),
+            TokenType::Identifier("break".to_string()),
+            TokenType::Symbol("+".to_string()),
+            TokenType::Symbol("=".to_string()),
+            TokenType::IntConst(0),
+        ]
+    );
+
+    let errors = |source: &str| -> Vec<String> {
+        let (_, errors) = tkzr::tokenize_dialect(source, Dialect::Extended);
+        errors.iter().map(|e| e.to_string()).collect()
+    };
+    assert_eq!(
+        errors("'ab' x '' 0x 0x10000 0xfg"),
+        [
+            "1:1: 'ab' is not a valid character constant, which is one printable character \
+             in single quotes",
+            "1:8: '' is not a valid character constant, which is one printable character \
+             in single quotes",
+            "1:11: 0x is not a valid hex constant",
+            "1:14: hex constant 0x10000 is out of range, the largest is 0xFFFF",
+            "1:22: 0xfg is not a valid hex constant",
+        ]
+    );
+}
+
+#[test]
+fn test_codegen() {
+    let source = "class Main {
+    static int n;
+    function int f(int x) {
+        let n = x + 1;
+        return -n;
+    }
+}";
+    let class = parser::parse(source).unwrap();
+    assert_eq!(
+        codegen::generate(&class, Grouping::LeftToRight),
+        "function Main.f 0
+push argument 0
+push constant 1
+add
+pop static 0
+push static 0
+neg
+return
+"
+    );
+}
+
+#[test]
+fn test_extended_dialect() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("Extended");
+    fs::create_dir_all(&dir).unwrap();
+    for entry in fs::read_dir("tests/compiler/Extended").unwrap() {
+        let path = entry.unwrap().path();
+        fs::copy(&path, dir.join(path.file_name().unwrap())).unwrap();
+    }
+    assert_ne!(Compiler::new(&dir).analyze(false), []);
+    let compiler = Compiler::new(&dir).with_dialect(Dialect::Extended);
+    assert_eq!(compiler.generate(), []);
+
+    // Standard VM code, on the standard emulator.
+    let mut emulator = VMEmulator::new(&dir);
+    emulator.run(1_000_000);
+    assert!(emulator.is_halted());
+    let expected = [25, 10, -1, 0, 1, 65, 25, -1, 0x34, 40, 14, 7, 3];
+    assert_eq!(emulator.ram[8000..8013], expected);
+
+    // And translated to Hack, where labels are global, on the CPU until
+    // Sys.init returns. That is also where the first function starts, but
+    // only the return restores the bootstrap's LCL of 0.
+    VM::new(&dir).translate();
+    let mut assembler = Assembler::new(&dir.with_extension("asm"));
+    assembler.run();
+    let ret = *assembler
+        .symbol_table()
+        .get_address("Sys.init$ret.0")
+        .unwrap() as u16;
+    let mut cpu = CPU::from_hack_file(&assembler.hack_path());
+    while !(cpu.pc == ret && cpu.ram[1] == 0) && cpu.cycles < 10_000_000 {
+        cpu.step();
+    }
+    assert_eq!(cpu.pc, ret);
+    assert_eq!(cpu.ram[8000..8013], expected);
+}
+
+#[test]
+fn test_extended_errors() {
+    let source = "class Main {
+    const int A = 1;
+    const int B = A * 2;
+    field int C;
+    const int C = 3;
+    const Array D = null;
+    const int E = C + 1;
+    function void f() {
+        var int i;
+        let A = 2;
+        let A += 1;
+        break;
+        for (i = true; i; i += false) {
+            continue;
+        }
+        while (true) {
+            if (i < B) {
+                break;
+            } else if (i) {
+                continue;
+            }
+        }
+        return;
+    }
+}";
+    let class = parser::parse_dialect(source, Dialect::Extended).unwrap();
+    let check = |strict: bool| -> Vec<String> {
+        analyzer::analyze(&[("Main.jack".to_string(), class.clone())], strict)
+            .iter()
+            .map(|d| d.to_string())
+            .collect()
+    };
+    let errors = [
+        "Main.jack:5:15: C is already declared at 4:15",
+        "Main.jack:6:11: constant D must be int, char or boolean",
+        "Main.jack:7:19: the value of constant E is not constant",
+        "Main.jack:10:13: cannot assign to constant A",
+        "Main.jack:11:13: cannot assign to constant A",
+        "Main.jack:12:9: break is not in a loop",
+    ];
+    assert_eq!(check(false), errors);
+    let mut strict = errors.to_vec();
+    strict.extend([
+        "Main.jack:13:18: cannot assign boolean to i, which is int",
+        "Main.jack:13:24: condition of for is int, not boolean",
+        "Main.jack:13:32: operator + needs int operands, found int and boolean",
+        "Main.jack:19:24: condition of if is int, not boolean",
+    ]);
+    assert_eq!(check(true), strict);
+
+    // None of it is standard Jack.
+    let errors = parser::parse(source).unwrap_err();
+    assert_eq!(
+        errors[0].to_string(),
+        "expected a subroutine declaration or '}', found 'const' at 2:5"
+    );
+}
+
+#[test]
+fn test_precedence_grouping() {
+    let value = |expression: &str, grouping: Grouping| {
+        let source = format!(
+            "class Main {{ function int f() {{ return {}; }} }}",
+            expression
+        );
+        let class = parser::parse(&source).unwrap();
+        match &class.subroutines[0].statements[0] {
+            Statement::Return {
+                value: Some(value), ..
+            } => value.value(&Default::default(), grouping),
+            statement => panic!("{:?}", statement),
+        }
+    };
+    let cases = [
+        ("1 + 2 * 3", 9, 7),
+        ("1 + 2 * 3 - 4 / 2", 2, 5),
+        ("8 - 4 - 2", 2, 2),
+        ("12 / 2 / 3", 2, 2),
+        ("2 | 1 = 1", 0, -1),
+        ("1 < 2 & (3 < 4)", -1, -1),
+        ("2 * (1 + 2)", 6, 6),
+    ];
+    for (expression, left_to_right, precedence) in cases {
+        assert_eq!(
+            value(expression, Grouping::LeftToRight),
+            Some(left_to_right),
+            "{}",
+            expression
+        );
+        assert_eq!(
+            value(expression, Grouping::Precedence),
+            Some(precedence),
+            "{}",
+            expression
+        );
+    }
+
+    let source = "class Main {
+    function boolean f(int x) {
+        return 1 + x * 3 < 4 & x > 0;
+    }
+}";
+    let class = parser::parse(source).unwrap();
+    assert_eq!(
+        codegen::generate(&class, Grouping::Precedence),
+        "function Main.f 0
+push constant 1
+push argument 0
+push constant 3
+call Math.multiply 2
+add
+push constant 4
+lt
+push argument 0
+push constant 0
+gt
+and
+return
+"
+    );
+
+    // Types follow the grouping too.
+    let program = [("Main.jack".to_string(), class)];
+    let check = |grouping| -> Vec<String> {
+        analyzer::analyze_grouped(&program, true, grouping)
+            .iter()
+            .map(|d| d.to_string())
+            .collect()
+    };
+    assert_eq!(
+        check(Grouping::LeftToRight),
+        ["Main.jack:3:30: operator & needs two booleans or two ints, found boolean and int"]
+    );
+    assert_eq!(check(Grouping::Precedence), Vec::<String>::new());
+}
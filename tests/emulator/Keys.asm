@@ -0,0 +1,31 @@
+// Stores the first three keys pressed at RAM[100], RAM[101] and RAM[102],
+// waiting for each key to be released before reading the next one.
+
+   @100
+   D=A
+   @R1
+   M=D              // R1 = next slot
+(WAIT)
+   @KBD
+   D=M
+   @WAIT
+   D;JEQ            // wait for a key
+   @R1
+   A=M
+   M=D              // *R1 = key
+   @R1
+   MD=M+1
+   @103
+   D=D-A
+   @END
+   D;JEQ            // three keys read
+(RELEASE)
+   @KBD
+   D=M
+   @RELEASE
+   D;JNE            // wait for the release
+   @WAIT
+   0;JMP
+(END)
+   @END
+   0;JMP
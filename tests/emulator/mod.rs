@@ -0,0 +1,772 @@
+use nand2tetris::assembler::Assembler;
+use nand2tetris::emulator::cpu::{Comp, Instruction, Jump, CPU, DEST_D, DEST_M, SCREEN};
+use nand2tetris::emulator::debugger::Debugger;
+use nand2tetris::emulator::differential::{Differential, Divergence};
+use nand2tetris::emulator::gdb::GdbServer;
+use nand2tetris::emulator::keyboard::{KeyEvent, Keyboard};
+use nand2tetris::emulator::profiler::{Profiler, SourceMap};
+use nand2tetris::emulator::recompiler;
+use nand2tetris::emulator::screen;
+use nand2tetris::emulator::snapshot;
+use nand2tetris::emulator::trace;
+use nand2tetris::emulator::vm_emulator::VMEmulator;
+use nand2tetris::vm::code::Code;
+use nand2tetris::vm::VM;
+use std::env;
+use std::fs;
+use std::io::prelude::*;
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+fn load(name: &str) -> CPU {
+    let mut path = Path::new("tests/assembler").join(name);
+    path.set_extension("cmp");
+    CPU::from_hack_file(&path)
+}
+
+#[test]
+fn test_decode() {
+    assert_eq!(
+        Instruction::decode(0b0000_0000_0001_0111),
+        Instruction::A(23)
+    );
+    // MD=M-1
+    assert_eq!(
+        Instruction::decode(0b1111_1100_1001_1000),
+        Instruction::C {
+            comp: Comp::MMinusOne,
+            dest: DEST_M | DEST_D,
+            jump: Jump::Null,
+        }
+    );
+    // D;JLE
+    assert_eq!(
+        Instruction::decode(0b1110_0011_0000_0110),
+        Instruction::C {
+            comp: Comp::D,
+            dest: 0,
+            jump: Jump::JLE,
+        }
+    );
+}
+
+#[test]
+fn test_alu_matches_named_computations() {
+    let (a, d, m) = (1234, -567, 89);
+    for bits in 0..128u16 {
+        let word = 0xe000 | (bits << 6);
+        if let Instruction::C { comp, .. } = Instruction::decode(word) {
+            if comp != Comp::Alu(bits as u8) {
+                assert_eq!(
+                    comp.compute(a, d, m),
+                    Comp::Alu(bits as u8).compute(a, d, m),
+                    "{:?}",
+                    comp
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_cpu_add() {
+    let mut cpu = load("Add");
+    // Add.asm has no halt loop, past its end the ROM reads as `@0`.
+    assert_eq!(cpu.run(1000), 1000);
+    assert_eq!(cpu.ram[0], 5);
+}
+
+#[test]
+fn test_cpu_max() {
+    let mut cpu = load("Max");
+    cpu.ram[0] = 7;
+    cpu.ram[1] = 12;
+    cpu.run(1000);
+    assert!(cpu.is_halted());
+    assert_eq!(cpu.ram[2], 12);
+}
+
+#[test]
+fn test_cpu_step_matches_run() {
+    let mut fast = load("Rect");
+    let mut slow = load("Rect");
+    fast.ram[0] = 5;
+    slow.ram[0] = 5;
+    let cycles = fast.run(10_000);
+    while !slow.is_halted() {
+        slow.step();
+    }
+    assert_eq!(slow.cycles, cycles);
+    assert_eq!((slow.a, slow.d, slow.pc), (fast.a, fast.d, fast.pc));
+    assert_eq!(slow.ram, fast.ram);
+    for row in 0..5 {
+        assert_eq!(fast.ram[SCREEN + 32 * row], -1);
+    }
+    assert_eq!(fast.ram[SCREEN + 32 * 5], 0);
+}
+
+#[test]
+fn test_cpu_runs_pong() {
+    let mut cpu = load("Pong");
+    assert_eq!(cpu.run(200_000), 200_000);
+    assert!(!cpu.is_halted());
+}
+
+#[test]
+fn test_debugger_session() {
+    let mut debugger = Debugger::load(Path::new("tests/assembler/Max.asm"));
+    debugger.cpu_mut().ram[0] = 3;
+    debugger.cpu_mut().ram[1] = 7;
+    let script = "\
+break OUTPUT_D
+watch R2
+continue
+print D
+print RAM[0..2]
+step 2
+continue
+x/3 R0
+info
+delete OUTPUT_D
+bogus
+quit
+step
+";
+    let mut output = Vec::new();
+    debugger.repl(script.as_bytes(), &mut output);
+    let output = String::from_utf8(output).unwrap();
+    let expected = "\
+(hdb) breakpoint at 12 (OUTPUT_D)
+(hdb) watchpoint on RAM[2] = 0
+(hdb) breakpoint
+12 (OUTPUT_D): @2
+(hdb) D = 7
+(hdb) RAM[0] = 3
+RAM[1] = 7
+(hdb) watchpoint RAM[2]: 0 -> 7
+14 (INFINITE_LOOP): @14
+(hdb) halted
+14 (INFINITE_LOOP): @14
+(hdb) 0: 0x0003 0x0007 0x0007
+(hdb) breakpoint at 12 (OUTPUT_D)
+watchpoint on RAM[2]
+(hdb) deleted breakpoint at 12 (OUTPUT_D)
+(hdb) error: unknown command bogus, try help
+(hdb) 
+";
+    assert_eq!(output, expected);
+    assert_eq!(debugger.cpu().pc, 14);
+}
+
+// Compares the screen with `tests/emulator/golden/<name>.pbm`. Run with
+// `UPDATE_GOLDEN=1` to rewrite the golden image after an intended change.
+fn assert_screen(cpu: &CPU, name: &str) {
+    let golden = Path::new("tests/emulator/golden").join(format!("{}.pbm", name));
+    let actual = screen::to_pbm(&cpu.ram);
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&golden, &actual).unwrap();
+    }
+    if fs::read(&golden).unwrap() != actual {
+        let mut path = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+        path.push(format!("{}.pbm", name));
+        fs::write(&path, &actual).unwrap();
+        panic!(
+            "screen differs from {}, see {}",
+            golden.display(),
+            path.display()
+        );
+    }
+}
+
+#[test]
+fn test_screen_rect() {
+    let mut cpu = load("Rect");
+    cpu.ram[0] = 40;
+    cpu.run(100_000);
+    assert!(cpu.is_halted());
+    assert_screen(&cpu, "Rect");
+    let terminal = screen::to_terminal(&cpu.ram, 1);
+    let lines: Vec<&str> = terminal.lines().collect();
+    assert_eq!(lines.len(), 128);
+    assert!(lines.iter().all(|line| line.chars().count() == 512));
+    assert_eq!(lines[0].trim_end(), "█".repeat(16));
+    assert_eq!(lines[19].trim_end(), "█".repeat(16));
+    assert_eq!(lines[20].trim_end(), "");
+}
+
+#[test]
+fn test_screen_pong() {
+    let mut cpu = load("Pong");
+    cpu.run(5_000_000);
+    assert_screen(&cpu, "Pong");
+}
+
+#[test]
+fn test_screen_png() {
+    let mut cpu = load("Rect");
+    cpu.ram[0] = 40;
+    cpu.run(100_000);
+    let png = screen::to_png(&cpu.ram);
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    assert_eq!(&png[12..16], b"IHDR");
+    // 512 x 256, bit depth 1, grayscale.
+    assert_eq!(&png[16..26], &[0, 0, 2, 0, 0, 0, 1, 0, 1, 0]);
+    assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+    // The zlib header, a final stored block of 16640 bytes, then the first
+    // row: its filter byte and 16 black pixels.
+    let idat = 8 + 25 + 8;
+    assert_eq!(&png[idat..idat + 2], &[0x78, 0x01]);
+    assert_eq!(&png[idat + 2..idat + 5], &[1, 0x00, 0x41]);
+    assert_eq!(&png[idat + 7..idat + 11], &[0, 0, 0, 0xff]);
+}
+
+const KEYS: &str = "\
+# letters, arrows and codes
+at cycle 100 press 'A'; at 200 release
+at 300 press up
+at 400 release; at 500 press 65
+at 1000 release
+at 2000 press f1
+";
+
+#[test]
+fn test_keyboard_parse() {
+    let keyboard = Keyboard::parse(KEYS).unwrap();
+    let keys: Vec<(u64, i16)> = keyboard
+        .events()
+        .iter()
+        .map(|event| (event.cycle, event.key))
+        .collect();
+    assert_eq!(
+        keys,
+        [
+            (100, 65),
+            (200, 0),
+            (300, 131),
+            (400, 0),
+            (500, 65),
+            (1000, 0),
+            (2000, 141)
+        ]
+    );
+    assert_eq!(
+        Keyboard::parse("at 10 press 1\nat ten release").unwrap_err(),
+        "line 2: at ten release: ten is not a cycle count"
+    );
+    assert!(Keyboard::parse("at 10 press nothing").is_err());
+    assert!(Keyboard::parse("press 10").is_err());
+}
+
+#[test]
+fn test_keyboard_cpu() {
+    let mut assembler = Assembler::new(Path::new("tests/emulator/Keys.asm"));
+    assembler.run();
+    let hack = assembler.hack_path();
+    let mut runs = Vec::new();
+    for _ in 0..2 {
+        let mut cpu = CPU::from_hack_file(&hack);
+        let mut keyboard = Keyboard::parse(KEYS).unwrap();
+        let cycles = keyboard.run(&mut cpu, 100_000);
+        assert!(cpu.is_halted());
+        assert_eq!(&cpu.ram[100..103], &[65, 131, 65]);
+        runs.push(cycles);
+    }
+    assert!(runs[0] > 500 && runs[0] < 2000);
+    assert_eq!(runs[0], runs[1]);
+}
+
+#[test]
+fn test_keyboard_vm() {
+    let mut runs = Vec::new();
+    for _ in 0..2 {
+        let mut vm = VMEmulator::new(Path::new("tests/emulator/Keys"));
+        let mut keyboard = Keyboard::new(vec![
+            KeyEvent { cycle: 50, key: 32 },
+            KeyEvent { cycle: 80, key: 0 },
+            KeyEvent { cycle: 90, key: 48 },
+            KeyEvent { cycle: 150, key: 0 },
+            KeyEvent {
+                cycle: 151,
+                key: 140,
+            },
+        ]);
+        let cycles = keyboard.run(&mut vm, 100_000);
+        assert!(vm.is_halted());
+        assert_eq!(&vm.ram[100..103], &[32, 48, 140]);
+        runs.push(cycles);
+    }
+    assert!(runs[0] > 151 && runs[0] < 100_000);
+    assert_eq!(runs[0], runs[1]);
+}
+
+#[test]
+fn test_keyboard_pong() {
+    // The bat keeps moving right until the left arrow is pressed.
+    let script = "at 5000000 press left; at 7000000 release";
+    let mut screens = Vec::new();
+    for keys in [script, script, ""].iter() {
+        let mut cpu = load("Pong");
+        Keyboard::parse(keys).unwrap().run(&mut cpu, 8_000_000);
+        assert_eq!(cpu.cycles, 8_000_000);
+        screens.push(screen::to_pbm(&cpu.ram));
+    }
+    assert!(screens[0] == screens[1]);
+    assert!(screens[0] != screens[2]);
+}
+
+#[test]
+fn test_vm_emulator_fib() {
+    let mut vm = VMEmulator::new(Path::new("tests/vm/Fib"));
+    let cycles = vm.run(1_000_000);
+    assert!(vm.is_halted());
+    assert_eq!(vm.run(10), 0);
+    assert_eq!(vm.cycles, cycles);
+    assert_eq!(vm.ram[5], 144);
+    assert_eq!(vm.ram[6], -9);
+    assert_eq!(vm.ram[0], 261);
+    let (location, command) = vm.source(vm.pc).unwrap();
+    assert_eq!(command.to_string(), "goto HALT");
+    assert!(location.starts_with("Sys.vm:"));
+}
+
+#[test]
+fn test_snapshot_pong() {
+    // Pong after 5M cycles, saved with `cpu -n 5000000 --save`.
+    let text = fs::read_to_string("tests/emulator/Pong.snapshot").unwrap();
+    let mut resumed = snapshot::load_cpu(&text).unwrap();
+    assert_eq!(snapshot::save_cpu(&resumed), text);
+    assert_eq!(resumed.cycles, 5_000_000);
+
+    let script = "at 5000000 press left; at 7000000 release";
+    Keyboard::parse(script)
+        .unwrap()
+        .run(&mut resumed, 3_000_000);
+    let mut fresh = load("Pong");
+    Keyboard::parse(script).unwrap().run(&mut fresh, 8_000_000);
+    assert_eq!(snapshot::save_cpu(&resumed), snapshot::save_cpu(&fresh));
+}
+
+#[test]
+fn test_snapshot_vm() {
+    let mut fresh = VMEmulator::new(Path::new("tests/vm/Fib"));
+    fresh.run(100);
+    let text = snapshot::save_vm(&fresh);
+    assert!(text.contains("# call stack: Sys.init > Main.fibonacci"));
+    let mut resumed = snapshot::load_vm(&text).unwrap();
+    assert_eq!(snapshot::save_vm(&resumed), text);
+
+    fresh.run(1_000_000);
+    resumed.run(1_000_000);
+    assert!(resumed.is_halted());
+    assert_eq!(resumed.ram[5], 144);
+    assert_eq!(snapshot::save_vm(&resumed), snapshot::save_vm(&fresh));
+}
+
+#[test]
+fn test_snapshot_errors() {
+    let vm = snapshot::save_vm(&VMEmulator::new(Path::new("tests/vm/Fib")));
+    assert_eq!(
+        snapshot::load_cpu(&vm).err().unwrap(),
+        "a vm snapshot, expected a cpu one"
+    );
+    assert!(snapshot::load_cpu("P4").is_err());
+    let text = "nand2tetris snapshot 1\nmachine cpu\ncycles 0\na 0\nd 0\npc 0\nrom 2\n0000\n";
+    assert_eq!(
+        snapshot::load_cpu(text).err().unwrap(),
+        "the ROM is cut short"
+    );
+    let text = text.replace("0000\n", "0000 zzzz\nram 0 0001\n");
+    assert_eq!(
+        snapshot::load_cpu(&text).err().unwrap(),
+        "line 8: zzzz is not a hex word"
+    );
+    let text = text.replace("zzzz", "0001");
+    assert_eq!(snapshot::load_cpu(&text).unwrap().ram[0], 1);
+}
+
+fn differential(path: &str) -> Differential {
+    let name = Path::new(path).file_stem().unwrap().to_string_lossy();
+    let mut asm = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    asm.push(format!("Diff{}.asm", name));
+    Differential::new(Path::new(path), &asm)
+}
+
+#[test]
+fn test_differential_agrees() {
+    let mut differential = differential("tests/vm/Fib");
+    assert_eq!(differential.run(1_000_000), None);
+    assert_eq!(differential.vm.ram[5], 144);
+}
+
+#[test]
+fn test_differential_overflow() {
+    let mut differential = differential("tests/emulator/Diff/Overflow.vm");
+    let divergence = differential.run(1_000).unwrap();
+    assert_eq!(
+        divergence,
+        Divergence::Word {
+            name: "temp 0".to_string(),
+            vm: -1,
+            cpu: 0,
+        }
+    );
+    assert_eq!(divergence.to_string(), "temp 0: VM emulator -1, CPU 0");
+}
+
+#[test]
+fn test_differential_labels() {
+    let mut differential = differential("tests/emulator/Diff/Labels.vm");
+    let divergence = differential.run(10_000).unwrap();
+    // The translator keeps labels global, so `Labels.count` jumps into
+    // `Labels.double` for its second iteration. The runs are compared as
+    // soon as it returns, with its result still on the stack.
+    assert_eq!(divergence.to_string(), "stack[5]: VM emulator 6, CPU 7");
+    assert_eq!(differential.vm.ram[5], 0);
+}
+
+#[test]
+fn test_differential_never_halts() {
+    let mut differential = differential("tests/emulator/Diff/Forever.vm");
+    assert_eq!(differential.run(10_000), None);
+    assert!(!differential.vm.is_halted());
+    assert!(differential.vm.ram[16] > 0);
+    assert!(differential.checkpoints > 0);
+}
+
+#[test]
+fn test_differential_no_checkpoint() {
+    // Sys.init's callee never returns, so the runs are never compared.
+    let mut differential = differential("tests/emulator/Diff/Stuck.vm");
+    assert_eq!(differential.run(10_000), None);
+    assert_eq!(differential.checkpoints, 0);
+}
+
+#[test]
+fn test_trace() {
+    let mut cpu = load("Max");
+    cpu.ram[0] = 3;
+    cpu.ram[1] = 7;
+    let mut out = Vec::new();
+    let cycles = trace::trace(&mut cpu, 1000, &mut out);
+    let out = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len() as u64, cycles + 1);
+    assert_eq!(lines[0], trace::HEADER);
+    assert_eq!(lines[1], "0 @0 0 0");
+    assert_eq!(lines[4], "3 D=D-M 1 -4");
+    assert_eq!(lines[cycles as usize], "13 M=D 2 7 2=7");
+}
+
+#[test]
+fn test_profiler_vm_functions() {
+    let asm = {
+        let mut path = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+        path.push("ProfileFib.asm");
+        path
+    };
+    let mut code = Code::new(&asm);
+    code.set_annotated(true);
+    VM::with_writer(Path::new("tests/vm/Fib"), Box::new(code)).translate();
+    let mut assembler = Assembler::new(&asm);
+    assembler.run();
+    let mut cpu = CPU::from_hack_file(&assembler.hack_path());
+    let source_map = SourceMap::from_asm(&asm);
+    assert_eq!(source_map.function_at(0), None);
+    let entry = *assembler.symbol_table().get_address("Main.misc").unwrap() as u16;
+    assert_eq!(source_map.function_entry(entry), Some("Main.misc"));
+    assert_eq!(source_map.function_at(entry + 1), Some("Main.misc"));
+
+    let mut profiler = Profiler::new(
+        assembler.symbol_table(),
+        cpu.words().len(),
+        Some(source_map),
+    );
+    let cycles = profiler.run(&mut cpu, 1_000_000);
+    assert!(cpu.is_halted());
+    assert_eq!(cpu.ram[5], 144);
+
+    let functions = profiler.functions();
+    let names: Vec<&str> = functions.iter().map(|(name, _)| *name).collect();
+    assert_eq!(
+        names,
+        ["Main.fibonacci", "Main.misc", "Sys.init", "(bootstrap)"]
+    );
+    assert_eq!(functions.iter().map(|(_, c)| c).sum::<u64>(), cycles);
+    assert_eq!(
+        profiler.labels().iter().map(|(_, c)| c).sum::<u64>(),
+        cycles
+    );
+
+    let folded = profiler.folded();
+    let mut total = 0;
+    for line in folded.lines() {
+        let (stack, count) = line.split_at(line.rfind(' ').unwrap());
+        assert!(stack == "(bootstrap)" || stack.starts_with("Sys.init"));
+        total += count.trim().parse::<u64>().unwrap();
+    }
+    assert_eq!(total, cycles);
+    // fibonacci(12) recurses 12 levels deep.
+    let deepest = format!("Sys.init{} ", ";Main.fibonacci".repeat(12));
+    assert!(folded.contains(&deepest));
+    assert!(!folded.contains(&format!("Sys.init{}", ";Main.fibonacci".repeat(13))));
+    assert!(folded.contains("\nSys.init;Main.misc "));
+    assert!(profiler.flat().contains("%  function\n"));
+}
+
+#[test]
+fn test_profiler_loop_is_no_call() {
+    // Sys.init has no locals and starts with the label it loops back to.
+    let asm = {
+        let mut path = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+        path.push("ProfileForever.asm");
+        path
+    };
+    let mut code = Code::new(&asm);
+    code.set_annotated(true);
+    VM::with_writer(Path::new("tests/emulator/Diff/Forever.vm"), Box::new(code)).translate();
+    let mut assembler = Assembler::new(&asm);
+    assembler.run();
+    let mut cpu = CPU::from_hack_file(&assembler.hack_path());
+    let mut profiler = Profiler::new(
+        assembler.symbol_table(),
+        cpu.words().len(),
+        Some(SourceMap::from_asm(&asm)),
+    );
+    profiler.run(&mut cpu, 10_000);
+
+    let folded = profiler.folded();
+    let stacks: Vec<&str> = folded
+        .lines()
+        .map(|line| line.rsplit_once(' ').unwrap().0)
+        .collect();
+    assert_eq!(stacks, ["(bootstrap)", "Sys.init", "Sys.init;Forever.inc"]);
+    let labels: Vec<&str> = profiler.labels().iter().map(|(name, _)| *name).collect();
+    assert!(labels.contains(&"Sys.init"));
+    assert!(!labels.contains(&"Sys.init$ret.0"));
+}
+
+#[test]
+fn test_profiler_labels() {
+    let mut assembler = Assembler::new(Path::new("tests/assembler/Max.asm"));
+    assembler.run();
+    let mut cpu = CPU::from_hack_file(&assembler.hack_path());
+    let mut profiler = Profiler::new(assembler.symbol_table(), cpu.words().len(), None);
+    profiler.run(&mut cpu, 1000);
+    // R0 = R1 = 0, so Max falls through to OUTPUT_D.
+    assert_eq!(profiler.labels(), [("(no label)", 10), ("OUTPUT_D", 2)]);
+    assert_eq!(profiler.folded(), "(no label) 10\nOUTPUT_D 2\n");
+    assert!(!profiler.flat().contains("function"));
+}
+
+// A scripted GDB remote protocol client.
+struct GdbClient {
+    stream: TcpStream,
+}
+
+impl GdbClient {
+    fn connect(cpu: CPU) -> (Self, thread::JoinHandle<CPU>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut server = GdbServer::new(cpu);
+            server.serve(&listener).unwrap();
+            server.cpu
+        });
+        let stream = TcpStream::connect(address).unwrap();
+        (GdbClient { stream }, server)
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let mut byte = [0];
+        self.stream.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+
+    fn send(&mut self, data: &str) -> String {
+        let sum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        write!(self.stream, "${}#{:02x}", data, sum).unwrap();
+        assert_eq!(self.read_byte(), b'+');
+        self.reply()
+    }
+
+    fn reply(&mut self) -> String {
+        assert_eq!(self.read_byte(), b'$');
+        let mut data = Vec::new();
+        loop {
+            match self.read_byte() {
+                b'#' => break,
+                byte => data.push(byte),
+            }
+        }
+        let checksum = [self.read_byte(), self.read_byte()];
+        let sum = data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        assert_eq!(
+            std::str::from_utf8(&checksum).unwrap(),
+            format!("{:02x}", sum)
+        );
+        self.stream.write_all(b"+").unwrap();
+        String::from_utf8(data).unwrap()
+    }
+}
+
+#[test]
+fn test_gdb_session() {
+    let (mut gdb, server) = GdbClient::connect(load("Max"));
+    assert!(gdb
+        .send("qSupported:multiprocess+;swbreak+")
+        .contains("qXfer:features:read+"));
+    let xml = gdb.send("qXfer:features:read:target.xml:0,fff");
+    assert!(xml.starts_with("l<?xml"));
+    assert!(xml.contains(r#"<reg name="pc" bitsize="32" type="code_ptr""#));
+    assert_eq!(gdb.send("?"), "S05");
+    assert_eq!(gdb.send("g"), "0000000000000000");
+
+    // R0 = 3, R1 = 7
+    assert_eq!(gdb.send("M800000,4:03000700"), "OK");
+    assert_eq!(gdb.send("m800000,4"), "03000700");
+    // @R0 and D=M (0xfc10) at the start of the ROM.
+    assert_eq!(gdb.send("m0,4"), "000010fc");
+    assert_eq!(gdb.send("M0,2:0000"), "E02");
+    // Reads past the end are cut short, reads from there fail.
+    assert_eq!(gdb.send("m80fffe,ffffffff"), "0000");
+    assert_eq!(gdb.send("m810000,2"), "E01");
+    assert_eq!(gdb.send("m1000,2"), "E01");
+
+    // A breakpoint at OUTPUT_D, ROM address 12.
+    assert_eq!(gdb.send("Z0,18,2"), "OK");
+    assert_eq!(gdb.send("vCont;c"), "S05");
+    assert_eq!(gdb.send("p2"), "18000000");
+    assert_eq!(gdb.send("s"), "S05");
+    assert_eq!(gdb.send("g"), "020007001a000000");
+    assert_eq!(gdb.send("z0,18,2"), "OK");
+    assert_eq!(gdb.send("P1=0900"), "OK");
+    // Runs into the final loop.
+    assert_eq!(gdb.send("c"), "S05");
+    assert_eq!(gdb.send("p2"), "1c000000");
+    assert_eq!(gdb.send("m800004,2"), "0900");
+    assert_eq!(gdb.send("vMustReplyEmpty"), "");
+
+    write!(gdb.stream, "$k#6b").unwrap();
+    assert_eq!(gdb.read_byte(), b'+');
+    let cpu = server.join().unwrap();
+    assert_eq!(cpu.ram[2], 9);
+}
+
+#[test]
+fn test_gdb_interrupt() {
+    // Keys waits for a key forever.
+    let mut assembler = Assembler::new(Path::new("tests/emulator/Keys.asm"));
+    assembler.run();
+    let (mut gdb, server) = GdbClient::connect(CPU::from_hack_file(&assembler.hack_path()));
+    assert_eq!(gdb.send("QStartNoAckMode"), "OK");
+    gdb.stream.write_all(b"$c#63").unwrap();
+    thread::sleep(Duration::from_millis(50));
+    gdb.stream.write_all(&[0x03]).unwrap();
+    assert_eq!(gdb.reply(), "S02");
+    gdb.stream.write_all(b"$D#44").unwrap();
+    assert_eq!(gdb.reply(), "OK");
+    assert!(server.join().unwrap().cycles > 0);
+}
+
+// Recompiles `cpu`'s program to C, builds it with the system C compiler and
+// runs it with `args`. Returns the cycles run and the final RAM, or None if
+// there is no C compiler.
+fn run_recompiled(name: &str, cpu: &CPU, args: &[&str]) -> Option<(u64, Vec<i16>)> {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let source = dir.join(format!("Recompiled{}.c", name));
+    let program = dir.join(format!("Recompiled{}", name));
+    let ram = dir.join(format!("Recompiled{}.ram", name));
+    fs::write(&source, recompiler::recompile(cpu.words())).unwrap();
+    let cc = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let status = match Command::new(&cc)
+        .arg("-O1")
+        .arg("-o")
+        .arg(&program)
+        .arg(&source)
+        .status()
+    {
+        Ok(status) => status,
+        Err(_) => {
+            eprintln!("no C compiler ({}), skipping", cc);
+            return None;
+        }
+    };
+    assert!(status.success());
+    let output = Command::new(&program)
+        .args(args)
+        .arg("-o")
+        .arg(&ram)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let cycles = stdout
+        .strip_prefix("ran ")
+        .and_then(|s| s.split(' ').next())
+        .unwrap()
+        .parse()
+        .unwrap();
+    let ram = fs::read(&ram)
+        .unwrap()
+        .chunks(2)
+        .map(|word| i16::from_le_bytes([word[0], word[1]]))
+        .collect();
+    Some((cycles, ram))
+}
+
+#[test]
+fn test_basic_blocks() {
+    let cpu = load("Max");
+    let blocks = recompiler::basic_blocks(cpu.words());
+    let starts: Vec<u16> = blocks.iter().map(|block| block.start).collect();
+    assert_eq!(starts, [0, 1, 2, 6, 10, 12, 14]);
+    assert_eq!(blocks.last().unwrap().end, 16);
+}
+
+#[test]
+fn test_recompiled_max() {
+    let mut cpu = load("Max");
+    let (cycles, ram) = match run_recompiled("Max", &cpu, &["0=7", "1=12"]) {
+        Some(run) => run,
+        None => return,
+    };
+    cpu.ram[0] = 7;
+    cpu.ram[1] = 12;
+    assert_eq!(cycles, cpu.run(1000));
+    assert_eq!(ram, cpu.ram);
+    assert_eq!(ram[2], 12);
+}
+
+#[test]
+fn test_recompiled_fib() {
+    // The VM translation returns from functions through computed jumps.
+    let asm = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("RecompiledFib.asm");
+    VM::with_writer(Path::new("tests/vm/Fib"), Box::new(Code::new(&asm))).translate();
+    let mut assembler = Assembler::new(&asm);
+    assembler.run();
+    let mut cpu = CPU::from_hack_file(&assembler.hack_path());
+    let (cycles, ram) = match run_recompiled("Fib", &cpu, &[]) {
+        Some(run) => run,
+        None => return,
+    };
+    assert_eq!(cycles, cpu.run(10_000_000));
+    assert!(cpu.is_halted());
+    assert_eq!(ram, cpu.ram);
+    assert_eq!(ram[5], 144);
+}
+
+#[test]
+fn test_recompiled_pong() {
+    let mut cpu = load("Pong");
+    let (cycles, ram) = match run_recompiled("Pong", &cpu, &["-n", "5000000"]) {
+        Some(run) => run,
+        None => return,
+    };
+    // The cycle limit is checked per block.
+    assert!((5_000_000..5_000_100).contains(&cycles));
+    assert_eq!(cpu.run(cycles), cycles);
+    assert_eq!(ram, cpu.ram);
+}
@@ -1,2 +1,4 @@
 mod assembler;
 mod compiler;
+mod emulator;
+mod vm;
@@ -0,0 +1,371 @@
+use nand2tetris::assembler::Assembler;
+use nand2tetris::emulator::cpu::CPU;
+use nand2tetris::vm::c_code::CCode;
+use nand2tetris::vm::code::Code;
+use nand2tetris::vm::optimizer::Optimizer;
+use nand2tetris::vm::parser::{CommandType, Parser, SourceCommand};
+use nand2tetris::vm::wat_code::WatCode;
+use nand2tetris::vm::writer::CodeWriter;
+use nand2tetris::vm::{self, VM};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::rc::Rc;
+
+fn tmp_path(name: &str) -> PathBuf {
+    let mut path = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    path.push(name);
+    path
+}
+
+fn parse(name: &str, source: &str) -> Vec<SourceCommand> {
+    let path = tmp_path(name);
+    fs::write(&path, source).unwrap();
+    Parser::new(&path).commands()
+}
+
+fn optimize(optimizer: &mut Optimizer, name: &str, source: &str) -> Vec<String> {
+    let commands = parse(name, source);
+    optimizer
+        .optimize(commands)
+        .iter()
+        .map(|c| c.command.to_string())
+        .collect()
+}
+
+#[test]
+fn test_parser_commands_keep_lines() {
+    let commands = parse(
+        "Lines.vm",
+        "// comment\npush constant 1\n\n  add // trailing\n",
+    );
+    assert_eq!(commands.len(), 2);
+    assert_eq!(commands[0].line, 2);
+    assert_eq!(commands[0].command.to_string(), "push constant 1");
+    assert_eq!(commands[1].line, 4);
+    assert_eq!(commands[1].command.to_string(), "add");
+}
+
+#[test]
+fn test_optimizer_fold_constants() {
+    let mut optimizer = Optimizer::none();
+    optimizer.fold_constants = true;
+    let source = "push constant 2\npush constant 3\nadd\npush constant 7\nsub\n\
+                  push constant 5\npush constant 5\neq\nand\n";
+    let res = optimize(&mut optimizer, "Fold.vm", source);
+    // (2 + 3 - 7) & (5 == 5) = -2 & -1 = -2
+    assert_eq!(res, vec!["push constant 2", "neg"]);
+}
+
+#[test]
+fn test_optimizer_cancel_push_pop() {
+    let mut optimizer = Optimizer::none();
+    optimizer.cancel_push_pop = true;
+    let source = "push local 2\npop local 2\npush local 2\npop local 3\n\
+                  push constant 1\npop constant 1\n";
+    let res = optimize(&mut optimizer, "Cancel.vm", source);
+    assert_eq!(
+        res,
+        vec![
+            "push local 2",
+            "pop local 3",
+            "push constant 1",
+            "pop constant 1"
+        ]
+    );
+}
+
+#[test]
+fn test_optimizer_invert_not_branch() {
+    let mut optimizer = Optimizer::none();
+    optimizer.invert_not_branch = true;
+    let source = "lt\nnot\nif-goto END\npush local 0\nnot\nif-goto END\n";
+    let res = optimize(&mut optimizer, "Invert.vm", source);
+    assert_eq!(
+        res,
+        vec![
+            "lt",
+            "if-goto END$skip.0",
+            "goto END",
+            "label END$skip.0",
+            "push local 0",
+            "not",
+            "if-goto END"
+        ]
+    );
+}
+
+#[test]
+fn test_optimizer_fuse_zero_check() {
+    let mut optimizer = Optimizer::none();
+    optimizer.fuse_zero_check = true;
+    let source = "push local 0\npush constant 0\neq\nif-goto END\ngt\npush constant 0\neq\n";
+    let res = optimize(&mut optimizer, "Zero.vm", source);
+    assert_eq!(
+        res,
+        vec![
+            "push local 0",
+            "if-goto END$skip.0",
+            "goto END",
+            "label END$skip.0",
+            "gt",
+            "not"
+        ]
+    );
+}
+
+#[test]
+fn test_optimizer_none_is_identity() {
+    let source = fs::read_to_string("tests/vm/Optimize.vm").unwrap();
+    let commands = parse("Identity.vm", &source);
+    let res = Optimizer::none().optimize(commands.clone());
+    assert_eq!(res, commands);
+}
+
+// Runs tests/vm/Optimize.vm translated to `name`.asm on the CPU until it
+// halts.
+fn run_optimize(name: &str, optimizer: Option<Optimizer>) -> CPU {
+    let asm = tmp_path(&format!("{}.asm", name));
+    let mut vm = VM::with_writer(Path::new("tests/vm/Optimize.vm"), Box::new(Code::new(&asm)));
+    if let Some(optimizer) = optimizer {
+        vm.set_optimizer(optimizer);
+    }
+    vm.translate();
+    let mut assembler = Assembler::new(&asm);
+    assembler.run();
+    let mut cpu = CPU::from_hack_file(&assembler.hack_path());
+    cpu.run(100_000);
+    assert!(cpu.is_halted());
+    cpu
+}
+
+#[test]
+fn test_optimized_translation_runs_the_same() {
+    let plain = run_optimize("Unoptimized", None);
+    let optimized = run_optimize("Optimized", Some(Optimizer::new()));
+    assert!(optimized.words().len() < plain.words().len());
+    // local 0 counts up to 10.
+    assert_eq!(plain.ram[plain.ram[1] as usize], 10);
+    // R13-R15 and the stack above SP are scratch space.
+    let sp = plain.ram[0] as usize;
+    assert_eq!(optimized.ram[..13], plain.ram[..13]);
+    assert_eq!(optimized.ram[256..sp], plain.ram[256..sp]);
+    assert_eq!(optimized.ram[2048..], plain.ram[2048..]);
+}
+
+#[test]
+fn test_verifier_accepts_valid_program() {
+    let report = vm::verify(Path::new("tests/vm/Optimize.vm"));
+    assert!(report.is_ok(), "{:?}", report.diagnostics);
+    assert_eq!(report.function("Sys.init").unwrap().max_stack_depth, 2);
+}
+
+#[test]
+fn test_verifier_reports_errors() {
+    let report = vm::verify(Path::new("tests/vm/Verify"));
+    let messages: Vec<String> = report.diagnostics.iter().map(|d| d.to_string()).collect();
+    let expected = [
+        "Main.vm:2: temp index must be between 0 and 7, found 12",
+        "Main.vm:3: cannot pop to the constant segment",
+        "Main.vm:4: constant index must be between 0 and 32767, found 70000",
+        "Main.vm:5: pointer index must be between 0 and 1, found 2",
+        "Main.vm:11: inconsistent stack height at label SKIP: 2 vs 1",
+        "Main.vm:21: `add` needs 2 values but the stack holds 0",
+        "Main.vm:6: call Main.add 1 passes too few arguments, Main.add uses argument 1",
+        "Main.vm:12: call to undeclared function Main.missing",
+    ];
+    for message in expected.iter() {
+        assert!(messages.contains(&message.to_string()), "{:?}", messages);
+    }
+    assert_eq!(messages.len(), expected.len(), "{:?}", messages);
+    assert_eq!(report.function("Main.add").unwrap().num_args_used, 2);
+}
+
+/// Records the writer calls it receives.
+struct Recorder {
+    calls: Rc<RefCell<Vec<String>>>,
+}
+
+impl Recorder {
+    fn record(&mut self, call: String) {
+        self.calls.borrow_mut().push(call);
+    }
+}
+
+impl CodeWriter for Recorder {
+    fn set_filename(&mut self, filename: &str) {
+        self.record(format!("file {}", filename));
+    }
+
+    fn write_init(&mut self) {
+        self.record("init".to_string());
+    }
+
+    fn write_arithmetic(&mut self, command: &str) {
+        self.record(command.to_string());
+    }
+
+    fn write_push_pop(&mut self, command: CommandType, segment: &str, index: i32) {
+        self.record(format!("{:?} {} {}", command, segment, index));
+    }
+
+    fn write_label(&mut self, label: &str) {
+        self.record(format!("label {}", label));
+    }
+
+    fn write_goto(&mut self, label: &str) {
+        self.record(format!("goto {}", label));
+    }
+
+    fn write_if(&mut self, label: &str) {
+        self.record(format!("if-goto {}", label));
+    }
+
+    fn write_function(&mut self, function_name: &str, num_vars: i32) {
+        self.record(format!("function {} {}", function_name, num_vars));
+    }
+
+    fn write_call(&mut self, function_name: &str, num_args: i32) {
+        self.record(format!("call {} {}", function_name, num_args));
+    }
+
+    fn write_return(&mut self) {
+        self.record("return".to_string());
+    }
+
+    fn finish(&mut self) {
+        self.record("finish".to_string());
+    }
+}
+
+#[test]
+fn test_translate_with_custom_writer() {
+    let calls = Rc::new(RefCell::new(Vec::new()));
+    let recorder = Recorder {
+        calls: calls.clone(),
+    };
+    let mut vm = VM::with_writer(Path::new("tests/vm/Verify"), Box::new(recorder));
+    vm.translate();
+    let calls = calls.borrow();
+    assert_eq!(
+        calls[..4],
+        [
+            "init",
+            "file Main.vm",
+            "function Main.main 1",
+            "PUSH temp 12"
+        ]
+    );
+    assert!(calls.contains(&"call Main.add 1".to_string()));
+    assert_eq!(calls.last().unwrap(), "finish");
+}
+
+fn run_c(dir: &str, name: &str, addresses: &[&str]) -> String {
+    let c_path = tmp_path(&format!("{}.c", name));
+    let mut vm = VM::with_writer(Path::new(dir), Box::new(CCode::new(&c_path)));
+    vm.translate();
+    let exe = tmp_path(name);
+    let status = Command::new("cc")
+        .arg("-O1")
+        .arg("-o")
+        .arg(&exe)
+        .arg(&c_path)
+        .status()
+        .expect("the C backend tests need a C compiler on the PATH as `cc`");
+    assert!(status.success());
+    let output = Command::new(&exe).args(addresses).output().unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_c_backend_runs_program() {
+    let output = run_c("tests/vm/Fib", "Fib", &["5", "6", "0", "16", "3001"]);
+    // fib(12) = 144; misc = ~(12 & 10 | 1) - (-1 & -1) = -10 + 1
+    assert_eq!(
+        output,
+        "RAM[5] = 144\nRAM[6] = -9\nRAM[0] = 261\nRAM[16] = -1\nRAM[3001] = -32767\n"
+    );
+}
+
+#[test]
+fn test_c_backend_scopes_labels() {
+    // Each VM function is a C function, so both `LOOP` labels are kept apart.
+    let output = run_c("tests/emulator/Diff/Labels.vm", "Labels", &["5", "6"]);
+    assert_eq!(output, "RAM[5] = 6\nRAM[6] = 8\n");
+}
+
+#[test]
+fn test_wat_backend_is_valid_wasm() {
+    let wat_path = tmp_path("Fib.wat");
+    let mut vm = VM::with_writer(Path::new("tests/vm/Fib"), Box::new(WatCode::new(&wat_path)));
+    vm.translate();
+    let text = fs::read_to_string(&wat_path).unwrap();
+    let wasm = wat::parse_str(&text).unwrap();
+    wasmparser::validate(&wasm).unwrap();
+    for export in ["memory", "screen", "screen_words", "keyboard", "run"].iter() {
+        assert!(text.contains(&format!("(export \"{}\")", export)));
+    }
+}
+
+#[test]
+fn test_wat_backend_scopes_labels() {
+    let wat_path = tmp_path("Labels.wat");
+    let path = Path::new("tests/emulator/Diff/Labels.vm");
+    VM::with_writer(path, Box::new(WatCode::new(&wat_path))).translate();
+    let text = fs::read_to_string(&wat_path).unwrap();
+    // Each segment starts with `end ;; $B<id> <name>`, and jumps set `$pc`
+    // to the id of their target.
+    let mut ids = HashMap::new();
+    let mut segment = String::new();
+    let mut branches = Vec::new();
+    for line in text.lines() {
+        if let Some(rest) = line.trim().strip_prefix("end ;; $B") {
+            let (id, name) = rest.split_once(' ').unwrap_or((rest, ""));
+            ids.insert(name.to_string(), id.to_string());
+            segment = name.to_string();
+        } else if line.trim().starts_with("(if (i32.ne (call $pop)") {
+            let target = line.split("(i32.const ").last().unwrap();
+            let target = target.split(')').next().unwrap().to_string();
+            branches.push((segment.clone(), target));
+        }
+    }
+    assert_ne!(ids["Labels.count$LOOP"], ids["Labels.double$LOOP"]);
+    assert_eq!(
+        branches,
+        [
+            (
+                "Labels.count$LOOP".to_string(),
+                ids["Labels.count$LOOP"].clone()
+            ),
+            (
+                "Labels.double$LOOP".to_string(),
+                ids["Labels.double$LOOP"].clone()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_annotated_assembly() {
+    let plain = tmp_path("Plain.asm");
+    let annotated = tmp_path("Annotated.asm");
+    VM::with_writer(Path::new("tests/vm/Fib"), Box::new(Code::new(&plain))).translate();
+    let mut code = Code::new(&annotated);
+    code.set_annotated(true);
+    VM::with_writer(Path::new("tests/vm/Fib"), Box::new(code)).translate();
+
+    let text = fs::read_to_string(&annotated).unwrap();
+    assert!(text.contains("// Main.vm:2: push argument 0\r\n@ARG\r\n"));
+    assert!(text.contains("// function Main.misc 2 (Main.vm:21)\r\n"));
+    assert!(text.contains("// Sys.vm:9: goto HALT\r\n"));
+
+    // Comments do not change the machine code.
+    Assembler::new(&plain).run();
+    Assembler::new(&annotated).run();
+    let plain = fs::read_to_string(plain.with_extension("hack")).unwrap();
+    let annotated = fs::read_to_string(annotated.with_extension("hack")).unwrap();
+    assert_eq!(plain, annotated);
+}